@@ -1,3 +1,5 @@
 fn main() {
+    // only the desktop app needs the Tauri context
+    #[cfg(feature = "desktop")]
     tauri_build::build()
 }
@@ -0,0 +1,386 @@
+use {
+    crate::message::MessageType,
+    rand::Rng,
+    ring::{
+        digest,
+        hmac,
+        pbkdf2
+    },
+    std::{
+        fmt::Display,
+        num::NonZeroU32
+    }
+};
+
+// Challenge-response login, modelled after SCRAM-SHA-256 (RFC 5802):
+//
+//   client: AUTH <key> <client nonce>
+//   server: CHALLENGE <client nonce + server nonce> <salt (hex)> <iterations>
+//   client: PROOF <client proof (hex)>
+//   server: HEY / NO
+//
+//   SaltedPassword  = PBKDF2-HMAC-SHA256(password, salt, iterations)
+//   ClientKey       = HMAC(SaltedPassword, "Client Key")
+//   StoredKey       = SHA256(ClientKey)
+//   AuthMessage     = key "," client nonce "," nonce "," salt "," iterations
+//                     (with the salt in hex, as sent in the CHALLENGE)
+//   ClientSignature = HMAC(StoredKey, AuthMessage)
+//   ClientProof     = ClientKey XOR ClientSignature
+//
+// The relay only needs the salt, iteration count and StoredKey to check the
+// proof, so neither the password nor anything it can log is enough to log in.
+// The relay itself is authenticated by TLS.
+
+/// Lowest iteration count accepted from a relay
+pub const MIN_ITERATIONS: u32 = 4096;
+/// Highest iteration count accepted from a relay, so a relay can't make the
+/// client hash forever
+pub const MAX_ITERATIONS: u32 = 1_000_000;
+
+const CLIENT_NONCE_BYTES: usize = 18;
+
+#[derive(Debug, PartialEq)]
+pub enum ChallengeError {
+    UnexpectedMessage,
+    NonceMismatch,
+    InvalidSalt,
+    InvalidIterations
+}
+
+impl Display for ChallengeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChallengeError::UnexpectedMessage => {
+                write!(f, "The server sent an unexpected message during the login.")
+            }
+            ChallengeError::NonceMismatch => {
+                write!(
+                    f,
+                    "The server's challenge doesn't continue the client's nonce."
+                )
+            }
+            ChallengeError::InvalidSalt => write!(f, "The server's salt isn't valid hex."),
+            ChallengeError::InvalidIterations => write!(
+                f,
+                "The server asked for an iteration count outside of {} to {}.",
+                MIN_ITERATIONS, MAX_ITERATIONS
+            )
+        }
+    }
+}
+
+/// What the client has to do after a message from the server
+#[derive(Debug, PartialEq)]
+pub enum ChallengeStep {
+    Prove(ProofInput), // compute the proof and send it
+    Accepted,
+    Rejected
+}
+
+/// What the proof for a challenge is computed from. The server picks the
+/// iteration count, so computing the proof can take seconds and shouldn't
+/// run on the async runtime.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProofInput {
+    password:     String,
+    salt:         Vec<u8>,
+    iterations:   u32,
+    auth_message: String
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum ChallengeState {
+    Started,   // AUTH sent, waiting for the challenge
+    ProofSent, // waiting for the verdict
+    Finished
+}
+
+/// Client side of a challenge login. `start` returns the first message, the
+/// server's answers are passed to `receive` until the login is decided.
+pub struct ChallengeLogin {
+    user:         String,
+    password:     String,
+    client_nonce: String,
+    state:        ChallengeState
+}
+
+impl ChallengeLogin {
+    pub fn new(user: String, password: String) -> Self {
+        let nonce: [u8; CLIENT_NONCE_BYTES] = rand::thread_rng().gen();
+        ChallengeLogin::with_nonce(user, password, to_hex(&nonce))
+    }
+
+    fn with_nonce(user: String, password: String, client_nonce: String) -> Self {
+        ChallengeLogin {
+            user,
+            password,
+            client_nonce,
+            state: ChallengeState::Started
+        }
+    }
+
+    pub fn start(&self) -> MessageType {
+        MessageType::AUTH(self.user.clone(), self.client_nonce.clone())
+    }
+
+    pub fn receive(&mut self, message: MessageType) -> Result<ChallengeStep, ChallengeError> {
+        match (self.state, message) {
+            (ChallengeState::Started, MessageType::CHALLENGE(nonce, salt, iterations)) => {
+                // the server's nonce has to extend ours, otherwise the challenge could be
+                // replayed from another login
+                if !nonce.starts_with(&self.client_nonce) || nonce.len() == self.client_nonce.len()
+                {
+                    return Err(ChallengeError::NonceMismatch);
+                }
+                if !(MIN_ITERATIONS..=MAX_ITERATIONS).contains(&iterations) {
+                    return Err(ChallengeError::InvalidIterations);
+                }
+                let salt_bytes = from_hex(&salt).ok_or(ChallengeError::InvalidSalt)?;
+
+                let message =
+                    auth_message(&self.user, &self.client_nonce, &nonce, &salt, iterations);
+
+                self.state = ChallengeState::ProofSent;
+                Ok(ChallengeStep::Prove(ProofInput {
+                    password: self.password.clone(),
+                    salt: salt_bytes,
+                    iterations,
+                    auth_message: message
+                }))
+            }
+            // unknown user or wrong password
+            (ChallengeState::Started, MessageType::NO)
+            | (ChallengeState::ProofSent, MessageType::NO) => {
+                self.state = ChallengeState::Finished;
+                Ok(ChallengeStep::Rejected)
+            }
+            (ChallengeState::ProofSent, MessageType::HEY) => {
+                self.state = ChallengeState::Finished;
+                Ok(ChallengeStep::Accepted)
+            }
+            _ => Err(ChallengeError::UnexpectedMessage)
+        }
+    }
+}
+
+impl ProofInput {
+    /// The PROOF message for the challenge, blocks for as long as PBKDF2 runs
+    pub fn compute(&self) -> MessageType {
+        let proof = client_proof(
+            &self.password,
+            &self.salt,
+            self.iterations,
+            &self.auth_message
+        );
+        MessageType::PROOF(to_hex(&proof))
+    }
+}
+
+/// The message both sides sign, binds the proof to this login
+pub fn auth_message(
+    user: &str,
+    client_nonce: &str,
+    nonce: &str,
+    salt: &str,
+    iterations: u32
+) -> String {
+    format!(
+        "{},{},{},{},{}",
+        user, client_nonce, nonce, salt, iterations
+    )
+}
+
+/// Proof that the client knows the password, see the protocol description
+/// above
+pub fn client_proof(password: &str, salt: &[u8], iterations: u32, auth_message: &str) -> Vec<u8> {
+    let mut salted_password = [0u8; digest::SHA256_OUTPUT_LEN];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(iterations).unwrap_or(NonZeroU32::new(1).unwrap()),
+        salt,
+        password.as_bytes(),
+        &mut salted_password
+    );
+
+    let salted_key = hmac::Key::new(hmac::HMAC_SHA256, &salted_password);
+    let client_key = hmac::sign(&salted_key, b"Client Key");
+    let stored_key = digest::digest(&digest::SHA256, client_key.as_ref());
+    let signature = hmac::sign(
+        &hmac::Key::new(hmac::HMAC_SHA256, stored_key.as_ref()),
+        auth_message.as_bytes()
+    );
+
+    client_key
+        .as_ref()
+        .iter()
+        .zip(signature.as_ref())
+        .map(|(key, sig)| key ^ sig)
+        .collect()
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 || !text.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        auth::{
+            auth_message,
+            client_proof,
+            from_hex,
+            to_hex,
+            ChallengeError,
+            ChallengeLogin,
+            ChallengeStep,
+            MIN_ITERATIONS
+        },
+        message::MessageType
+    };
+
+    fn new_login() -> ChallengeLogin {
+        ChallengeLogin::with_nonce(
+            String::from("foo"),
+            String::from("bar"),
+            String::from("c0ffee")
+        )
+    }
+
+    fn challenge(nonce: &str, iterations: u32) -> MessageType {
+        MessageType::CHALLENGE(String::from(nonce), String::from("5a17"), iterations)
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0x00, 0xab, 0x10]), "00ab10");
+        assert_eq!(from_hex("00ab10"), Some(vec![0x00, 0xab, 0x10]));
+        assert_eq!(from_hex("00AB10"), Some(vec![0x00, 0xab, 0x10]));
+        assert_eq!(from_hex(""), Some(Vec::new()));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+        assert_eq!(from_hex("ß0"), None);
+        assert_eq!(from_hex("+1"), None);
+    }
+
+    #[test]
+    fn test_client_proof() {
+        let message = auth_message("foo", "c0ffee", "c0ffee42", "5a17", MIN_ITERATIONS);
+        let proof = client_proof("bar", &[0x5a, 0x17], MIN_ITERATIONS, &message);
+        assert_eq!(proof.len(), 32);
+
+        // same input, same proof; anything else changes it
+        assert_eq!(
+            client_proof("bar", &[0x5a, 0x17], MIN_ITERATIONS, &message),
+            proof
+        );
+        assert_ne!(
+            client_proof("baz", &[0x5a, 0x17], MIN_ITERATIONS, &message),
+            proof
+        );
+        assert_ne!(
+            client_proof("bar", &[0x5a, 0x18], MIN_ITERATIONS, &message),
+            proof
+        );
+        assert_ne!(
+            client_proof("bar", &[0x5a, 0x17], MIN_ITERATIONS + 1, &message),
+            proof
+        );
+    }
+
+    #[test]
+    fn test_login_accepted() {
+        let mut login = new_login();
+        assert_eq!(
+            login.start(),
+            MessageType::AUTH(String::from("foo"), String::from("c0ffee"))
+        );
+
+        let expected = client_proof(
+            "bar",
+            &[0x5a, 0x17],
+            MIN_ITERATIONS,
+            &auth_message("foo", "c0ffee", "c0ffee42", "5a17", MIN_ITERATIONS)
+        );
+        let input = match login.receive(challenge("c0ffee42", MIN_ITERATIONS)) {
+            Ok(ChallengeStep::Prove(input)) => input,
+            step => panic!("expected a proof to compute, got {:?}", step)
+        };
+        assert_eq!(input.compute(), MessageType::PROOF(to_hex(&expected)));
+        assert_eq!(login.receive(MessageType::HEY), Ok(ChallengeStep::Accepted));
+
+        // the login is over
+        assert_eq!(
+            login.receive(MessageType::HEY),
+            Err(ChallengeError::UnexpectedMessage)
+        );
+    }
+
+    #[test]
+    fn test_login_rejected() {
+        // wrong password
+        let mut login = new_login();
+        assert!(login.receive(challenge("c0ffee42", MIN_ITERATIONS)).is_ok());
+        assert_eq!(login.receive(MessageType::NO), Ok(ChallengeStep::Rejected));
+
+        // unknown user
+        let mut login = new_login();
+        assert_eq!(login.receive(MessageType::NO), Ok(ChallengeStep::Rejected));
+    }
+
+    #[test]
+    fn test_invalid_challenges() {
+        // has to extend the client nonce
+        assert_eq!(
+            new_login().receive(challenge("beef42", MIN_ITERATIONS)),
+            Err(ChallengeError::NonceMismatch)
+        );
+        assert_eq!(
+            new_login().receive(challenge("c0ffee", MIN_ITERATIONS)),
+            Err(ChallengeError::NonceMismatch)
+        );
+
+        // iteration bounds
+        assert_eq!(
+            new_login().receive(challenge("c0ffee42", 1)),
+            Err(ChallengeError::InvalidIterations)
+        );
+        assert_eq!(
+            new_login().receive(challenge("c0ffee42", u32::MAX)),
+            Err(ChallengeError::InvalidIterations)
+        );
+
+        // salt isn't hex
+        assert_eq!(
+            new_login().receive(MessageType::CHALLENGE(
+                String::from("c0ffee42"),
+                String::from("salt"),
+                MIN_ITERATIONS
+            )),
+            Err(ChallengeError::InvalidSalt)
+        );
+
+        // HEY before the proof
+        assert_eq!(
+            new_login().receive(MessageType::HEY),
+            Err(ChallengeError::UnexpectedMessage)
+        );
+        // no second challenge
+        let mut login = new_login();
+        assert!(login.receive(challenge("c0ffee42", MIN_ITERATIONS)).is_ok());
+        assert_eq!(
+            login.receive(challenge("c0ffee43", MIN_ITERATIONS)),
+            Err(ChallengeError::UnexpectedMessage)
+        );
+    }
+}
@@ -0,0 +1,594 @@
+//! Headless BOOP client. Uses the same config and partner files as the
+//! desktop app, but doesn't need a webview, so it works from a terminal, a
+//! cron job or a CI box.
+
+use std::{
+    env,
+    path::PathBuf,
+    process::exit,
+    sync::Arc,
+    time::Duration
+};
+
+use {
+    boop_snoot::{
+        config::BoopConfig,
+        error::BoopError,
+        files::{
+            get_config_file_path,
+            get_data_file_path,
+            get_object_or_default,
+            save_file
+        },
+        history::{
+            record_boop,
+            record_delivery,
+            BoopDirection,
+            BoopHistory,
+            DeliveryStatus,
+            HistoryHandle,
+            HISTORY_FILENAME
+        },
+        message::{
+            new_boop_id,
+            BoopContent,
+            BoopKind,
+            Capability,
+            MessageType
+        },
+        network::{
+            connect_to_server,
+            disconnect_from_server,
+            BoopDeliveryStatus,
+            ConnectionEvent,
+            ConnectionInterface,
+            EventRx,
+            ServerConnectionStatus
+        },
+        partners::{
+            build_partners_map,
+            BoopPartner,
+            PartnerOnlineStatus,
+            PartnersHandle
+        },
+        profiles::{
+            ProfilePaths,
+            Profiles,
+            PROFILES_FILENAME
+        },
+        requests::{
+            record_boop_request,
+            RequestsHandle,
+            RequestsInbox
+        },
+        secrets::{
+            KeySource,
+            KEY_FILENAME
+        },
+        tls::init_trust_anchors
+    },
+    flexi_logger::Logger,
+    tokio::{
+        sync::{
+            mpsc::unbounded_channel,
+            Mutex
+        },
+        time::timeout
+    },
+    tokio_rustls::rustls::RootCertStore
+};
+
+/// How long `status` waits for the server to report every partner
+const STATUS_TIMEOUT: u64 = 5;
+/// How long `boop` waits for the server to confirm the delivery
+const DELIVERY_TIMEOUT: u64 = 5;
+/// How long the client waits for the connection to close after a disconnect
+const DISCONNECT_TIMEOUT: u64 = 3;
+/// Environment variable with the master passphrase, if the password is
+/// protected by one
+const PASSPHRASE_VAR: &str = "BOOP_PASSPHRASE";
+/// Environment variable with the id of the profile to use instead of the
+/// active one
+const PROFILE_VAR: &str = "BOOP_PROFILE";
+
+const USAGE: &str = "usage: boop-cli <command>
+
+commands:
+  connect          log in to the configured server and report the result
+  boop <partner>   boop a partner (by user key or nickname)
+    [--emoji <emoji>] [--note <note>] [--kind gentle|urgent|come-here]
+                   with an emoji, a short note or a kind (on relays that support it)
+  status           ask the server for the online status of every partner
+  listen           stay connected and print incoming boops and status changes
+                   (boops by non-partners are kept as requests for the desktop app)
+
+environment:
+  BOOP_PASSPHRASE  master passphrase, if the saved password is protected by one
+  BOOP_PROFILE     id of the profile to use, the desktop app's active one by default";
+
+enum Command {
+    Connect,
+    Boop(String, BoopContent),
+    Status,
+    Listen
+}
+
+#[tokio::main]
+async fn main() {
+    // log warnings to stderr, can be overridden with RUST_LOG
+    if let Err(err) = Logger::try_with_env_or_str("warn").and_then(|logger| logger.start()) {
+        eprintln!("failed to initialize logging: {}", err);
+    }
+
+    let command = match parse_args(env::args().skip(1).collect()) {
+        Some(command) => command,
+        None => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    // same profiles and files as the desktop app
+    let profiles: Profiles = get_object_or_default(&get_config_file_path(PROFILES_FILENAME));
+    let profile = env::var(PROFILE_VAR).unwrap_or_else(|_| profiles.active());
+    if profiles.get(&profile).is_none() {
+        eprintln!("there is no profile \"{}\"", profile);
+        exit(2);
+    }
+    let paths = ProfilePaths::new(&profile);
+
+    let config_path = paths.config.clone();
+    let config: BoopConfig = get_object_or_default(&config_path);
+    let partners: Vec<BoopPartner> = get_object_or_default(&paths.partners);
+    let partners_handle: PartnersHandle = Arc::new(Mutex::new(build_partners_map(partners)));
+
+    // sent and received boops end up in the same history as the desktop app's
+    let history_handle: HistoryHandle =
+        match BoopHistory::load(get_data_file_path(HISTORY_FILENAME)) {
+            Ok(history) => Arc::new(Mutex::new(history)),
+            Err(err) => {
+                eprintln!("failed to read boop history: {}", err);
+                exit(1);
+            }
+        };
+
+    // same key file as the desktop app, the passphrase can't be asked for here
+    let mut key_source = KeySource::new(get_config_file_path(KEY_FILENAME));
+    if let Ok(passphrase) = env::var(PASSPHRASE_VAR) {
+        key_source = match key_source
+            .with_passphrase(passphrase, config.password_salt())
+            .await
+        {
+            Ok(unlocked) => unlocked,
+            Err(err) => {
+                eprintln!("failed to unlock the saved password: {}", err);
+                exit(1);
+            }
+        };
+    }
+
+    // custom CAs from the config are added when connecting
+    let trust_anchors = match init_trust_anchors() {
+        Ok(trust_anchors) => trust_anchors,
+        Err(err) => {
+            eprintln!("failed to load the OS trust anchors: {}", err);
+            RootCertStore::empty()
+        }
+    };
+
+    let conn_handle = Mutex::new(None);
+    let (event_tx, mut event_rx) = unbounded_channel();
+
+    let server = config.server_address();
+    let user = config.user_name();
+    let pinned_fingerprint = config.pinned_fingerprint();
+    let config_handle = Arc::new(Mutex::new(config));
+    match connect_to_server(
+        &conn_handle,
+        Arc::clone(&config_handle),
+        Arc::new(Mutex::new(key_source)),
+        Arc::clone(&partners_handle),
+        trust_anchors,
+        event_tx
+    )
+    .await
+    {
+        Ok(_) => {}
+        Err(BoopError::LoginRejected) => {
+            eprintln!("{} refused the login of {}", server, user);
+            exit(1);
+        }
+        Err(err) => {
+            eprintln!("failed to connect to {}: {}", server, err);
+            exit(1);
+        }
+    }
+
+    // the server's certificate might have been pinned on first use
+    let config = config_handle.lock().await.clone();
+    if config.pinned_fingerprint() != pinned_fingerprint {
+        if let Err(err) = save_file(&config_path, &config).await {
+            eprintln!("failed to save the pinned server certificate: {}", err);
+        }
+    }
+
+    let exit_code = match command {
+        Command::Connect => {
+            println!("logged in to {} as {}", server, user);
+            0
+        }
+        Command::Boop(partner, content) => {
+            boop(
+                &conn_handle,
+                &partners_handle,
+                &history_handle,
+                &profile,
+                &mut event_rx,
+                partner,
+                content
+            )
+            .await
+        }
+        Command::Status => status(&partners_handle, &mut event_rx).await,
+        Command::Listen => {
+            // same inbox as the desktop app, so requests can be answered there
+            let requests_path = paths.requests.clone();
+            let requests: RequestsInbox = get_object_or_default(&requests_path);
+            let requests_handle: RequestsHandle = Arc::new(Mutex::new(requests));
+            listen(
+                &partners_handle,
+                &requests_handle,
+                &history_handle,
+                &profile,
+                &requests_path,
+                &mut event_rx
+            )
+            .await
+        }
+    };
+
+    // close the connection properly before exiting
+    disconnect_from_server(&conn_handle).await;
+    let _ = timeout(
+        Duration::from_secs(DISCONNECT_TIMEOUT),
+        wait_for_disconnect(&mut event_rx)
+    )
+    .await;
+
+    exit(exit_code);
+}
+
+fn parse_args(args: Vec<String>) -> Option<Command> {
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+    match args.as_slice() {
+        ["connect"] => Some(Command::Connect),
+        ["boop", partner, options @ ..] => Some(Command::Boop(
+            String::from(*partner),
+            parse_boop_options(options)?
+        )),
+        ["status"] => Some(Command::Status),
+        ["listen"] => Some(Command::Listen),
+        _ => None
+    }
+}
+
+/// `--emoji`, `--note` and `--kind` of a boop, each with a value
+fn parse_boop_options(options: &[&str]) -> Option<BoopContent> {
+    let (mut emoji, mut note, mut kind) = (None, None, BoopKind::Plain);
+    for option in options.chunks(2) {
+        match option {
+            ["--emoji", value] => emoji = Some(String::from(*value)),
+            ["--note", value] => note = Some(String::from(*value)),
+            ["--kind", value] => kind = BoopKind::from_name(value)?,
+            _ => return None
+        }
+    }
+
+    Some(BoopContent::new(emoji, note, kind))
+}
+
+async fn boop(
+    conn_handle: &Mutex<Option<ConnectionInterface>>,
+    partners_handle: &PartnersHandle,
+    history_handle: &HistoryHandle,
+    server: &str,
+    event_rx: &mut EventRx,
+    partner: String,
+    content: BoopContent
+) -> i32 {
+    if let Err(reason) = content.check() {
+        eprintln!("the boop can't be sent: {}", reason);
+        return 2;
+    }
+
+    // the partner can be given by user key or by nickname
+    let partner_key = {
+        let partners = partners_handle.lock().await;
+        if partners.contains_key(&partner) {
+            partner
+        } else if let Some((key, _)) = partners
+            .iter()
+            .find(|(_, (boop_partner, _))| boop_partner.nickname() == partner)
+        {
+            key.clone()
+        } else {
+            eprintln!("{} isn't one of your partners", partner);
+            return 1;
+        }
+    };
+
+    let id = new_boop_id();
+    let mut acked = false;
+    let connection_interface = conn_handle.lock().await;
+    let sent = match &*connection_interface {
+        Some(connection) => {
+            acked = connection.features.borrow().supports(Capability::Ack);
+            connection.sink.send(MessageType::BOOP(
+                partner_key.clone(),
+                Some(id.clone()),
+                content
+            ))
+        }
+        None => Err(BoopError::NotConnected)
+    };
+    drop(connection_interface);
+
+    if let Err(err) = sent {
+        eprintln!("failed to boop {}: {}", partner_key, err);
+        record_boop(
+            history_handle,
+            server,
+            Some(id),
+            partner_key,
+            BoopDirection::Sent,
+            DeliveryStatus::Failed
+        )
+        .await;
+        return 1;
+    }
+
+    record_boop(
+        history_handle,
+        server,
+        Some(id.clone()),
+        partner_key.clone(),
+        BoopDirection::Sent,
+        DeliveryStatus::Queued
+    )
+    .await;
+
+    let delivery = timeout(
+        Duration::from_secs(DELIVERY_TIMEOUT),
+        wait_for_delivery(event_rx, history_handle, &id, acked)
+    )
+    .await;
+    match delivery {
+        Ok(Some(BoopDeliveryStatus::Sent)) => {
+            // servers without acks don't say whether the boop arrived
+            println!("booped {}", partner_key);
+            0
+        }
+        Ok(Some(BoopDeliveryStatus::Delivered)) => {
+            println!("booped {}, delivered", partner_key);
+            0
+        }
+        Ok(Some(BoopDeliveryStatus::NotAvailable)) => {
+            eprintln!("{} isn't available, the boop wasn't delivered", partner_key);
+            1
+        }
+        Ok(Some(_)) | Ok(None) => {
+            eprintln!("failed to deliver the boop to {}", partner_key);
+            1
+        }
+        Err(_) if !acked => {
+            eprintln!("the boop to {} wasn't sent in time", partner_key);
+            1
+        }
+        Err(_) => {
+            println!(
+                "booped {}, but the server didn't confirm the delivery",
+                partner_key
+            );
+            0
+        }
+    }
+}
+
+/// Waits for the server's answer to the boop with the given id, or only for
+/// the write on servers without acks. Every report is put into the history.
+/// `None` if the connection closed first.
+async fn wait_for_delivery(
+    event_rx: &mut EventRx,
+    history_handle: &HistoryHandle,
+    id: &str,
+    acked: bool
+) -> Option<BoopDeliveryStatus> {
+    while let Some(event) = event_rx.recv().await {
+        match event {
+            ConnectionEvent::BoopDelivery(boop_id, status) if boop_id == id => {
+                record_delivery(history_handle, id, status).await;
+                if !acked || status != BoopDeliveryStatus::Sent {
+                    return Some(status);
+                }
+            }
+            ConnectionEvent::StatusChanged(ServerConnectionStatus::Disconnected) => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+async fn status(partners_handle: &PartnersHandle, event_rx: &mut EventRx) -> i32 {
+    let partner_count = partners_handle.lock().await.len();
+
+    // the connection asks for every partner right after logging in, wait until all
+    // of them have answered (or the server takes too long)
+    let mut answered = 0;
+    let _ = timeout(Duration::from_secs(STATUS_TIMEOUT), async {
+        while answered < partner_count {
+            match event_rx.recv().await {
+                Some(ConnectionEvent::PartnerStatusChanged(..)) => answered += 1,
+                Some(_) => {}
+                None => break
+            }
+        }
+    })
+    .await;
+
+    let partners = partners_handle.lock().await;
+    for (user_key, (partner, online_status)) in partners.iter() {
+        println!(
+            "{} ({}): {}",
+            partner.nickname(),
+            user_key,
+            status_text(*online_status)
+        );
+    }
+
+    0
+}
+
+async fn listen(
+    partners_handle: &PartnersHandle,
+    requests_handle: &RequestsHandle,
+    history_handle: &HistoryHandle,
+    server: &str,
+    requests_path: &PathBuf,
+    event_rx: &mut EventRx
+) -> i32 {
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return 0,
+            event = event_rx.recv() => {
+                match event {
+                    Some(ConnectionEvent::Booped(partner_key, content)) => {
+                        record_boop(history_handle, server, None, partner_key.clone(), BoopDirection::Received, DeliveryStatus::Received).await;
+                        println!("BOOP {}{}", display_name(partners_handle, &partner_key).await, content_text(&content));
+                    }
+                    Some(ConnectionEvent::BoopRequested(user_key)) => {
+                        match record_boop_request(requests_handle, requests_path, &user_key).await {
+                            Ok(true) => println!("REQUEST {}", user_key),
+                            Ok(false) => {}
+                            Err(err) => eprintln!("failed to save boop request by {}: {}", user_key, err)
+                        }
+                    }
+                    Some(ConnectionEvent::PartnerStatusChanged(partner_key, online_status)) => {
+                        println!(
+                            "{} {}",
+                            status_text(online_status).to_ascii_uppercase(),
+                            display_name(partners_handle, &partner_key).await
+                        );
+                    }
+                    Some(ConnectionEvent::StatusChanged(ServerConnectionStatus::Reconnecting(info))) => {
+                        if let Some(reason) = info.give_up_reason() {
+                            eprintln!("connection lost, gave up reconnecting: {}", reason);
+                        } else {
+                            eprintln!("connection lost, reconnection attempt {}", info.attempt());
+                        }
+                    }
+                    Some(ConnectionEvent::StatusChanged(ServerConnectionStatus::Disconnected)) | None => {
+                        eprintln!("disconnected from server");
+                        return 1;
+                    }
+                    Some(ConnectionEvent::StatusChanged(_))
+                    | Some(ConnectionEvent::CertificatePinned(_))
+                    | Some(ConnectionEvent::BoopDelivery(..))
+                    | Some(ConnectionEvent::QualityReport(_)) => {}
+                }
+            }
+        }
+    }
+}
+
+async fn wait_for_disconnect(event_rx: &mut EventRx) {
+    while let Some(event) = event_rx.recv().await {
+        if event == ConnectionEvent::StatusChanged(ServerConnectionStatus::Disconnected) {
+            return;
+        }
+    }
+}
+
+async fn display_name(partners_handle: &PartnersHandle, partner_key: &String) -> String {
+    match partners_handle.lock().await.get(partner_key) {
+        Some((partner, _)) => format!("{} ({})", partner.nickname(), partner_key),
+        None => partner_key.clone()
+    }
+}
+
+/// Kind, emoji and note of a boop, e.g. ` URGENT 🐶 "come over"`
+fn content_text(content: &BoopContent) -> String {
+    let mut text = String::new();
+    if content.kind() != BoopKind::Plain {
+        text.push_str(&format!(" {}", content.kind().name().to_ascii_uppercase()));
+    }
+    if let Some(emoji) = content.emoji() {
+        text.push_str(&format!(" {}", emoji));
+    }
+    if let Some(note) = content.note() {
+        text.push_str(&format!(" {:?}", note));
+    }
+    text
+}
+
+fn status_text(online_status: PartnerOnlineStatus) -> &'static str {
+    match online_status {
+        PartnerOnlineStatus::Online => "online",
+        PartnerOnlineStatus::Afk => "afk",
+        PartnerOnlineStatus::Unknown => "unknown"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::boop,
+        boop_snoot::{
+            history::{
+                BoopHistory,
+                DeliveryStatus
+            },
+            message::BoopContent,
+            partners::{
+                build_partners_map,
+                BoopPartner
+            }
+        },
+        std::{
+            env,
+            fs,
+            sync::Arc
+        },
+        tokio::sync::{
+            mpsc::unbounded_channel,
+            Mutex
+        }
+    };
+
+    #[tokio::test]
+    async fn test_boop_without_connection() {
+        let file = env::temp_dir().join(format!("boop-cli-history-{}", std::process::id()));
+        let _ = fs::remove_file(&file);
+        let history_handle = Arc::new(Mutex::new(BoopHistory::load(file.clone()).unwrap()));
+        let partner = BoopPartner::new(String::from("Alice"), String::from("alice"));
+        let partners_handle = Arc::new(Mutex::new(build_partners_map(vec![partner])));
+        let (_event_tx, mut event_rx) = unbounded_channel();
+
+        let code = boop(
+            &Mutex::new(None),
+            &partners_handle,
+            &history_handle,
+            "default",
+            &mut event_rx,
+            String::from("Alice"),
+            BoopContent::default()
+        )
+        .await;
+        assert_eq!(code, 1);
+
+        let entries = history_handle.lock().await.page("default", "alice", 0, 10);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].status(), DeliveryStatus::Failed);
+
+        let _ = fs::remove_file(&file);
+    }
+}
@@ -1,10 +1,58 @@
-use serde::{
-    Deserialize,
-    Serialize
+use {
+    crate::{
+        error::BoopError,
+        files::get_config_file_path,
+        network::{
+            DEFAULT_ALLOWED_PING_MISSED,
+            DEFAULT_CONNECT_TIMEOUT,
+            DEFAULT_HANDSHAKE_TIMEOUT,
+            DEFAULT_PARTNER_CHECK_INTERVAL,
+            DEFAULT_PING_INTERVAL,
+            DEFAULT_WRITE_TIMEOUT
+        },
+        outbox::DEFAULT_OUTBOX_EXPIRY,
+        ratelimit::{
+            DEFAULT_BOOP_BURST,
+            DEFAULT_BOOP_REFILL
+        },
+        schema::{
+            Migration,
+            Versioned
+        },
+        secrets::{
+            EncryptedSecret,
+            KeySource,
+            KEY_FILENAME
+        }
+    },
+    serde::{
+        Deserialize,
+        Serialize
+    },
+    serde_json::Value
 };
 
 pub const CONFIG_FILENAME: &str = "boop.config.json"; // TODO: change to same directory later
 
+/// Shown instead of the password in the settings, a config from the frontend
+/// with this password keeps the saved one
+pub const REDACTED_PASSWORD: &str =
+    "\u{2022}\u{2022}\u{2022}\u{2022}\u{2022}\u{2022}\u{2022}\u{2022}";
+
+/// How the user proves who they are when logging in
+#[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum LoginMode {
+    Password,    // password in the CONNECT message
+    Certificate  // client certificate, CONNECT without password
+}
+
+impl Default for LoginMode {
+    fn default() -> Self {
+        LoginMode::Password
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct BoopConfig {
@@ -12,20 +60,187 @@ pub struct BoopConfig {
     server_address: String,
 
     // login data
-    user:     String,
-    password: String
+    user:               String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    password:           String, // plain text, only from the frontend until it's encrypted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encrypted_password: Option<EncryptedSecret>,
+    #[serde(default)]
+    login_mode:         LoginMode,
+
+    // tls config
+    #[serde(default)]
+    ca_files:           Vec<String>, // extra PEM files with trusted CA certificates
+    #[serde(default)]
+    only_custom_cas:    bool, // don't trust the OS certificates
+    #[serde(default)]
+    pin_certificate:    bool, // trust on first use instead of CAs
+    #[serde(default)]
+    pinned_fingerprint: Option<String>,
+    #[serde(default)]
+    client_certificate: Option<String>, // PEM file with the client certificate chain
+    #[serde(default)]
+    client_key:         Option<String>, // PEM file with the client certificate's private key
+
+    // outbox
+    #[serde(default = "default_outbox_expiry")]
+    outbox_expiry: u32, // minutes a boop waits for the connection before it's dropped
+
+    // limit of sent boops per partner
+    #[serde(default = "default_boop_burst")]
+    boop_burst:  u32, // boops in a row, 0 turns the limit off
+    #[serde(default = "default_boop_refill")]
+    boop_refill: u32, // seconds until another boop is allowed
+
+    // advanced network settings
+    #[serde(default)]
+    network: NetworkSettings
+}
+
+/// Keepalive and timeouts of the connection, all times in seconds. Changes
+/// are used from the next connection on.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NetworkSettings {
+    ping_interval:          u64,
+    allowed_ping_missed:    u32, // unanswered pings before the connection is dropped
+    partner_check_interval: u64, // presence polling, on relays without presence push
+    connect_timeout:        u64,
+    handshake_timeout:      u64, // for the TLS handshake and again for the login
+    write_timeout:          u64
+}
+
+fn default_outbox_expiry() -> u32 {
+    DEFAULT_OUTBOX_EXPIRY
+}
+
+fn default_boop_burst() -> u32 {
+    DEFAULT_BOOP_BURST
+}
+
+fn default_boop_refill() -> u32 {
+    DEFAULT_BOOP_REFILL
 }
 
 impl Default for BoopConfig {
     fn default() -> Self {
         BoopConfig {
-            server_address: String::new(),
-            user:           String::new(),
-            password:       String::new()
+            server_address:     String::new(),
+            user:               String::new(),
+            password:           String::new(),
+            encrypted_password: None,
+            login_mode:         LoginMode::Password,
+            ca_files:           Vec::new(),
+            only_custom_cas:    false,
+            pin_certificate:    false,
+            pinned_fingerprint: None,
+            client_certificate: None,
+            client_key:         None,
+            outbox_expiry:      DEFAULT_OUTBOX_EXPIRY,
+            boop_burst:         DEFAULT_BOOP_BURST,
+            boop_refill:        DEFAULT_BOOP_REFILL,
+            network:            NetworkSettings::default()
+        }
+    }
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        NetworkSettings {
+            ping_interval:          DEFAULT_PING_INTERVAL,
+            allowed_ping_missed:    DEFAULT_ALLOWED_PING_MISSED,
+            partner_check_interval: DEFAULT_PARTNER_CHECK_INTERVAL,
+            connect_timeout:        DEFAULT_CONNECT_TIMEOUT,
+            handshake_timeout:      DEFAULT_HANDSHAKE_TIMEOUT,
+            write_timeout:          DEFAULT_WRITE_TIMEOUT
         }
     }
 }
 
+impl NetworkSettings {
+    pub fn ping_interval(&self) -> u64 {
+        self.ping_interval
+    }
+
+    pub fn allowed_ping_missed(&self) -> u32 {
+        self.allowed_ping_missed
+    }
+
+    pub fn partner_check_interval(&self) -> u64 {
+        self.partner_check_interval
+    }
+
+    pub fn connect_timeout(&self) -> u64 {
+        self.connect_timeout
+    }
+
+    pub fn handshake_timeout(&self) -> u64 {
+        self.handshake_timeout
+    }
+
+    pub fn write_timeout(&self) -> u64 {
+        self.write_timeout
+    }
+
+    /// Checks that every value is within its range, so a typo can't flood the
+    /// relay with pings or leave a dead connection hanging for hours
+    pub fn validate(&self) -> Result<(), BoopError> {
+        let checks = [
+            ("ping interval", self.ping_interval, 1, 300),
+            (
+                "allowed missed pings",
+                u64::from(self.allowed_ping_missed),
+                1,
+                20
+            ),
+            (
+                "partner check interval",
+                self.partner_check_interval,
+                5,
+                3600
+            ),
+            ("connect timeout", self.connect_timeout, 1, 120),
+            ("handshake timeout", self.handshake_timeout, 1, 120),
+            ("write timeout", self.write_timeout, 1, 120)
+        ];
+
+        for (name, value, min, max) in checks {
+            if value < min || value > max {
+                return Err(BoopError::InvalidSettings(format!(
+                    "the {} has to be between {} and {}",
+                    name, min, max
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+// version 1: the password may be saved in plain text
+// version 2: the password is always encrypted
+impl Versioned for BoopConfig {
+    const MIGRATIONS: &'static [Migration] = &[encrypt_plaintext_password];
+    const VERSION: u32 = 2;
+}
+
+/// Encrypts a plain text password from before encryption with the key file (a
+/// master passphrase can be set in the settings later)
+fn encrypt_plaintext_password(mut data: Value) -> Result<Value, String> {
+    let password = match data.get("password").and_then(Value::as_str) {
+        Some(password) if !password.is_empty() => password.to_string(),
+        _ => return Ok(data)
+    };
+
+    let keys = KeySource::new(get_config_file_path(KEY_FILENAME));
+    let encrypted =
+        EncryptedSecret::seal(&password, &keys, false).map_err(|err| err.to_string())?;
+    data["encryptedPassword"] = serde_json::to_value(encrypted).map_err(|err| err.to_string())?;
+    if let Some(object) = data.as_object_mut() {
+        object.remove("password");
+    }
+    Ok(data)
+}
+
 impl BoopConfig {
     pub fn server_address(&self) -> String {
         self.server_address.clone()
@@ -35,7 +250,183 @@ impl BoopConfig {
         self.user.clone()
     }
 
-    pub fn password(&self) -> String {
-        self.password.clone()
+    /// The login password, decrypted if necessary
+    pub fn password(&self, keys: &KeySource) -> Result<String, BoopError> {
+        match &self.encrypted_password {
+            Some(encrypted) => encrypted.open(keys),
+            None => Ok(self.password.clone())
+        }
+    }
+
+    /// Whether the password is stored in plain text (settings from the
+    /// frontend)
+    pub fn has_plaintext_password(&self) -> bool {
+        !self.password.is_empty()
+    }
+
+    /// Whether the password is encrypted with the master passphrase
+    pub fn password_uses_passphrase(&self) -> bool {
+        self.encrypted_password
+            .as_ref()
+            .map_or(false, |encrypted| encrypted.uses_passphrase())
+    }
+
+    /// Salt the master passphrase key of the password is derived with
+    pub fn password_salt(&self) -> Option<Vec<u8>> {
+        self.encrypted_password
+            .as_ref()
+            .and_then(|encrypted| encrypted.salt())
+    }
+
+    /// Encrypts the plain text password, with the master passphrase or the key
+    /// file
+    pub fn encrypt_password(
+        &mut self,
+        keys: &KeySource,
+        with_passphrase: bool
+    ) -> Result<(), BoopError> {
+        if self.password.is_empty() {
+            return Ok(());
+        }
+
+        self.encrypted_password = Some(EncryptedSecret::seal(
+            &self.password,
+            keys,
+            with_passphrase
+        )?);
+        self.password.clear();
+        Ok(())
+    }
+
+    /// Encrypts the password again with other keys, e.g. after the master
+    /// passphrase was set or removed
+    pub fn reencrypt_password(
+        &mut self,
+        from: &KeySource,
+        to: &KeySource,
+        with_passphrase: bool
+    ) -> Result<(), BoopError> {
+        let password = self.password(from)?;
+        self.encrypted_password = Some(EncryptedSecret::seal(&password, to, with_passphrase)?);
+        self.password.clear();
+        Ok(())
+    }
+
+    /// Copy for the frontend, with a placeholder instead of the password
+    pub fn redacted(&self) -> BoopConfig {
+        let mut redacted = self.clone();
+        if self.encrypted_password.is_some() || !self.password.is_empty() {
+            redacted.password = String::from(REDACTED_PASSWORD);
+        }
+        redacted.encrypted_password = None;
+        redacted
+    }
+
+    /// Keeps the password of `current` if this config (from the frontend) only
+    /// has the placeholder
+    pub fn keep_password_of(&mut self, current: &BoopConfig) {
+        if self.password == REDACTED_PASSWORD {
+            self.password = current.password.clone();
+            self.encrypted_password = current.encrypted_password.clone();
+        }
+    }
+
+    pub fn login_mode(&self) -> LoginMode {
+        self.login_mode
+    }
+
+    pub fn ca_files(&self) -> Vec<String> {
+        self.ca_files.clone()
+    }
+
+    pub fn only_custom_cas(&self) -> bool {
+        self.only_custom_cas
+    }
+
+    pub fn pin_certificate(&self) -> bool {
+        self.pin_certificate
+    }
+
+    /// SPKI fingerprint of the relay's certificate, set on first use
+    pub fn pinned_fingerprint(&self) -> Option<String> {
+        self.pinned_fingerprint.clone()
+    }
+
+    pub fn set_pinned_fingerprint(&mut self, fingerprint: Option<String>) {
+        self.pinned_fingerprint = fingerprint;
+    }
+
+    pub fn client_certificate(&self) -> Option<String> {
+        self.client_certificate.clone()
+    }
+
+    pub fn client_key(&self) -> Option<String> {
+        self.client_key.clone()
+    }
+
+    pub fn outbox_expiry(&self) -> u32 {
+        self.outbox_expiry
+    }
+
+    pub fn boop_burst(&self) -> u32 {
+        self.boop_burst
+    }
+
+    pub fn boop_refill(&self) -> u32 {
+        self.boop_refill
+    }
+
+    pub fn network(&self) -> NetworkSettings {
+        self.network
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{
+            config::{
+                BoopConfig,
+                NetworkSettings
+            },
+            error::BoopError
+        },
+        serde_json::json
+    };
+
+    #[test]
+    fn test_network_settings_defaults() {
+        // configs from before the network settings, or with only some of them
+        let config: BoopConfig = serde_json::from_value(json!({
+            "serverAddress": "localhost:1234",
+            "user": "foo",
+            "network": { "pingInterval": 30 }
+        }))
+        .unwrap();
+
+        let network = config.network();
+        assert_eq!(network.ping_interval(), 30);
+        assert_eq!(
+            network.write_timeout(),
+            NetworkSettings::default().write_timeout()
+        );
+        assert_eq!(network.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_network_settings_validation() {
+        let network: NetworkSettings =
+            serde_json::from_value(json!({ "connectTimeout": 0 })).unwrap();
+        assert!(matches!(
+            network.validate(),
+            Err(BoopError::InvalidSettings(_))
+        ));
+
+        let network: NetworkSettings =
+            serde_json::from_value(json!({ "pingInterval": 301 })).unwrap();
+        assert!(matches!(
+            network.validate(),
+            Err(BoopError::InvalidSettings(_))
+        ));
     }
 }
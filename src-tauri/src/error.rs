@@ -0,0 +1,228 @@
+use {
+    serde::{
+        ser::SerializeStruct,
+        Serialize,
+        Serializer
+    },
+    std::{
+        fmt::{
+            self,
+            Display
+        },
+        io
+    },
+    tokio_rustls::rustls
+};
+
+/// What went wrong during the TLS handshake
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum TlsErrorCategory {
+    Certificate, // the server's certificate wasn't accepted
+    Handshake    // anything else
+}
+
+/// Errors reported to the owner of the connection and returned by the Tauri
+/// commands. Serialized as `{ kind, message }` so the frontend can react to
+/// the kind and show the message (a changed certificate also has the new
+/// `fingerprint`, so the user can accept it).
+#[derive(Debug, PartialEq, Clone)]
+pub enum BoopError {
+    Io(String),
+    Dns(String, String), // server address, reason
+    Tls(TlsErrorCategory, String),
+    CertificateChanged(String, String), // pinned fingerprint, presented fingerprint
+    ClientCertificate(String),
+    Credentials(String),
+    CredentialsLocked,
+    Protocol(String),
+    Profile(String),
+    InvalidBoop(String),
+    InvalidSettings(String),
+    RateLimited(i64), // milliseconds until the next boop is allowed
+    LoginRejected,
+    NotConnected,
+    SinkClosed,
+    SinkFull
+}
+
+impl BoopError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BoopError::Io(_) => "io",
+            BoopError::Dns(..) => "dns",
+            BoopError::Tls(TlsErrorCategory::Certificate, _) => "tlsCertificate",
+            BoopError::Tls(TlsErrorCategory::Handshake, _) => "tlsHandshake",
+            BoopError::CertificateChanged(..) => "certificateChanged",
+            BoopError::ClientCertificate(_) => "clientCertificate",
+            BoopError::Credentials(_) => "credentials",
+            BoopError::CredentialsLocked => "credentialsLocked",
+            BoopError::Protocol(_) => "protocol",
+            BoopError::Profile(_) => "profile",
+            BoopError::InvalidBoop(_) => "invalidBoop",
+            BoopError::InvalidSettings(_) => "invalidSettings",
+            BoopError::RateLimited(_) => "rateLimited",
+            BoopError::LoginRejected => "loginRejected",
+            BoopError::NotConnected => "notConnected",
+            BoopError::SinkClosed => "sinkClosed",
+            BoopError::SinkFull => "sinkFull"
+        }
+    }
+
+    /// I/O error for a server that broke the protocol, it turns back into
+    /// `BoopError::Protocol` when the connection code reports it
+    pub fn protocol_violation(reason: impl Into<String>) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            BoopError::Protocol(reason.into())
+        )
+    }
+}
+
+impl Display for BoopError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BoopError::Io(reason) => write!(f, "I/O error: {}", reason),
+            BoopError::Dns(server, reason) => write!(
+                f,
+                "couldn't resolve the server address \"{}\" ({}), check the address in the \
+                 settings (it needs a port, like example.com:1234)",
+                server, reason
+            ),
+            BoopError::Tls(TlsErrorCategory::Certificate, reason) => write!(
+                f,
+                "the server's certificate was rejected ({}), check the server address or ask \
+                 your server provider",
+                reason
+            ),
+            BoopError::Tls(TlsErrorCategory::Handshake, reason) => {
+                write!(f, "the secure connection to the server failed: {}", reason)
+            }
+            BoopError::CertificateChanged(pinned, presented) => write!(
+                f,
+                "the server's certificate changed (pinned key {}, now {}), only accept the new \
+                 key if your server provider told you about the change",
+                pinned, presented
+            ),
+            BoopError::ClientCertificate(reason) => write!(
+                f,
+                "the client certificate can't be used ({}), check the certificate and key \
+                 files in the settings",
+                reason
+            ),
+            BoopError::Credentials(reason) => write!(
+                f,
+                "the saved password can't be used ({}), enter it again in the settings",
+                reason
+            ),
+            BoopError::CredentialsLocked => write!(
+                f,
+                "the saved password is protected by the master passphrase, enter it first"
+            ),
+            BoopError::Protocol(reason) => write!(
+                f,
+                "the server doesn't speak the BOOP protocol as expected: {}",
+                reason
+            ),
+            BoopError::Profile(reason) => write!(f, "the profiles can't be changed: {}", reason),
+            BoopError::InvalidBoop(reason) => write!(f, "the boop can't be sent: {}", reason),
+            BoopError::InvalidSettings(reason) => {
+                write!(f, "the settings can't be saved: {}", reason)
+            }
+            BoopError::RateLimited(wait) => write!(
+                f,
+                "that's a lot of boops, wait {} seconds before booping again",
+                (wait + 999) / 1000
+            ),
+            BoopError::LoginRejected => write!(
+                f,
+                "the server refused the login, check your user name and password"
+            ),
+            BoopError::NotConnected => write!(f, "not connected to the server"),
+            BoopError::SinkClosed => write!(
+                f,
+                "the connection to the server is closing, try again after reconnecting"
+            ),
+            BoopError::SinkFull => write!(
+                f,
+                "the connection to the server is busy, try again in a moment"
+            )
+        }
+    }
+}
+
+impl std::error::Error for BoopError {}
+
+impl Serialize for BoopError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("BoopError", 2)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        if let BoopError::CertificateChanged(_, presented) = self {
+            state.serialize_field("fingerprint", presented)?;
+        }
+        state.end()
+    }
+}
+
+impl From<io::Error> for BoopError {
+    fn from(err: io::Error) -> Self {
+        // TLS failures come wrapped in I/O errors
+        if let Some(tls_err) = err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+        {
+            return BoopError::from(tls_err.clone());
+        }
+
+        // and so do protocol violations
+        if let Some(boop_err) = err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<BoopError>())
+        {
+            return boop_err.clone();
+        }
+
+        BoopError::Io(err.to_string())
+    }
+}
+
+impl From<rustls::Error> for BoopError {
+    fn from(err: rustls::Error) -> Self {
+        let category = match err {
+            rustls::Error::NoCertificatesPresented
+            | rustls::Error::UnsupportedNameType
+            | rustls::Error::InvalidCertificateEncoding
+            | rustls::Error::InvalidCertificateSignatureType
+            | rustls::Error::InvalidCertificateSignature
+            | rustls::Error::InvalidCertificateData(_)
+            | rustls::Error::InvalidSct(_) => TlsErrorCategory::Certificate,
+            _ => TlsErrorCategory::Handshake
+        };
+
+        BoopError::Tls(category, err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::error::BoopError,
+        std::io
+    };
+
+    #[test]
+    fn test_io_error_kinds() {
+        let violation = BoopError::protocol_violation("expected HELLO, got HEY");
+        assert_eq!(
+            BoopError::from(violation),
+            BoopError::Protocol(String::from("expected HELLO, got HEY"))
+        );
+
+        // broken files aren't the server's fault
+        let invalid = io::Error::new(io::ErrorKind::InvalidData, "expected value");
+        assert_eq!(
+            BoopError::from(invalid),
+            BoopError::Io(String::from("expected value"))
+        );
+    }
+}
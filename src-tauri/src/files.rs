@@ -1,49 +1,281 @@
 use {
+    crate::schema::{
+        to_versioned,
+        upgrade,
+        SchemaError,
+        Versioned
+    },
     directories::ProjectDirs,
-    serde::{
-        de::DeserializeOwned,
-        Serialize
-    }
+    tokio::io::AsyncWriteExt
 };
 
 use std::{
+    ffi::OsString,
     fs,
     io::{
         self,
-        BufReader
+        BufReader,
+        Write
     },
-    path::PathBuf
+    path::{
+        Path,
+        PathBuf
+    },
+    process,
+    sync::atomic::{
+        AtomicU64,
+        Ordering
+    }
 };
 
-pub async fn save_file<T>(filename: &PathBuf, partners: &T) -> Result<(), io::Error>
-where T: Serialize {
-    let serialized = serde_json::to_string_pretty::<T>(partners)?;
-    tokio::fs::write(PathBuf::from(filename), serialized).await
+/// How many previous versions of a file are kept as backups
+pub const BACKUP_GENERATIONS: u32 = 3;
+
+/// A file that couldn't be read and what was used instead
+#[derive(Debug, PartialEq, Clone)]
+pub enum FileRecovery {
+    RestoredBackup(PathBuf, PathBuf), // broken file, backup that replaced it
+    NoValidBackup(PathBuf),           // broken file, default was used
+    NewerVersion(PathBuf, u32, PathBuf)  // file, its schema version, preserved copy
+}
+
+impl FileRecovery {
+    pub fn message(&self) -> String {
+        match self {
+            FileRecovery::RestoredBackup(file, backup) => format!(
+                "{} was damaged and has been restored from the backup {}",
+                file.to_string_lossy(),
+                backup.to_string_lossy()
+            ),
+            FileRecovery::NoValidBackup(file) => format!(
+                "{} was damaged and there was no usable backup, it has been reset",
+                file.to_string_lossy()
+            ),
+            FileRecovery::NewerVersion(file, version, copy) => format!(
+                "{} was saved by a newer version of BOOP (schema version {}). It has been kept \
+                 as {}, older settings are used until you update.",
+                file.to_string_lossy(),
+                version,
+                copy.to_string_lossy()
+            )
+        }
+    }
+}
+
+/// Saves `object` without ever leaving a half-written file behind: the data
+/// is written to a temporary file, synced and then renamed over the old
+/// file. The old file is kept as the newest backup. The data is saved with
+/// its schema version. Every save uses its own temporary file, so saves of
+/// the same file at the same time don't mix.
+pub async fn save_file<T>(filename: &PathBuf, object: &T) -> Result<(), io::Error>
+where T: Versioned {
+    let serialized = serde_json::to_string_pretty(&to_versioned(object)?)?;
+
+    let temp_file = temp_path(filename);
+    let mut file = tokio::fs::File::create(&temp_file).await?;
+    file.write_all(serialized.as_bytes()).await?;
+    file.sync_all().await?;
+    drop(file);
+
+    if let Err(err) = rotate_backups(filename).await {
+        let _ = tokio::fs::remove_file(&temp_file).await;
+        return Err(err);
+    }
+    tokio::fs::rename(&temp_file, filename).await?;
+
+    let filename = filename.clone();
+    tokio::task::spawn_blocking(move || sync_dir(&filename)).await?
 }
 
-pub fn get_object_or_default<T: Default + DeserializeOwned>(filename: &PathBuf) -> T {
-    match read_file(filename) {
-        Ok(data) => data,
-        Err(err) => {
-            if err.kind() != io::ErrorKind::NotFound {
-                error!("failed to read the file: {}", err);
+/// Reads `filename`, falls back to the newest backup that can be read if the
+/// file is damaged and to the default if there is none. Data from older
+/// versions is migrated.
+pub fn get_object_or_default<T: Versioned>(filename: &PathBuf) -> T {
+    get_object_or_backup(filename).0
+}
+
+/// Same as `get_object_or_default`, but also tells whether the file had to be
+/// recovered. A restored backup is written back in place of the broken file.
+/// A file from a newer version is copied aside first, so it isn't lost when
+/// the file is saved again. A file from an older version is written back
+/// upgraded, so the old data (like a plain text password) doesn't stay on disk.
+pub fn get_object_or_backup<T: Versioned>(filename: &PathBuf) -> (T, Option<FileRecovery>) {
+    let err = match read_file(filename) {
+        Ok((data, version)) => {
+            if version < T::VERSION {
+                if let Err(err) = write_upgraded(filename, &data) {
+                    error!(
+                        "failed to save the upgraded {}: {}",
+                        filename.to_string_lossy(),
+                        err
+                    );
+                }
             }
-            info!(
-                "file {} not found, using default",
-                filename.to_string_lossy()
+            return (data, None);
+        }
+        Err(err) => err
+    };
+
+    if err.kind() == io::ErrorKind::NotFound {
+        info!(
+            "file {} not found, using default",
+            filename.to_string_lossy()
+        );
+        return (T::default(), None);
+    }
+
+    error!(
+        "failed to read the file {}: {}",
+        filename.to_string_lossy(),
+        err
+    );
+
+    let newer = newer_version(&err).map(|version| {
+        let copy = with_suffix(filename, &format!(".v{}", version));
+        if let Err(err) = fs::copy(filename, &copy) {
+            error!(
+                "failed to keep a copy of {}: {}",
+                filename.to_string_lossy(),
+                err
             );
-            T::default()
+        }
+        FileRecovery::NewerVersion(filename.clone(), version, copy)
+    });
+
+    for generation in 1..=BACKUP_GENERATIONS {
+        let backup = backup_path(filename, generation);
+        match read_file(&backup) {
+            Ok((data, _)) => {
+                warn!(
+                    "restoring {} from {}",
+                    filename.to_string_lossy(),
+                    backup.to_string_lossy()
+                );
+                if let Err(err) = restore_backup(filename, &backup) {
+                    error!("failed to write the restored backup back: {}", err);
+                }
+                return (
+                    data,
+                    newer.or(Some(FileRecovery::RestoredBackup(filename.clone(), backup)))
+                );
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => warn!(
+                "backup {} is damaged as well: {}",
+                backup.to_string_lossy(),
+                err
+            )
         }
     }
+
+    error!(
+        "no usable backup of {}, using default",
+        filename.to_string_lossy()
+    );
+    (
+        T::default(),
+        newer.or(Some(FileRecovery::NoValidBackup(filename.clone())))
+    )
+}
+
+pub fn backup_path(filename: &Path, generation: u32) -> PathBuf {
+    with_suffix(filename, &format!(".bak.{}", generation))
+}
+
+fn with_suffix(filename: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(filename.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
 }
 
-fn read_file<T: DeserializeOwned>(filename: &PathBuf) -> Result<T, io::Error> {
+/// Temporary file next to `filename` that no other save uses
+fn temp_path(filename: &Path) -> PathBuf {
+    static SAVES: AtomicU64 = AtomicU64::new(0);
+    let save = SAVES.fetch_add(1, Ordering::Relaxed);
+    with_suffix(filename, &format!(".{}.{}.tmp", process::id(), save))
+}
+
+/// Syncs the directory of `filename`, so a file renamed into it is still
+/// there after a crash. Windows can't open directories as files, it's
+/// skipped there.
+#[cfg(unix)]
+fn sync_dir(filename: &Path) -> io::Result<()> {
+    match filename.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => fs::File::open(dir)?.sync_all(),
+        _ => Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_filename: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Shifts every backup one generation back (dropping the oldest one) and
+/// copies the current file to the first generation
+async fn rotate_backups(filename: &PathBuf) -> io::Result<()> {
+    if tokio::fs::metadata(filename).await.is_err() {
+        // nothing to back up yet
+        return Ok(());
+    }
+
+    for generation in (1..BACKUP_GENERATIONS).rev() {
+        match tokio::fs::rename(
+            backup_path(filename, generation),
+            backup_path(filename, generation + 1)
+        )
+        .await
+        {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+
+    tokio::fs::copy(filename, backup_path(filename, 1)).await?;
+    Ok(())
+}
+
+/// Replaces a broken file with a copy of its backup, so the broken file
+/// doesn't push the good backups out at the next save
+fn restore_backup(filename: &PathBuf, backup: &PathBuf) -> io::Result<()> {
+    let temp_file = temp_path(filename);
+    fs::copy(backup, &temp_file)?;
+    fs::File::open(&temp_file)?.sync_all()?;
+    fs::rename(&temp_file, filename)?;
+    sync_dir(filename)
+}
+
+/// Replaces a file from an older version with its upgraded data
+fn write_upgraded<T: Versioned>(filename: &PathBuf, object: &T) -> io::Result<()> {
+    let serialized = serde_json::to_string_pretty(&to_versioned(object)?)?;
+
+    let temp_file = temp_path(filename);
+    let mut file = fs::File::create(&temp_file)?;
+    file.write_all(serialized.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&temp_file, filename)?;
+    sync_dir(filename)
+}
+
+/// Reads the data and the schema version it was saved with
+fn read_file<T: Versioned>(filename: &PathBuf) -> Result<(T, u32), io::Error> {
     let buf_reader = BufReader::new(fs::File::open(filename)?);
-    let data: T = serde_json::from_reader(buf_reader)?;
+    let data = upgrade(serde_json::from_reader(buf_reader)?)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
 
     Ok(data)
 }
 
+/// Schema version of a file that was saved by a newer version
+fn newer_version(err: &io::Error) -> Option<u32> {
+    match err.get_ref()?.downcast_ref::<SchemaError>()? {
+        SchemaError::NewerVersion(version) => Some(*version),
+        _ => None
+    }
+}
+
 pub fn get_config_file_path(filename: &str) -> PathBuf {
     if let Some(proj_dir) = ProjectDirs::from("dev", "iyoshok", "boop.client") {
         let cfg_dir = proj_dir.config_dir();
@@ -61,6 +293,41 @@ pub fn get_config_file_path(filename: &str) -> PathBuf {
     panic!("failed to get config directory");
 }
 
+/// Path of a file in a profile's own directory in the config directory
+pub fn get_profile_file_path(profile: &str, filename: &str) -> PathBuf {
+    if let Some(proj_dir) = ProjectDirs::from("dev", "iyoshok", "boop.client") {
+        let profile_dir = proj_dir.config_dir().join("profiles").join(profile);
+
+        if fs::create_dir_all(&profile_dir).is_ok() {
+            return profile_dir.join(filename);
+        } else {
+            panic!(
+                "failed to create profile directory: {}",
+                profile_dir.to_string_lossy()
+            );
+        }
+    }
+
+    panic!("failed to get config directory");
+}
+
+pub fn get_data_file_path(filename: &str) -> PathBuf {
+    if let Some(proj_dir) = ProjectDirs::from("dev", "iyoshok", "boop.client") {
+        let data_dir = proj_dir.data_dir();
+
+        if fs::create_dir_all(data_dir).is_ok() {
+            return data_dir.join(filename);
+        } else {
+            panic!(
+                "failed to create data directory: {}",
+                data_dir.to_string_lossy()
+            );
+        }
+    }
+
+    panic!("failed to get data directory");
+}
+
 pub fn get_log_dir_name(base: &str) -> PathBuf {
     if let Some(proj_dir) = ProjectDirs::from("dev", "iyoshok", "boop.client") {
         let log_dir = proj_dir.data_dir().join(base);
@@ -77,3 +344,179 @@ pub fn get_log_dir_name(base: &str) -> PathBuf {
 
     panic!("failed to get logging directory");
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{
+            files::{
+                backup_path,
+                get_object_or_backup,
+                save_file,
+                FileRecovery,
+                BACKUP_GENERATIONS
+            },
+            schema::{
+                from_versioned,
+                Migration,
+                Versioned
+            }
+        },
+        std::{
+            env,
+            fs,
+            path::{
+                Path,
+                PathBuf
+            }
+        }
+    };
+
+    impl Versioned for Vec<i32> {
+        const MIGRATIONS: &'static [Migration] = &[];
+        const VERSION: u32 = 1;
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("boop-files-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_save_keeps_backups() {
+        let dir = temp_dir("backups");
+        let file = dir.join("list.json");
+
+        for i in 0..5 {
+            save_file(&file, &vec![i]).await.unwrap();
+        }
+
+        let (saved, recovery): (Vec<i32>, _) = get_object_or_backup(&file);
+        assert_eq!(saved, vec![4]);
+        assert_eq!(recovery, None);
+
+        // newest backup is the previous version, only BACKUP_GENERATIONS are kept
+        for generation in 1..=BACKUP_GENERATIONS {
+            let backup: Vec<i32> = from_versioned(
+                serde_json::from_str(&fs::read_to_string(backup_path(&file, generation)).unwrap())
+                    .unwrap()
+            )
+            .unwrap();
+            assert_eq!(backup, vec![4 - generation as i32]);
+        }
+        assert!(!backup_path(&file, BACKUP_GENERATIONS + 1).exists());
+        assert!(temp_files(&dir).is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_saves() {
+        let dir = temp_dir("concurrent");
+        let file = dir.join("list.json");
+
+        let saves: Vec<_> = (0..8)
+            .map(|i| {
+                let file = file.clone();
+                tokio::spawn(async move { save_file(&file, &vec![i; 1000]).await })
+            })
+            .collect();
+        for save in saves {
+            save.await.unwrap().unwrap();
+        }
+
+        // one of the saves wins completely, none is mixed with another one
+        let (saved, recovery): (Vec<i32>, _) = get_object_or_backup(&file);
+        assert_eq!(recovery, None);
+        assert_eq!(saved.len(), 1000);
+        assert!(saved.iter().all(|i| *i == saved[0]));
+        assert!(temp_files(&dir).is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    fn temp_files(dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().ends_with(".tmp"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_damaged_file_is_restored() {
+        let dir = temp_dir("restore");
+        let file = dir.join("list.json");
+
+        save_file(&file, &vec![1]).await.unwrap();
+        save_file(&file, &vec![2]).await.unwrap();
+        save_file(&file, &vec![3]).await.unwrap();
+
+        // truncated write, the newest backup is broken as well
+        fs::write(&file, "[3").unwrap();
+        fs::write(backup_path(&file, 1), "").unwrap();
+
+        let (restored, recovery): (Vec<i32>, _) = get_object_or_backup(&file);
+        assert_eq!(restored, vec![1]);
+        assert_eq!(
+            recovery,
+            Some(FileRecovery::RestoredBackup(
+                file.clone(),
+                backup_path(&file, 2)
+            ))
+        );
+
+        // the restored data is written back, so the next read works as usual
+        let (reread, recovery): (Vec<i32>, _) = get_object_or_backup(&file);
+        assert_eq!(reread, vec![1]);
+        assert_eq!(recovery, None);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_damaged_file_without_backup() {
+        let dir = temp_dir("nobackup");
+        let file = dir.join("list.json");
+
+        let (missing, recovery): (Vec<i32>, _) = get_object_or_backup(&file);
+        assert!(missing.is_empty());
+        assert_eq!(recovery, None);
+
+        fs::write(&file, "not json").unwrap();
+        let (defaulted, recovery): (Vec<i32>, _) = get_object_or_backup(&file);
+        assert!(defaulted.is_empty());
+        assert_eq!(recovery, Some(FileRecovery::NoValidBackup(file.clone())));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_newer_version_is_preserved() {
+        let dir = temp_dir("newer");
+        let file = dir.join("list.json");
+
+        save_file(&file, &vec![1]).await.unwrap();
+        save_file(&file, &vec![2]).await.unwrap();
+        let newer = r#"{ "version": 2, "data": { "items": [3] } }"#;
+        fs::write(&file, newer).unwrap();
+
+        // the newest backup the current version can read is used
+        let (restored, recovery): (Vec<i32>, _) = get_object_or_backup(&file);
+        let copy = dir.join("list.json.v2");
+        assert_eq!(restored, vec![1]);
+        assert_eq!(
+            recovery,
+            Some(FileRecovery::NewerVersion(file.clone(), 2, copy.clone()))
+        );
+        assert_eq!(fs::read_to_string(&copy).unwrap(), newer);
+
+        // saving again doesn't touch the preserved copy
+        save_file(&file, &vec![4]).await.unwrap();
+        assert_eq!(fs::read_to_string(&copy).unwrap(), newer);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,646 @@
+use {
+    crate::{
+        network::BoopDeliveryStatus,
+        profiles::DEFAULT_PROFILE
+    },
+    chrono::{
+        Datelike,
+        TimeZone,
+        Utc
+    },
+    serde::{
+        Deserialize,
+        Serialize
+    },
+    std::{
+        collections::{
+            BTreeMap,
+            HashMap
+        },
+        fs,
+        io::{
+            self,
+            BufRead,
+            BufReader
+        },
+        path::PathBuf,
+        sync::Arc
+    },
+    tokio::{
+        io::AsyncWriteExt,
+        sync::Mutex
+    }
+};
+
+pub const HISTORY_FILENAME: &str = "boop.history.jsonl";
+
+pub type HistoryHandle = Arc<Mutex<BoopHistory>>;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum BoopDirection {
+    Sent,
+    Received
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum DeliveryStatus {
+    Queued,       // handed to the connection, not written yet
+    Sent,         // written to the connection
+    Delivered,    // acked by the server
+    NotAvailable, // the partner wasn't online
+    Failed,       // couldn't be handed to the connection or delivered
+    Received      // booped by a partner
+}
+
+/// A single boop in the history
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    timestamp:   i64, // unix timestamp in millis
+    direction:   BoopDirection,
+    #[serde(default = "default_server")]
+    server:      String, // profile id, the default one for entries from before profiles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id:          Option<String>, // of a sent boop, to match its delivery report
+    partner_key: String,
+    status:      DeliveryStatus
+}
+
+/// Delivery report for a sent boop, appended to the history file after the
+/// boop's entry
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct StatusUpdate {
+    id:     String,
+    status: DeliveryStatus
+}
+
+/// A line of the history file
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum HistoryLine {
+    Entry(HistoryEntry),
+    Update(StatusUpdate)
+}
+
+/// Length of the periods boops are counted in
+#[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum HistoryPeriod {
+    Day,
+    Week
+}
+
+/// Number of boops in one day ("2022-05-30") or ISO week ("2022-W22")
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryCount {
+    period:   String,
+    sent:     u32,
+    received: u32
+}
+
+/// Append-only boop history of all servers. Every boop is a JSON line in the
+/// history file, all entries are kept in memory (with an index per server and
+/// partner) so queries don't have to touch the disk.
+pub struct BoopHistory {
+    file:       PathBuf,
+    entries:    Vec<HistoryEntry>,
+    by_partner: HashMap<(String, String), Vec<usize>>, // by server and partner key
+    by_id:      HashMap<String, usize>
+}
+
+fn default_server() -> String {
+    String::from(DEFAULT_PROFILE)
+}
+
+impl HistoryEntry {
+    pub fn new(
+        server: String,
+        id: Option<String>,
+        partner_key: String,
+        direction: BoopDirection,
+        status: DeliveryStatus
+    ) -> Self {
+        HistoryEntry {
+            timestamp: Utc::now().timestamp_millis(),
+            direction,
+            server,
+            id,
+            partner_key,
+            status
+        }
+    }
+
+    pub fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    pub fn direction(&self) -> BoopDirection {
+        self.direction
+    }
+
+    pub fn server(&self) -> String {
+        self.server.clone()
+    }
+
+    pub fn id(&self) -> Option<String> {
+        self.id.clone()
+    }
+
+    pub fn partner_key(&self) -> String {
+        self.partner_key.clone()
+    }
+
+    pub fn status(&self) -> DeliveryStatus {
+        self.status
+    }
+}
+
+impl DeliveryStatus {
+    /// Status a delivery report gives a sent boop in the history, `None` if
+    /// it doesn't change it
+    pub fn from_report(status: BoopDeliveryStatus) -> Option<DeliveryStatus> {
+        match status {
+            BoopDeliveryStatus::Delivered => Some(DeliveryStatus::Delivered),
+            BoopDeliveryStatus::NotAvailable => Some(DeliveryStatus::NotAvailable),
+            BoopDeliveryStatus::Failed | BoopDeliveryStatus::Expired => {
+                Some(DeliveryStatus::Failed)
+            }
+            BoopDeliveryStatus::Sent => Some(DeliveryStatus::Sent),
+            BoopDeliveryStatus::Queued => None
+        }
+    }
+}
+
+impl HistoryCount {
+    pub fn period(&self) -> String {
+        self.period.clone()
+    }
+
+    pub fn sent(&self) -> u32 {
+        self.sent
+    }
+
+    pub fn received(&self) -> u32 {
+        self.received
+    }
+}
+
+impl BoopHistory {
+    /// Reads the history file, a missing file is an empty history
+    pub fn load(file: PathBuf) -> io::Result<BoopHistory> {
+        let mut history = BoopHistory {
+            file,
+            entries: Vec::new(),
+            by_partner: HashMap::new(),
+            by_id: HashMap::new()
+        };
+
+        let reader = match fs::File::open(&history.file) {
+            Ok(f) => BufReader::new(f),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(history),
+            Err(err) => return Err(err)
+        };
+
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // a line can be cut off if the app died while writing it, don't throw the
+            // rest of the history away because of that
+            match serde_json::from_str::<HistoryLine>(&line) {
+                Ok(HistoryLine::Entry(entry)) => history.index(entry),
+                Ok(HistoryLine::Update(update)) => {
+                    history.set_status(&update.id, update.status);
+                }
+                Err(err) => warn!("skipped broken boop history entry: {}", err)
+            }
+        }
+
+        Ok(history)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Appends a boop to the history file and the in-memory history
+    pub async fn append(&mut self, entry: HistoryEntry) -> io::Result<()> {
+        self.write_line(&entry).await?;
+        self.index(entry);
+        Ok(())
+    }
+
+    /// Changes the status of the sent boop with the given id. Returns false
+    /// if there is no such boop.
+    pub async fn update_status(&mut self, id: &str, status: DeliveryStatus) -> io::Result<bool> {
+        if !self.by_id.contains_key(id) {
+            return Ok(false);
+        }
+
+        self.write_line(&StatusUpdate {
+            id: String::from(id),
+            status
+        })
+        .await?;
+        Ok(self.set_status(id, status))
+    }
+
+    /// Boops with a partner of a server, newest first, skipping the `offset`
+    /// newest ones
+    pub fn page(
+        &self,
+        server: &str,
+        partner_key: &str,
+        offset: usize,
+        limit: usize
+    ) -> Vec<HistoryEntry> {
+        match self
+            .by_partner
+            .get(&(String::from(server), String::from(partner_key)))
+        {
+            Some(indices) => indices
+                .iter()
+                .rev()
+                .skip(offset)
+                .take(limit)
+                .map(|i| self.entries[*i].clone())
+                .collect(),
+            None => Vec::new()
+        }
+    }
+
+    /// Number of sent and received boops of a server per period (in the given
+    /// timezone), oldest period first. Counts all partners of the server if
+    /// `partner_key` is `None`.
+    pub fn counts<Tz: TimeZone>(
+        &self,
+        server: &str,
+        partner_key: Option<&str>,
+        period: HistoryPeriod,
+        tz: &Tz
+    ) -> Vec<HistoryCount> {
+        let entries: Box<dyn Iterator<Item = &HistoryEntry>> = match partner_key {
+            Some(partner_key) => match self
+                .by_partner
+                .get(&(String::from(server), String::from(partner_key)))
+            {
+                Some(indices) => Box::new(indices.iter().map(|i| &self.entries[*i])),
+                None => return Vec::new()
+            },
+            None => Box::new(self.entries.iter().filter(|entry| entry.server == server))
+        };
+
+        // keys sort chronologically
+        let mut counts: BTreeMap<String, (u32, u32)> = BTreeMap::new();
+        for entry in entries {
+            let date = match tz.timestamp_millis_opt(entry.timestamp).single() {
+                Some(time) => time.naive_local().date(),
+                None => continue
+            };
+            let key = match period {
+                HistoryPeriod::Day => date.format("%Y-%m-%d").to_string(),
+                HistoryPeriod::Week => {
+                    let week = date.iso_week();
+                    format!("{}-W{:02}", week.year(), week.week())
+                }
+            };
+
+            let count = counts.entry(key).or_insert((0, 0));
+            match entry.direction {
+                BoopDirection::Sent => count.0 += 1,
+                BoopDirection::Received => count.1 += 1
+            }
+        }
+
+        counts
+            .into_iter()
+            .map(|(period, (sent, received))| HistoryCount {
+                period,
+                sent,
+                received
+            })
+            .collect()
+    }
+
+    /// Deletes the whole history
+    pub async fn clear(&mut self) -> io::Result<()> {
+        match tokio::fs::remove_file(&self.file).await {
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err)
+        }
+
+        self.entries.clear();
+        self.by_partner.clear();
+        self.by_id.clear();
+        Ok(())
+    }
+
+    /// Writes the whole history to `target` as a JSON array
+    pub async fn export(&self, target: &PathBuf) -> io::Result<()> {
+        let serialized = serde_json::to_string_pretty(&self.entries)?;
+        tokio::fs::write(target, serialized).await
+    }
+
+    async fn write_line<T: Serialize>(&self, value: &T) -> io::Result<()> {
+        let mut line = serde_json::to_string(value)?;
+        line.push('\n');
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        file.flush().await
+    }
+
+    fn set_status(&mut self, id: &str, status: DeliveryStatus) -> bool {
+        match self.by_id.get(id) {
+            Some(i) => {
+                self.entries[*i].status = status;
+                true
+            }
+            None => false
+        }
+    }
+
+    fn index(&mut self, entry: HistoryEntry) {
+        if let Some(id) = &entry.id {
+            self.by_id.insert(id.clone(), self.entries.len());
+        }
+        self.by_partner
+            .entry((entry.server.clone(), entry.partner_key.clone()))
+            .or_default()
+            .push(self.entries.len());
+        self.entries.push(entry);
+    }
+}
+
+/// Appends a boop to the history, failures are only logged because a boop
+/// shouldn't fail just because the history couldn't be written
+pub async fn record_boop(
+    history_handle: &HistoryHandle,
+    server: &str,
+    id: Option<String>,
+    partner_key: String,
+    direction: BoopDirection,
+    status: DeliveryStatus
+) {
+    let entry = HistoryEntry::new(String::from(server), id, partner_key, direction, status);
+    if let Err(err) = history_handle.lock().await.append(entry).await {
+        error!("failed to write boop to history: {}", err);
+    }
+}
+
+/// Puts the delivery report for a sent boop into the history, reports that
+/// don't change its status are ignored
+pub async fn record_delivery(history_handle: &HistoryHandle, id: &str, status: BoopDeliveryStatus) {
+    let status = match DeliveryStatus::from_report(status) {
+        Some(status) => status,
+        None => return
+    };
+    match history_handle.lock().await.update_status(id, status).await {
+        Ok(true) => {}
+        Ok(false) => debug!("boop {} isn't in the history", id),
+        Err(err) => error!("failed to write boop delivery to history: {}", err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{
+            history::{
+                record_delivery,
+                BoopDirection,
+                BoopHistory,
+                DeliveryStatus,
+                HistoryEntry,
+                HistoryPeriod
+            },
+            network::BoopDeliveryStatus,
+            profiles::DEFAULT_PROFILE
+        },
+        chrono::Utc,
+        std::{
+            env,
+            fs,
+            path::PathBuf,
+            sync::Arc
+        },
+        tokio::sync::Mutex
+    };
+
+    fn temp_file(name: &str) -> PathBuf {
+        let file = env::temp_dir().join(format!("boop-history-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&file);
+        file
+    }
+
+    fn entry(partner_key: &str, timestamp: i64, direction: BoopDirection) -> HistoryEntry {
+        HistoryEntry {
+            timestamp,
+            direction,
+            server: String::from("work"),
+            id: None,
+            partner_key: String::from(partner_key),
+            status: DeliveryStatus::Sent
+        }
+    }
+
+    #[tokio::test]
+    async fn test_history_survives_reload() {
+        let file = temp_file("reload");
+        let mut history = BoopHistory::load(file.clone()).unwrap();
+        assert!(history.is_empty());
+
+        history
+            .append(entry("alice", 1, BoopDirection::Sent))
+            .await
+            .unwrap();
+        history
+            .append(entry("bob", 2, BoopDirection::Received))
+            .await
+            .unwrap();
+
+        // a half-written line doesn't break the rest
+        let mut contents = fs::read_to_string(&file).unwrap();
+        contents.push_str("{\"timestamp\":3,\"dir");
+        fs::write(&file, contents).unwrap();
+
+        let history = BoopHistory::load(file.clone()).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.page("work", "bob", 0, 10), vec![entry(
+            "bob",
+            2,
+            BoopDirection::Received
+        )]);
+
+        let _ = fs::remove_file(&file);
+    }
+
+    #[tokio::test]
+    async fn test_page_newest_first() {
+        let file = temp_file("page");
+        let mut history = BoopHistory::load(file.clone()).unwrap();
+        for timestamp in 0..10 {
+            history
+                .append(entry("alice", timestamp, BoopDirection::Sent))
+                .await
+                .unwrap();
+            history
+                .append(entry("bob", timestamp, BoopDirection::Sent))
+                .await
+                .unwrap();
+        }
+
+        let timestamps: Vec<i64> = history
+            .page("work", "alice", 3, 4)
+            .iter()
+            .map(|entry| entry.timestamp())
+            .collect();
+        assert_eq!(timestamps, vec![6, 5, 4, 3]);
+        assert!(history.page("work", "alice", 10, 4).is_empty());
+        assert!(history.page("work", "carol", 0, 4).is_empty());
+        assert!(history.page("home", "alice", 0, 4).is_empty());
+
+        history.clear().await.unwrap();
+        assert!(history.is_empty());
+        assert!(BoopHistory::load(file).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_counts() {
+        const DAY: i64 = 24 * 60 * 60 * 1000;
+        // 2022-05-30 (monday of ISO week 22)
+        const MONDAY: i64 = 1653868800000;
+
+        let file = temp_file("counts");
+        let mut history = BoopHistory::load(file.clone()).unwrap();
+        history
+            .append(entry("alice", MONDAY, BoopDirection::Sent))
+            .await
+            .unwrap();
+        history
+            .append(entry("alice", MONDAY + 1, BoopDirection::Received))
+            .await
+            .unwrap();
+        history
+            .append(entry("bob", MONDAY + DAY, BoopDirection::Sent))
+            .await
+            .unwrap();
+        history
+            .append(entry("alice", MONDAY + 7 * DAY, BoopDirection::Sent))
+            .await
+            .unwrap();
+
+        let days: Vec<(String, u32, u32)> = history
+            .counts("work", Some("alice"), HistoryPeriod::Day, &Utc)
+            .iter()
+            .map(|count| (count.period(), count.sent(), count.received()))
+            .collect();
+        assert_eq!(days, vec![
+            (String::from("2022-05-30"), 1, 1),
+            (String::from("2022-06-06"), 1, 0)
+        ]);
+
+        let weeks: Vec<(String, u32, u32)> = history
+            .counts("work", None, HistoryPeriod::Week, &Utc)
+            .iter()
+            .map(|count| (count.period(), count.sent(), count.received()))
+            .collect();
+        assert_eq!(weeks, vec![
+            (String::from("2022-W22"), 2, 1),
+            (String::from("2022-W23"), 1, 0)
+        ]);
+        assert!(history
+            .counts("home", None, HistoryPeriod::Week, &Utc)
+            .is_empty());
+
+        let _ = fs::remove_file(&file);
+    }
+
+    #[tokio::test]
+    async fn test_delivery_updates_entry() {
+        let file = temp_file("delivery");
+        let mut history = BoopHistory::load(file.clone()).unwrap();
+        let mut sent = entry("alice", 1, BoopDirection::Sent);
+        sent.id = Some(String::from("b1"));
+        history.append(sent).await.unwrap();
+        history
+            .append(entry("alice", 2, BoopDirection::Received))
+            .await
+            .unwrap();
+
+        assert!(history
+            .update_status("b1", DeliveryStatus::Delivered)
+            .await
+            .unwrap());
+        assert!(!history
+            .update_status("b2", DeliveryStatus::Failed)
+            .await
+            .unwrap());
+        assert_eq!(
+            history.page("work", "alice", 1, 1)[0].status(),
+            DeliveryStatus::Delivered
+        );
+
+        // the update is applied again when loading
+        let history = BoopHistory::load(file.clone()).unwrap();
+        assert_eq!(history.len(), 2);
+        let page = history.page("work", "alice", 0, 10);
+        assert_eq!(page[1].id(), Some(String::from("b1")));
+        assert_eq!(page[1].status(), DeliveryStatus::Delivered);
+        assert_eq!(page[0].status(), DeliveryStatus::Sent);
+
+        let _ = fs::remove_file(&file);
+    }
+
+    #[tokio::test]
+    async fn test_boop_stays_queued_until_written() {
+        let file = temp_file("queued");
+        let history_handle = Arc::new(Mutex::new(BoopHistory::load(file.clone()).unwrap()));
+        let mut queued = entry("alice", 1, BoopDirection::Sent);
+        queued.id = Some(String::from("b1"));
+        queued.status = DeliveryStatus::Queued;
+        history_handle.lock().await.append(queued).await.unwrap();
+
+        let status =
+            || async { history_handle.lock().await.page("work", "alice", 0, 1)[0].status() };
+        record_delivery(&history_handle, "b1", BoopDeliveryStatus::Queued).await;
+        assert_eq!(status().await, DeliveryStatus::Queued);
+        record_delivery(&history_handle, "b1", BoopDeliveryStatus::Sent).await;
+        assert_eq!(status().await, DeliveryStatus::Sent);
+        record_delivery(&history_handle, "b1", BoopDeliveryStatus::Delivered).await;
+        assert_eq!(status().await, DeliveryStatus::Delivered);
+
+        let _ = fs::remove_file(&file);
+    }
+
+    #[test]
+    fn test_entries_before_profiles() {
+        let file = temp_file("legacy");
+        fs::write(
+            &file,
+            "{\"timestamp\":1,\"direction\":\"sent\",\"partnerKey\":\"alice\",\"status\":\"sent\"}\n"
+        )
+        .unwrap();
+
+        let history = BoopHistory::load(file.clone()).unwrap();
+        let page = history.page(DEFAULT_PROFILE, "alice", 0, 10);
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].server(), DEFAULT_PROFILE);
+
+        let _ = fs::remove_file(&file);
+    }
+}
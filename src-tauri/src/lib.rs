@@ -0,0 +1,24 @@
+//! Shared core of the BOOP client: configuration, persistence, the line
+//! protocol and the server connection. Used by the desktop app as well as by
+//! the headless `boop-cli` binary.
+
+#[macro_use]
+extern crate log;
+
+pub mod auth;
+pub mod config;
+pub mod error;
+pub mod files;
+pub mod history;
+pub mod message;
+pub mod network;
+pub mod outbox;
+pub mod partners;
+pub mod profiles;
+pub mod queue;
+pub mod ratelimit;
+pub mod requests;
+pub mod schema;
+pub mod secrets;
+pub mod stats;
+pub mod tls;
@@ -6,22 +6,38 @@
 const LOG_DIR_BASE: &str = "logs";
 
 use {
+    boop_snoot::network::{
+        events_of_server,
+        BoopDeliveryStatus,
+        ConnectionEvent,
+        ConnectionMap,
+        ReconnectInfo,
+        ServerConnectionStatus,
+        ServerEventRx,
+        ServerEventTx
+    },
+    chrono::{
+        Local,
+        Utc
+    },
     flexi_logger::{
         Duplicate,
         FileSpec,
         Logger,
         WriteMode
     },
-    rustls_native_certs::load_native_certs,
     std::{
-        collections::HashMap,
-        io,
+        collections::{
+            HashMap,
+            HashSet
+        },
         process::exit,
-        sync::Arc
+        sync::Arc,
+        time::Duration
     },
     tauri::State,
     tokio::sync::{
-        mpsc,
+        mpsc::unbounded_channel,
         Mutex
     },
     tokio_rustls::rustls::RootCertStore
@@ -30,11 +46,6 @@ use {
 #[macro_use]
 extern crate log;
 
-mod config;
-mod files;
-mod message;
-mod network;
-mod partners;
 mod window_titles;
 
 use {
@@ -55,10 +66,13 @@ use {
     window_titles::get_random_window_title
 };
 
-use {
-    config::CONFIG_FILENAME,
-    files::get_config_file_path,
-    partners::PARTNERS_FILENAME
+use boop_snoot::{
+    files::{
+        get_config_file_path,
+        get_data_file_path
+    },
+    history::HISTORY_FILENAME,
+    profiles::PROFILES_FILENAME
 };
 
 use tauri::Menu;
@@ -69,42 +83,75 @@ use tauri::{
     Submenu
 };
 
-use crate::{
+use boop_snoot::{
     config::BoopConfig,
+    error::BoopError,
     files::{
         get_log_dir_name,
-        get_object_or_default,
-        save_file
+        get_object_or_backup,
+        save_file,
+        FileRecovery
+    },
+    history::{
+        record_boop,
+        record_delivery,
+        BoopDirection,
+        BoopHistory,
+        DeliveryStatus,
+        HistoryCount,
+        HistoryEntry,
+        HistoryHandle,
+        HistoryPeriod
+    },
+    message::{
+        new_boop_id,
+        BoopContent,
+        MessageType
     },
-    message::MessageType,
     network::connect_to_server,
-    partners::BoopPartner
+    outbox::{
+        Outbox,
+        OutboxHandle,
+        QueuedBoop
+    },
+    partners::{
+        build_partners_map,
+        BoopPartner,
+        PartnerOnlineStatus,
+        PartnersHandle,
+        PartnersMap
+    },
+    profiles::{
+        Profile,
+        ProfilePaths,
+        Profiles
+    },
+    ratelimit::{
+        FloodHandle,
+        Incoming,
+        LimiterHandle
+    },
+    requests::{
+        record_boop_request,
+        BoopRequest,
+        RequestsHandle,
+        RequestsInbox
+    },
+    secrets::{
+        KeyHandle,
+        KeySource,
+        KEY_FILENAME
+    },
+    stats::ConnectionStats,
+    tls::init_trust_anchors
 };
 
-#[derive(Debug)]
-pub enum ControlMessage {
-    CloseConnection
-}
-
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct FrontendPartnerObject {
     nickname: String,
     user_key: String,
-    online:   i8
-}
-
-#[derive(Debug, PartialEq, Copy, Clone)]
-pub enum PartnerOnlineStatus {
-    Afk = -1,
-    Unknown = 0,
-    Online = 1
-}
-
-#[derive(Debug, PartialEq, Copy, Clone)]
-pub enum ServerConnectionStatus {
-    Disconnected = -1,
-    AttemptingConnection = 0,
-    Connected = 1
+    online:   i8,
+    server:   String // id of the profile the partner belongs to
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -118,79 +165,170 @@ impl Display for FrontendErrorMessage {
     }
 }
 
-/// Shorthand for the transmit half of the message channel.
-pub type SinkTx = mpsc::UnboundedSender<MessageType>;
-pub type ControlTx = mpsc::UnboundedSender<ControlMessage>;
+// state definitions
+pub struct ProfilesState(Mutex<Profiles>);
+pub struct LoadedProfiles(Mutex<HashMap<String, ProfileHandles>>);
+pub struct HistoryState(HistoryHandle);
+pub struct ConnectionState(ConnectionMap); // by profile id
+pub struct TrustAnchors(RootCertStore);
+pub struct ConnectionEvents(ServerEventTx);
+pub struct OnlineServers(Mutex<HashSet<String>>); // logged in, changed with the outbox locked
+pub struct FileRecoveries(Mutex<Vec<FileRecovery>>);
+pub struct ConnectionQuality(Mutex<HashMap<String, ConnectionStats>>); // last report by server
+
+/// Settings, keys, partners, boop requests and outbox of a loaded profile.
+/// Every profile has its own server connection, with the profile id as server
+/// id.
+#[derive(Clone)]
+pub struct ProfileHandles {
+    config:   Arc<Mutex<BoopConfig>>,
+    keys:     KeyHandle, // the master passphrase is entered per profile
+    partners: PartnersHandle,
+    requests: RequestsHandle,
+    outbox:   OutboxHandle, // boops made while the server wasn't connected
+    limits:   (LimiterHandle, FloodHandle), // sent and received boops per partner
+    files:    ProfilePaths
+}
 
-/// Shorthand for the receive half of the message channel.
-pub type SinkRx = mpsc::UnboundedReceiver<MessageType>;
-pub type ControlRx = mpsc::UnboundedReceiver<ControlMessage>;
+impl LoadedProfiles {
+    async fn get(&self, id: &str) -> Result<ProfileHandles, BoopError> {
+        self.0
+            .lock()
+            .await
+            .get(id)
+            .cloned()
+            .ok_or_else(|| BoopError::Profile(format!("the profile \"{}\" isn't loaded", id)))
+    }
 
-// state definitions
-struct ConnectionInterface {
-    sink:            SinkTx,
-    control_channel: ControlTx
+    /// Loads the profile's files, unless it's loaded already
+    async fn load(&self, id: &str, recoveries: &FileRecoveries) -> ProfileHandles {
+        let mut loaded = self.0.lock().await;
+        if let Some(profile) = loaded.get(id) {
+            return profile.clone();
+        }
+
+        let mut found = Vec::new();
+        let profile = load_profile(id, &mut found).await;
+        recoveries.0.lock().await.extend(found);
+
+        loaded.insert(String::from(id), profile.clone());
+        profile
+    }
+
+    async fn unload(&self, id: &str) {
+        let _ = self.0.lock().await.remove(id);
+    }
+
+    async fn all(&self) -> Vec<(String, ProfileHandles)> {
+        let loaded = self.0.lock().await;
+        loaded
+            .iter()
+            .map(|(id, profile)| (id.clone(), profile.clone()))
+            .collect()
+    }
 }
 
-pub struct ConfigState(Arc<Mutex<BoopConfig>>);
-pub struct PartnersState(Arc<Mutex<HashMap<String, (BoopPartner, PartnerOnlineStatus)>>>);
-pub struct ConnectionState(Arc<Mutex<Option<ConnectionInterface>>>);
-pub struct TrustAnchors(RootCertStore);
-struct ConfigFilePath(PathBuf);
-struct PartnersFilePath(PathBuf);
+// every event names the server (profile id) it came from
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 struct BoopPayload {
-    partner_key: String
+    server:      String,
+    partner_key: String,
+    content:     BoopContent, // emoji, note and kind
+    count:       u32          // boops of a flood shown as one
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+struct BoopReceipt {
+    id:     String,
+    status: BoopDeliveryStatus // always queued at first
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+struct BoopDeliveryPayload {
+    server: String,
+    id:     String, // returned by the boop command
+    status: BoopDeliveryStatus
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+struct ConnectionQualityPayload {
+    server: String,
+    stats:  ConnectionStats
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+struct BoopRequestPayload {
+    server:   String,
+    user_key: String
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 struct PartnerUpdatePayload {
+    server:   String,
     user_key: String,
     online:   i8
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 struct ConnectionStatusPayload {
-    status: i8
+    server:    String,
+    status:    i8,
+    reconnect: Option<ReconnectInfo>
 }
 
 fn main() {
     // initialize logger
     init_logging();
 
-    let config_path = get_config_file_path(CONFIG_FILENAME);
-    let partners_path = get_config_file_path(PARTNERS_FILENAME);
-
-    // get config
-    let config: BoopConfig = get_object_or_default(&config_path);
-
-    // get saved partners and build hashmap
-    let partners: Vec<BoopPartner> = get_object_or_default(&partners_path);
-    let mut partners_hashmap = HashMap::new();
-    for partner in partners {
-        partners_hashmap.insert(partner.user_key(), (partner, PartnerOnlineStatus::Unknown));
-    }
+    // get profiles, the active one decides which files are used
+    let mut recoveries = Vec::new();
+    let (profiles, recovery): (Profiles, _) =
+        get_object_or_backup(&get_config_file_path(PROFILES_FILENAME));
+    recoveries.extend(recovery);
+
+    // get config, partners and boop requests of the active profile, other profiles
+    // are loaded when they're used
+    let active_profile = profiles.active();
+    let profile = tauri::async_runtime::block_on(load_profile(&active_profile, &mut recoveries));
+    let loaded_profiles = HashMap::from([(active_profile, profile)]);
+
+    // get boop history
+    let history = match BoopHistory::load(get_data_file_path(HISTORY_FILENAME)) {
+        Ok(history) => history,
+        Err(err) => {
+            error!("failed to read boop history: {}", err);
+            exit(-1);
+        }
+    };
 
-    // initialize cert store
+    // initialize cert store, custom CAs from the config are added when connecting
     let cert_store = match init_trust_anchors() {
         Ok(cert_store) => cert_store,
         Err(err) => {
-            error!("failed to initialize trust anchors: {}", err);
-            exit(-1);
+            error!("failed to load the OS trust anchors: {}", err);
+            RootCertStore::empty()
         }
     };
 
+    // events reported by the server connections, forwarded to the frontend
+    let (event_tx, event_rx) = unbounded_channel();
+
     let mut tauri_builder = tauri::Builder::default()
-        .manage(ConnectionState(Arc::new(Mutex::new(None))))
-        .manage(ConfigState(Arc::new(Mutex::new(config))))
-        .manage(PartnersState(Arc::new(Mutex::new(partners_hashmap))))
+        .manage(ConnectionState(ConnectionMap::default()))
+        .manage(ProfilesState(Mutex::new(profiles)))
+        .manage(LoadedProfiles(Mutex::new(loaded_profiles)))
+        .manage(HistoryState(Arc::new(Mutex::new(history))))
         .manage(TrustAnchors(cert_store))
-        .manage(ConfigFilePath(config_path))
-        .manage(PartnersFilePath(partners_path))
+        .manage(ConnectionEvents(event_tx))
+        .manage(OnlineServers(Mutex::new(HashSet::new())))
+        .manage(ConnectionQuality(Mutex::new(HashMap::new())))
+        .manage(FileRecoveries(Mutex::new(recoveries)))
         .setup(|app| {
             let main_window = app.get_window("main").unwrap();
-            let _ = main_window.set_title(&get_random_window_title())?;
+            main_window.set_title(&get_random_window_title())?;
+
+            tauri::async_runtime::spawn(forward_connection_events(event_rx, main_window));
 
             Ok(())
         })
@@ -199,11 +337,31 @@ fn main() {
             disconnect,
             get_settings,
             save_settings,
+            unlock_credentials,
+            set_master_passphrase,
+            accept_server_certificate,
+            get_profiles,
+            create_profile,
+            rename_profile,
+            delete_profile,
+            switch_profile,
             get_partners,
             add_or_update_partner,
             del_partner,
+            get_boop_requests,
+            accept_boop_request,
+            reject_boop_request,
+            block_boop_request,
+            get_boop_history,
+            get_boop_counts,
+            clear_boop_history,
+            export_boop_history,
+            take_file_recoveries,
             show_main_window,
-            boop
+            boop,
+            get_outbox,
+            cancel_queued_boop,
+            get_connection_stats
         ]);
 
     if let Some(menu) = get_window_menu() {
@@ -232,20 +390,6 @@ fn init_logging() {
     debug!("log initialized");
 }
 
-fn init_trust_anchors() -> Result<RootCertStore, io::Error> {
-    let certs = load_native_certs()?;
-    debug!("found {} OS certificates", certs.len());
-
-    let mut roots = RootCertStore::empty();
-    for cert in certs {
-        roots
-            .add(&tokio_rustls::rustls::Certificate(cert.0))
-            .unwrap();
-    }
-
-    Ok(roots)
-}
-
 #[cfg(target_os = "macos")]
 fn get_window_menu() -> Option<Menu> {
     // create menu to allow copy + paste on macos (what a stupid way to handle this)
@@ -277,23 +421,41 @@ fn get_window_menu() -> Option<Menu> {
 }
 
 #[tauri::command]
-async fn get_settings<'a>(state: State<'a, ConfigState>) -> Result<BoopConfig, ()> {
-    Ok(state.0.lock().await.clone())
+async fn get_settings<'a>(
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>
+) -> Result<BoopConfig, BoopError> {
+    let (_, profile) = target_profile(None, &profiles_state, &loaded).await?;
+
+    // the password never leaves the backend
+    let config = profile.config.lock().await;
+    Ok(config.redacted())
 }
 
 #[tauri::command]
 async fn save_settings<'a>(
     new_settings: BoopConfig,
-    config_state: State<'a, ConfigState>,
-    config_file: State<'a, ConfigFilePath>
-) -> Result<(), ()> {
-    let mut config = config_state.0.lock().await;
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>
+) -> Result<(), BoopError> {
+    let (_, profile) = target_profile(None, &profiles_state, &loaded).await?;
+    let mut config = profile.config.lock().await;
     debug!("saving settings");
+    new_settings.network().validate()?;
+
+    // the frontend only knows the placeholder, a new password gets encrypted like
+    // the old one
+    let mut new_settings = new_settings;
+    new_settings.keep_password_of(&config);
+    new_settings.encrypt_password(
+        &*profile.keys.lock().await,
+        config.password_uses_passphrase()
+    )?;
 
     // save changes to disk
-    if let Err(err) = save_file(&config_file.0, &new_settings).await {
+    if let Err(err) = save_file(&profile.files.config, &new_settings).await {
         error!("failed to save new settings to disk: {}", err);
-        return Err(());
+        return Err(BoopError::from(err));
     }
 
     // save changes to state
@@ -305,10 +467,13 @@ async fn save_settings<'a>(
 #[tauri::command]
 async fn add_or_update_partner<'a>(
     partner: BoopPartner,
-    partners_state: State<'a, PartnersState>,
-    partners_file: State<'a, PartnersFilePath>
-) -> Result<(), ()> {
-    let mut partners = partners_state.0.lock().await;
+    server: Option<String>,
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>,
+    conn_state: State<'a, ConnectionState>
+) -> Result<(), BoopError> {
+    let (id, profile) = target_profile(server, &profiles_state, &loaded).await?;
+    let mut partners = profile.partners.lock().await;
 
     // update state
     let old_val_option = partners.insert(
@@ -317,8 +482,8 @@ async fn add_or_update_partner<'a>(
     );
 
     // save changes to disk and roll state changes back if the disk write failed
-    let disk_write_result = save_partners_changes(&partners, &partners_file.0).await;
-    if let Err(_) = disk_write_result {
+    let disk_write_result = save_partners_changes(&partners, &profile.files.partners).await;
+    if disk_write_result.is_err() {
         // uh oh something went wrong while saving -> restore previous state so disk and
         // memory state match
         if let Some(old_val) = old_val_option {
@@ -328,6 +493,9 @@ async fn add_or_update_partner<'a>(
             // the value was newly created -> delete
             let _ = partners.remove(&partner.user_key());
         }
+    } else if old_val_option.is_none() {
+        // a new partner's status is pushed by the server from now on
+        update_subscriptions(&conn_state, &id, vec![partner.user_key()], Vec::new()).await;
     }
 
     // the success of this operation is bound to the success of the disk write, so
@@ -338,17 +506,20 @@ async fn add_or_update_partner<'a>(
 #[tauri::command]
 async fn del_partner<'a>(
     partner_key: String,
-    partners_state: State<'a, PartnersState>,
-    partners_file: State<'a, PartnersFilePath>
-) -> Result<(), ()> {
-    let mut partners = partners_state.0.lock().await;
+    server: Option<String>,
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>,
+    conn_state: State<'a, ConnectionState>
+) -> Result<(), BoopError> {
+    let (id, profile) = target_profile(server, &profiles_state, &loaded).await?;
+    let mut partners = profile.partners.lock().await;
 
     // update state
     let old_val_option = partners.remove(&partner_key);
 
     // save changes to disk and roll state changes back if the disk write failed
-    let disk_write_result = save_partners_changes(&partners, &partners_file.0).await;
-    if let Err(_) = disk_write_result {
+    let disk_write_result = save_partners_changes(&partners, &profile.files.partners).await;
+    if disk_write_result.is_err() {
         // uh oh something went wrong while saving -> restore previous state so disk and
         // memory state match
         if let Some(old_val) = old_val_option {
@@ -359,6 +530,8 @@ async fn del_partner<'a>(
         // else: if the value didn't exist beforehand (old_val_option == None) ,
         // we didn't delete it and therefore didn't alter the state, so do
         // nothing :)
+    } else if old_val_option.is_some() {
+        update_subscriptions(&conn_state, &id, Vec::new(), vec![partner_key]).await;
     }
 
     // the success of this operation is bound to the success of the disk write, so
@@ -366,102 +539,539 @@ async fn del_partner<'a>(
     disk_write_result
 }
 
+/// Partners of every loaded profile, each with the server it's reached on
 #[tauri::command]
 async fn get_partners<'a>(
-    state: State<'a, PartnersState>
-) -> Result<Vec<FrontendPartnerObject>, ()> {
-    let partners = state.0.lock().await;
-    Ok(get_partners_payload(&*partners))
+    loaded: State<'a, LoadedProfiles>
+) -> Result<Vec<FrontendPartnerObject>, BoopError> {
+    let mut payload = Vec::new();
+    for (server, profile) in loaded.all().await {
+        let partners = profile.partners.lock().await;
+        payload.extend(get_partners_payload(&server, &partners));
+    }
+
+    Ok(payload)
+}
+
+#[tauri::command]
+async fn get_boop_requests<'a>(
+    server: Option<String>,
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>
+) -> Result<Vec<BoopRequest>, BoopError> {
+    let (_, profile) = target_profile(server, &profiles_state, &loaded).await?;
+    let requests = profile.requests.lock().await;
+    Ok(requests.requests().clone())
+}
+
+#[tauri::command]
+async fn accept_boop_request<'a>(
+    user_key: String,
+    nickname: String,
+    server: Option<String>,
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>,
+    conn_state: State<'a, ConnectionState>
+) -> Result<(), BoopError> {
+    let (id, profile) = target_profile(server, &profiles_state, &loaded).await?;
+
+    // the requester becomes a partner, same as adding them by hand
+    add_or_update_partner(
+        BoopPartner::new(nickname, user_key.clone()),
+        Some(id),
+        profiles_state,
+        loaded,
+        conn_state
+    )
+    .await?;
+
+    change_requests(&profile.requests, &profile.files.requests, |inbox| {
+        let _ = inbox.remove(&user_key);
+    })
+    .await
+}
+
+#[tauri::command]
+async fn reject_boop_request<'a>(
+    user_key: String,
+    server: Option<String>,
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>
+) -> Result<(), BoopError> {
+    let (_, profile) = target_profile(server, &profiles_state, &loaded).await?;
+    change_requests(&profile.requests, &profile.files.requests, |inbox| {
+        let _ = inbox.remove(&user_key);
+    })
+    .await
+}
+
+#[tauri::command]
+async fn block_boop_request<'a>(
+    user_key: String,
+    server: Option<String>,
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>
+) -> Result<(), BoopError> {
+    let (_, profile) = target_profile(server, &profiles_state, &loaded).await?;
+    change_requests(&profile.requests, &profile.files.requests, |inbox| {
+        inbox.block(&user_key)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn get_boop_history<'a>(
+    partner_key: String,
+    offset: usize,
+    limit: usize,
+    server: Option<String>,
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>,
+    state: State<'a, HistoryState>
+) -> Result<Vec<HistoryEntry>, BoopError> {
+    let (server, _) = target_profile(server, &profiles_state, &loaded).await?;
+    let history = state.0.lock().await;
+    Ok(history.page(&server, &partner_key, offset, limit))
+}
+
+#[tauri::command]
+async fn get_boop_counts<'a>(
+    partner_key: Option<String>,
+    period: HistoryPeriod,
+    server: Option<String>,
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>,
+    state: State<'a, HistoryState>
+) -> Result<Vec<HistoryCount>, BoopError> {
+    let (server, _) = target_profile(server, &profiles_state, &loaded).await?;
+    let history = state.0.lock().await;
+    Ok(history.counts(&server, partner_key.as_deref(), period, &Local))
+}
+
+#[tauri::command]
+async fn clear_boop_history<'a>(state: State<'a, HistoryState>) -> Result<(), BoopError> {
+    debug!("clearing boop history");
+    if let Err(err) = state.0.lock().await.clear().await {
+        error!("failed to clear boop history: {}", err);
+        return Err(BoopError::from(err));
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn export_boop_history<'a>(
+    path: PathBuf,
+    state: State<'a, HistoryState>
+) -> Result<(), BoopError> {
+    debug!("exporting boop history to {}", path.to_string_lossy());
+    if let Err(err) = state.0.lock().await.export(&path).await {
+        error!("failed to export boop history: {}", err);
+        return Err(BoopError::from(err));
+    }
+
+    Ok(())
+}
+
+/// Keeps the master passphrase (for this session) if it opens the saved
+/// password of the profile
+#[tauri::command]
+async fn unlock_credentials<'a>(
+    passphrase: String,
+    server: Option<String>,
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>
+) -> Result<(), BoopError> {
+    let (id, profile) = target_profile(server, &profiles_state, &loaded).await?;
+
+    // the key derivation takes a while, nothing stays locked for it
+    let salt = profile.config.lock().await.password_salt();
+    let unlocked = profile
+        .keys
+        .lock()
+        .await
+        .clone()
+        .with_passphrase(passphrase, salt)
+        .await?;
+
+    let config = profile.config.lock().await;
+    config.password(&unlocked)?;
+    *profile.keys.lock().await = unlocked;
+
+    info!("unlocked the saved password of profile {}", id);
+    Ok(())
+}
+
+/// Encrypts the saved password with a new master passphrase, or with the key
+/// file if `passphrase` is `None`
+#[tauri::command]
+async fn set_master_passphrase<'a>(
+    passphrase: Option<String>,
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>
+) -> Result<(), BoopError> {
+    let (_, profile) = target_profile(None, &profiles_state, &loaded).await?;
+
+    // the key derivation takes a while, nothing stays locked for it
+    let with_passphrase = passphrase.is_some();
+    let current_keys = profile.keys.lock().await.clone();
+    let new_keys = match passphrase {
+        Some(passphrase) => current_keys.with_passphrase(passphrase, None).await?,
+        None => current_keys.without_passphrase()
+    };
+
+    let mut config = profile.config.lock().await;
+    let mut keys = profile.keys.lock().await;
+
+    let mut new_config = config.clone();
+    new_config.reencrypt_password(&keys, &new_keys, with_passphrase)?;
+    if let Err(err) = save_file(&profile.files.config, &new_config).await {
+        error!("failed to save the re-encrypted password: {}", err);
+        return Err(BoopError::from(err));
+    }
+
+    *config = new_config;
+    *keys = new_keys;
+    info!(
+        "password is now encrypted with the {}",
+        if with_passphrase {
+            "master passphrase"
+        } else {
+            "key file"
+        }
+    );
+    Ok(())
+}
+
+/// Pins a new server certificate after the user accepted the change
+#[tauri::command]
+async fn accept_server_certificate<'a>(
+    fingerprint: String,
+    server: Option<String>,
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>
+) -> Result<(), BoopError> {
+    let (_, profile) = target_profile(server, &profiles_state, &loaded).await?;
+    let mut config = profile.config.lock().await;
+    info!("user accepted server certificate key {}", fingerprint);
+
+    let mut new_config = config.clone();
+    new_config.set_pinned_fingerprint(Some(fingerprint));
+    if let Err(err) = save_file(&profile.files.config, &new_config).await {
+        error!(
+            "failed to save accepted server certificate to disk: {}",
+            err
+        );
+        return Err(BoopError::from(err));
+    }
+
+    *config = new_config;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_profiles<'a>(state: State<'a, ProfilesState>) -> Result<Profiles, BoopError> {
+    Ok(state.0.lock().await.clone())
+}
+
+#[tauri::command]
+async fn create_profile<'a>(
+    name: String,
+    profiles_state: State<'a, ProfilesState>
+) -> Result<Profile, BoopError> {
+    let mut profiles = profiles_state.0.lock().await;
+
+    let mut new_profiles = profiles.clone();
+    let profile = new_profiles.create(&name)?;
+    save_profiles(&new_profiles).await?;
+
+    info!("created profile {}", profile.id());
+    *profiles = new_profiles;
+    Ok(profile)
+}
+
+#[tauri::command]
+async fn rename_profile<'a>(
+    id: String,
+    name: String,
+    profiles_state: State<'a, ProfilesState>
+) -> Result<(), BoopError> {
+    let mut profiles = profiles_state.0.lock().await;
+
+    let mut new_profiles = profiles.clone();
+    new_profiles.rename(&id, &name)?;
+    save_profiles(&new_profiles).await?;
+
+    *profiles = new_profiles;
+    Ok(())
+}
+
+/// Deletes a profile (not the active one) together with its files, its
+/// connection is closed
+#[tauri::command]
+async fn delete_profile<'a>(
+    id: String,
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>,
+    conn_state: State<'a, ConnectionState>
+) -> Result<(), BoopError> {
+    let mut profiles = profiles_state.0.lock().await;
+
+    let mut new_profiles = profiles.clone();
+    new_profiles.delete(&id)?;
+    save_profiles(&new_profiles).await?;
+    *profiles = new_profiles;
+
+    conn_state.0.disconnect(&id).await;
+    loaded.unload(&id).await;
+
+    // the profile is gone either way, left over files are only logged
+    if let Err(err) = ProfilePaths::new(&id).remove_files() {
+        error!("failed to delete the files of profile {}: {}", id, err);
+    }
+
+    info!("deleted profile {}", id);
+    Ok(())
 }
 
+/// Makes another profile the active one, whose settings are shown and where
+/// new partners are added. Connections to the other profiles' servers stay
+/// open. Returns whether the profile is connected already, otherwise the
+/// frontend connects it.
+#[tauri::command]
+async fn switch_profile<'a>(
+    id: String,
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>,
+    conn_state: State<'a, ConnectionState>,
+    recoveries: State<'a, FileRecoveries>
+) -> Result<bool, BoopError> {
+    let mut profiles = profiles_state.0.lock().await;
+    let previous = profiles.active();
+
+    if previous != id {
+        let mut new_profiles = profiles.clone();
+        new_profiles.switch(&id)?;
+        save_profiles(&new_profiles).await?;
+        *profiles = new_profiles;
+
+        loaded.load(&id, &recoveries).await;
+
+        // profiles without a connection are only kept while they're active
+        if !conn_state.0.is_connected(&previous).await {
+            loaded.unload(&previous).await;
+        }
+
+        info!("switched to profile {}", id);
+    }
+
+    Ok(conn_state.0.is_connected(&id).await)
+}
+
+/// Connects to the server of a profile (the active one by default), an
+/// existing connection to it is replaced
 #[tauri::command]
 async fn connect(
+    server: Option<String>,
+    profiles_state: State<'_, ProfilesState>,
+    loaded: State<'_, LoadedProfiles>,
     conn_state: State<'_, ConnectionState>,
-    config_state: State<'_, ConfigState>,
-    partners_state: State<'_, PartnersState>,
     trust_anchors: State<'_, TrustAnchors>,
-    window: Window
-) -> Result<bool, ()> {
-    let boxed_window = Arc::new(window);
-    match connect_to_server(
-        conn_state,
-        config_state,
-        partners_state,
-        trust_anchors,
-        Arc::clone(&boxed_window)
+    events: State<'_, ConnectionEvents>
+) -> Result<(), BoopError> {
+    let (id, profile) = target_profile(server, &profiles_state, &loaded).await?;
+
+    let res = connect_to_server(
+        &*conn_state.0.handle(&id).await,
+        profile.config,
+        profile.keys,
+        profile.partners,
+        trust_anchors.0.clone(),
+        events_of_server(&id, events.0.clone())
     )
-    .await
-    {
-        Ok(logged_in) => {
-            info!("logged in? {}", logged_in);
-            if !logged_in {
-                send_connection_status(&boxed_window, ServerConnectionStatus::Disconnected);
-            }
-            Ok(logged_in) // tells the frontend whether the login was accepted
-                          // or not
-        }
-        Err(e) => {
-            send_connection_status(&boxed_window, ServerConnectionStatus::Disconnected);
-
-            // error logging
-            error!("opening the new connection has failed, details follow");
-            if let Some(err) = e.downcast_ref::<tokio::io::Error>() {
-                error!("I/O error: {}", err);
-            } else if let Some(err) = e.downcast_ref::<std::io::Error>() {
-                error!("I/O error: {}", err);
-            } else {
-                error!("something weird happened: {}", e);
-            }
+    .await;
 
-            Err(())
+    match &res {
+        Ok(_) => info!("logged in to the server of profile {}", id),
+        Err(err) => {
+            let _ = events.0.send((
+                id.clone(),
+                ConnectionEvent::StatusChanged(ServerConnectionStatus::Disconnected)
+            ));
+            error!(
+                "opening the new connection for profile {} has failed: {}",
+                id, err
+            );
         }
     }
+
+    // the frontend tells a refused login apart from other failures by the error
+    // kind
+    res
 }
 
+/// Closes the connection to one server, or to all of them if `server` is
+/// `None`
 #[tauri::command]
-async fn disconnect(conn_state: State<'_, ConnectionState>) -> Result<(), ()> {
-    // lock current connection interface, close the connection, clear the handle and
-    // keep the lock to make sure no other process tries to access the
-    // connection during this connect call send close message to the current
-    // stream handler -> will close the existing connection
-    let mut interface_option = conn_state.0.lock().await;
-    if let Some(conn_interface) = &*interface_option {
-        // check if channel is still open (the connection might have been terminated
-        // unexpectedly before with the control channel going out of scope)
-        if !conn_interface.control_channel.is_closed() {
-            let close_res = conn_interface
-                .control_channel
-                .send(ControlMessage::CloseConnection);
-            if let Err(err) = close_res {
-                error!("failed to close connection as requested: {}", err);
+async fn disconnect(
+    server: Option<String>,
+    profiles_state: State<'_, ProfilesState>,
+    loaded: State<'_, LoadedProfiles>,
+    conn_state: State<'_, ConnectionState>
+) -> Result<(), BoopError> {
+    match server {
+        Some(id) => {
+            conn_state.0.disconnect(&id).await;
+
+            // a profile in the background isn't needed anymore without its connection
+            if profiles_state.0.lock().await.active() != id {
+                loaded.unload(&id).await;
             }
         }
+        None => conn_state.0.disconnect_all().await
     }
-    // drop the interface -> its locked so existing connections should be
-    // interrupted until the new interface is built
-    *interface_option = None;
 
     Ok(())
 }
 
+/// Boops a partner, `content` (emoji, note and kind) is left out on relays that
+/// don't support it. Boops wait in the profile's outbox while the server isn't
+/// connected. Returns the boop's id, it's queued until the connection wrote
+/// it. Its delivery is reported with `boop-delivery` events.
 #[tauri::command]
-async fn boop(partner_key: String, connection_state: State<'_, ConnectionState>) -> Result<(), ()> {
-    let connection_interface = connection_state.0.lock().await;
-
-    if let Some(connections) = &*connection_interface {
-        if !connections.sink.is_closed() {
-            if let Err(err) = connections.sink.send(MessageType::BOOP(partner_key)) {
-                error!("failed to send boop to sink: {}", err);
-                return Err(());
-            }
-        } else {
-            warn!("client tried to boop, but the sink channel was closed");
+#[allow(clippy::too_many_arguments)]
+async fn boop(
+    partner_key: String,
+    content: Option<BoopContent>,
+    server: Option<String>,
+    profiles_state: State<'_, ProfilesState>,
+    loaded: State<'_, LoadedProfiles>,
+    conn_state: State<'_, ConnectionState>,
+    online_state: State<'_, OnlineServers>,
+    history_state: State<'_, HistoryState>
+) -> Result<BoopReceipt, BoopError> {
+    let (server, profile) = target_profile(server, &profiles_state, &loaded).await?;
+
+    let content = content.unwrap_or_default();
+    content.check().map_err(BoopError::InvalidBoop)?;
+
+    // a stuck key shouldn't flood the partner (or get us kicked by the relay)
+    let (burst, refill) = boop_limit(&profile).await;
+    profile
+        .limits
+        .0
+        .lock()
+        .await
+        .try_take(&partner_key, Utc::now().timestamp_millis(), burst, refill)
+        .map_err(BoopError::RateLimited)?;
+
+    let id = new_boop_id();
+    {
+        // the outbox stays locked, so it can't be flushed before the boop is in it
+        let mut outbox = profile.outbox.lock().await;
+        if !online_state.0.lock().await.contains(&server) {
+            info!("{} isn't connected, queued boop to {}", server, partner_key);
+            outbox.push(QueuedBoop::new(
+                id.clone(),
+                partner_key,
+                content,
+                Utc::now().timestamp_millis()
+            ));
+            save_file(&profile.files.outbox, &*outbox).await?;
+            return Ok(BoopReceipt {
+                id,
+                status: BoopDeliveryStatus::Queued
+            });
         }
-    } else {
-        warn!("client tried to boop without an active server connection");
     }
 
-    Ok(())
+    // every partner is reached through the server of their profile
+    let res = conn_state
+        .0
+        .send(
+            &server,
+            MessageType::BOOP(partner_key.clone(), Some(id.clone()), content)
+        )
+        .await;
+    if let Err(err) = &res {
+        warn!("failed to boop {} on {}: {}", partner_key, server, err);
+    }
+
+    let status = match res {
+        Ok(_) => DeliveryStatus::Queued,
+        Err(_) => DeliveryStatus::Failed
+    };
+    record_boop(
+        &history_state.0,
+        &server,
+        Some(id.clone()),
+        partner_key,
+        BoopDirection::Sent,
+        status
+    )
+    .await;
+    res.map(|_| BoopReceipt {
+        id,
+        status: BoopDeliveryStatus::Queued
+    })
+}
+
+/// Boops waiting for the server to be connected, oldest first
+#[tauri::command]
+async fn get_outbox<'a>(
+    server: Option<String>,
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>
+) -> Result<Vec<QueuedBoop>, BoopError> {
+    let (_, profile) = target_profile(server, &profiles_state, &loaded).await?;
+    let outbox = profile.outbox.lock().await;
+    Ok(outbox.boops().clone())
+}
+
+/// Takes a boop out of the outbox, false if it isn't queued (anymore)
+#[tauri::command]
+async fn cancel_queued_boop<'a>(
+    id: String,
+    server: Option<String>,
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>
+) -> Result<bool, BoopError> {
+    let (_, profile) = target_profile(server, &profiles_state, &loaded).await?;
+    let mut outbox = profile.outbox.lock().await;
+    if outbox.cancel(&id).is_none() {
+        return Ok(false);
+    }
+
+    save_file(&profile.files.outbox, &*outbox).await?;
+    Ok(true)
+}
+
+/// Round trip times of the server connection, `None` until the connection
+/// reported them
+#[tauri::command]
+async fn get_connection_stats<'a>(
+    server: Option<String>,
+    profiles_state: State<'a, ProfilesState>,
+    loaded: State<'a, LoadedProfiles>,
+    quality_state: State<'a, ConnectionQuality>
+) -> Result<Option<ConnectionStats>, BoopError> {
+    let (id, _) = target_profile(server, &profiles_state, &loaded).await?;
+    Ok(quality_state.0.lock().await.get(&id).cloned())
+}
+
+/// Reports files that were damaged at startup (only once, so the user isn't
+/// told again after every reconnect)
+#[tauri::command]
+async fn take_file_recoveries(
+    state: State<'_, FileRecoveries>
+) -> Result<Vec<FrontendErrorMessage>, BoopError> {
+    let mut recoveries = state.0.lock().await;
+    Ok(recoveries
+        .drain(..)
+        .map(|recovery| FrontendErrorMessage {
+            message: recovery.message()
+        })
+        .collect())
 }
 
 #[tauri::command]
@@ -470,10 +1080,223 @@ async fn show_main_window(window: tauri::Window) {
     window.get_window("main").unwrap().show().unwrap();
 }
 
-pub fn send_partners_update_event(window: &Window, user_key: &String, status: PartnerOnlineStatus) {
+/// Sends the boops that were queued while the server wasn't connected, oldest
+/// first. Boops that waited longer than the profile's expiry are dropped.
+async fn flush_outbox(window: &Window, server: &str) {
+    let profile = match window.state::<LoadedProfiles>().get(server).await {
+        Ok(profile) => profile,
+        Err(err) => {
+            warn!("couldn't send the outbox of {}: {}", server, err);
+            return;
+        }
+    };
+    let expiry = profile.config.lock().await.outbox_expiry();
+    let history_state = window.state::<HistoryState>();
+    let conn_state = window.state::<ConnectionState>();
+
+    let mut outbox = profile.outbox.lock().await;
+    if !outbox.is_empty() {
+        let expired = outbox.remove_expired(Utc::now().timestamp_millis(), expiry);
+        for boop in expired {
+            info!(
+                "dropped boop to {}, it waited longer than {} minutes",
+                boop.partner_key(),
+                expiry
+            );
+            record_boop(
+                &history_state.0,
+                server,
+                Some(boop.id()),
+                boop.partner_key(),
+                BoopDirection::Sent,
+                DeliveryStatus::Failed
+            )
+            .await;
+            send_boop_delivery_to_frontend(window, server, boop.id(), BoopDeliveryStatus::Expired);
+        }
+
+        let mut queued = outbox.take_all().into_iter();
+        while let Some(boop) = queued.next() {
+            let message = MessageType::BOOP(boop.partner_key(), Some(boop.id()), boop.content());
+            if let Err(err) = conn_state.0.send(server, message).await {
+                // the connection is gone again, the rest waits for the next one
+                warn!("failed to send the outbox of {}: {}", server, err);
+                outbox.push(boop);
+                queued.for_each(|boop| outbox.push(boop));
+                break;
+            }
+
+            record_boop(
+                &history_state.0,
+                server,
+                Some(boop.id()),
+                boop.partner_key(),
+                BoopDirection::Sent,
+                DeliveryStatus::Queued
+            )
+            .await;
+        }
+
+        if let Err(err) = save_file(&profile.files.outbox, &*outbox).await {
+            error!("failed to save the outbox of {}: {}", server, err);
+        }
+        if !outbox.is_empty() {
+            return;
+        }
+    }
+
+    window
+        .state::<OnlineServers>()
+        .0
+        .lock()
+        .await
+        .insert(String::from(server));
+}
+
+/// Burst and refill of the profile's boop limit
+async fn boop_limit(profile: &ProfileHandles) -> (u32, u32) {
+    let config = profile.config.lock().await;
+    (config.boop_burst(), config.boop_refill())
+}
+
+/// Shows a boop, unless the partner floods us: boops over the limit are
+/// counted and shown as one once the limit allows the next boop
+async fn show_boop(window: &Window, server: &str, partner_key: String, content: BoopContent) {
+    let profile = match window.state::<LoadedProfiles>().get(server).await {
+        Ok(profile) => profile,
+        Err(_) => return send_boop_to_frontend(window, server, partner_key, content, 1)
+    };
+
+    let now = Utc::now().timestamp_millis();
+    let incoming = profile
+        .limits
+        .1
+        .lock()
+        .await
+        .receive(&partner_key, content, now);
+    let mut wait = match incoming {
+        Incoming::Show(content) => {
+            return send_boop_to_frontend(window, server, partner_key, content, 1)
+        }
+        Incoming::Collapse(wait) => wait,
+        Incoming::Counted => return // the flood is shown already
+    };
+
+    let window = window.clone();
+    let server = String::from(server);
+    let floods = profile.limits.1;
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_millis(wait as u64)).await;
+            let now = Utc::now().timestamp_millis();
+            let summary = floods.lock().await.summary(&partner_key, now);
+            match summary {
+                Ok(Some((count, content))) => {
+                    return send_boop_to_frontend(&window, &server, partner_key, content, count)
+                }
+                Ok(None) => return,
+                Err(later) => wait = later
+            }
+        }
+    });
+}
+
+/// Relays the events of the server connections to the frontend
+async fn forward_connection_events(mut event_rx: ServerEventRx, window: Window) {
+    while let Some((server, event)) = event_rx.recv().await {
+        match event {
+            ConnectionEvent::StatusChanged(status) => {
+                if status == ServerConnectionStatus::Connected {
+                    flush_outbox(&window, &server).await;
+                } else {
+                    window
+                        .state::<OnlineServers>()
+                        .0
+                        .lock()
+                        .await
+                        .remove(&server);
+                    window
+                        .state::<ConnectionQuality>()
+                        .0
+                        .lock()
+                        .await
+                        .remove(&server);
+                }
+                send_connection_status(&window, &server, status)
+            }
+            ConnectionEvent::Booped(partner_key, content) => {
+                record_boop(
+                    &window.state::<HistoryState>().0,
+                    &server,
+                    None,
+                    partner_key.clone(),
+                    BoopDirection::Received,
+                    DeliveryStatus::Received
+                )
+                .await;
+                show_boop(&window, &server, partner_key, content).await
+            }
+            ConnectionEvent::BoopRequested(user_key) => {
+                // boops by non-partners end up in the profile's requests inbox instead
+                let profile = match window.state::<LoadedProfiles>().get(&server).await {
+                    Ok(profile) => profile,
+                    Err(err) => {
+                        warn!("dropped boop request by {}: {}", user_key, err);
+                        continue;
+                    }
+                };
+                match record_boop_request(&profile.requests, &profile.files.requests, &user_key)
+                    .await
+                {
+                    Ok(true) => send_boop_request_to_frontend(&window, &server, user_key),
+                    Ok(false) => {}
+                    Err(err) => error!("failed to save boop request to disk: {}", err)
+                }
+            }
+            ConnectionEvent::BoopDelivery(id, status) => {
+                record_delivery(&window.state::<HistoryState>().0, &id, status).await;
+                send_boop_delivery_to_frontend(&window, &server, id, status)
+            }
+            ConnectionEvent::PartnerStatusChanged(user_key, status) => {
+                send_partners_update_event(&window, &server, &user_key, status)
+            }
+            ConnectionEvent::QualityReport(stats) => {
+                window
+                    .state::<ConnectionQuality>()
+                    .0
+                    .lock()
+                    .await
+                    .insert(server.clone(), stats.clone());
+                send_connection_quality_to_frontend(&window, &server, stats)
+            }
+            ConnectionEvent::CertificatePinned(_) => {
+                // the connection already put the fingerprint into the config
+                let profile = match window.state::<LoadedProfiles>().get(&server).await {
+                    Ok(profile) => profile,
+                    Err(err) => {
+                        error!("failed to save pinned server certificate: {}", err);
+                        continue;
+                    }
+                };
+                let config = profile.config.lock().await.clone();
+                if let Err(err) = save_file(&profile.files.config, &config).await {
+                    error!("failed to save pinned server certificate to disk: {}", err);
+                }
+            }
+        }
+    }
+}
+
+pub fn send_partners_update_event(
+    window: &Window,
+    server: &str,
+    user_key: &str,
+    status: PartnerOnlineStatus
+) {
     debug!("sending partners-update event to frontend");
     if let Err(err) = window.emit_all("partner-status-changed", PartnerUpdatePayload {
-        user_key: user_key.clone(),
+        server:   String::from(server),
+        user_key: String::from(user_key),
         online:   status as i8
     }) {
         error!("failed to send partners update to frontend: {}", err);
@@ -482,26 +1305,101 @@ pub fn send_partners_update_event(window: &Window, user_key: &String, status: Pa
 
 /// Transforms the partners state to an event payload intended for frontend
 /// partner updates
-fn get_partners_payload(
-    partners: &HashMap<String, (BoopPartner, PartnerOnlineStatus)>
-) -> Vec<FrontendPartnerObject> {
+fn get_partners_payload(server: &str, partners: &PartnersMap) -> Vec<FrontendPartnerObject> {
     let mut vec = Vec::new();
 
-    for (_, (partner, status)) in partners {
+    for (partner, status) in partners.values() {
         vec.push(FrontendPartnerObject {
             nickname: partner.nickname(),
             user_key: partner.user_key(),
-            online:   *status as i8
+            online:   *status as i8,
+            server:   String::from(server)
         })
     }
 
     vec
 }
 
+/// Subscribes to added partners and unsubscribes from removed ones, the
+/// partner change itself is saved either way
+async fn update_subscriptions(
+    conn_state: &ConnectionState,
+    server: &str,
+    added: Vec<String>,
+    removed: Vec<String>
+) {
+    if let Err(err) = conn_state
+        .0
+        .update_subscriptions(server, added, removed)
+        .await
+    {
+        warn!(
+            "failed to update the presence subscriptions on {}: {}",
+            server, err
+        );
+    }
+}
+
+/// The profile (and server) a command is meant for, the active one if the
+/// frontend didn't name one
+async fn target_profile(
+    server: Option<String>,
+    profiles_state: &ProfilesState,
+    loaded: &LoadedProfiles
+) -> Result<(String, ProfileHandles), BoopError> {
+    let id = match server {
+        Some(id) => id,
+        None => profiles_state.0.lock().await.active()
+    };
+
+    let profile = loaded.get(&id).await?;
+    Ok((id, profile))
+}
+
+/// Reads the config, partners, boop requests and outbox of a profile
+async fn load_profile(id: &str, recoveries: &mut Vec<FileRecovery>) -> ProfileHandles {
+    let files = ProfilePaths::new(id);
+    let keys = KeySource::new(get_config_file_path(KEY_FILENAME));
+
+    let (config, recovery): (BoopConfig, _) = get_object_or_backup(&files.config);
+    recoveries.extend(recovery);
+
+    // get saved partners and build hashmap
+    let (partners, recovery): (Vec<BoopPartner>, _) = get_object_or_backup(&files.partners);
+    recoveries.extend(recovery);
+
+    // get boop requests by non-partners and blocked keys
+    let (requests, recovery): (RequestsInbox, _) = get_object_or_backup(&files.requests);
+    recoveries.extend(recovery);
+
+    // boops that were made while the server wasn't connected
+    let (outbox, recovery): (Outbox, _) = get_object_or_backup(&files.outbox);
+    recoveries.extend(recovery);
+
+    ProfileHandles {
+        config: Arc::new(Mutex::new(config)),
+        keys: Arc::new(Mutex::new(keys)),
+        partners: Arc::new(Mutex::new(build_partners_map(partners))),
+        requests: Arc::new(Mutex::new(requests)),
+        outbox: Arc::new(Mutex::new(outbox)),
+        limits: Default::default(),
+        files
+    }
+}
+
+async fn save_profiles(profiles: &Profiles) -> Result<(), BoopError> {
+    if let Err(err) = save_file(&get_config_file_path(PROFILES_FILENAME), profiles).await {
+        error!("failed to save profiles to disk: {}", err);
+        return Err(BoopError::from(err));
+    }
+
+    Ok(())
+}
+
 async fn save_partners_changes(
-    partners: &HashMap<String, (BoopPartner, PartnerOnlineStatus)>,
+    partners: &PartnersMap,
     partners_file: &PathBuf
-) -> Result<(), ()> {
+) -> Result<(), BoopError> {
     let partner_config: Vec<BoopPartner> = partners
         .iter()
         .map(|(_, (partner_object, _))| partner_object.clone())
@@ -509,7 +1407,27 @@ async fn save_partners_changes(
 
     if let Err(err) = save_file(partners_file, &partner_config).await {
         error!("failed to save changed partners config to disk: {}", err);
-        return Err(());
+        return Err(BoopError::from(err));
+    }
+
+    Ok(())
+}
+
+/// Applies `change` to the requests inbox and saves it, the change is rolled
+/// back if the disk write fails
+async fn change_requests(
+    requests_handle: &RequestsHandle,
+    requests_file: &PathBuf,
+    change: impl FnOnce(&mut RequestsInbox)
+) -> Result<(), BoopError> {
+    let mut inbox = requests_handle.lock().await;
+    let previous = inbox.clone();
+    change(&mut inbox);
+
+    if let Err(err) = save_file(requests_file, &*inbox).await {
+        error!("failed to save changed boop requests to disk: {}", err);
+        *inbox = previous;
+        return Err(BoopError::from(err));
     }
 
     Ok(())
@@ -523,20 +1441,87 @@ pub fn send_error_to_frontend(window: &Window, error_message: FrontendErrorMessa
     }
 }
 
-pub fn send_boop_to_frontend(window: &Window, partner_key: String) {
-    debug!("transmitting boop by {} to frontend", partner_key);
-    let emit_res = window.emit_all("booped", BoopPayload { partner_key });
+pub fn send_boop_to_frontend(
+    window: &Window,
+    server: &str,
+    partner_key: String,
+    content: BoopContent,
+    count: u32
+) {
+    debug!(
+        "transmitting {} boop(s) by {} to frontend",
+        count, partner_key
+    );
+    let emit_res = window.emit_all("booped", BoopPayload {
+        server: String::from(server),
+        partner_key,
+        content,
+        count
+    });
     if let Err(send_err) = emit_res {
         error!("failed to send boop to frontend: {}\n", send_err);
     }
 }
 
-pub fn send_connection_status(window: &Window, conn_status: ServerConnectionStatus) {
-    let status = conn_status as i8;
-    debug!("sending connection status change {} to frontend", status);
-    let emit_res = window.emit_all("connection-state-changed", ConnectionStatusPayload {
+pub fn send_boop_delivery_to_frontend(
+    window: &Window,
+    server: &str,
+    id: String,
+    status: BoopDeliveryStatus
+) {
+    debug!(
+        "transmitting delivery status {:?} of boop {} to frontend",
+        status, id
+    );
+    let emit_res = window.emit_all("boop-delivery", BoopDeliveryPayload {
+        server: String::from(server),
+        id,
         status
     });
+    if let Err(send_err) = emit_res {
+        error!("failed to send boop delivery to frontend: {}\n", send_err);
+    }
+}
+
+pub fn send_connection_quality_to_frontend(window: &Window, server: &str, stats: ConnectionStats) {
+    let emit_res = window.emit_all("connection-quality", ConnectionQualityPayload {
+        server: String::from(server),
+        stats
+    });
+    if let Err(send_err) = emit_res {
+        error!(
+            "failed to send connection quality to frontend: {}\n",
+            send_err
+        );
+    }
+}
+
+pub fn send_boop_request_to_frontend(window: &Window, server: &str, user_key: String) {
+    debug!("transmitting boop request by {} to frontend", user_key);
+    let emit_res = window.emit_all("boop-requested", BoopRequestPayload {
+        server: String::from(server),
+        user_key
+    });
+    if let Err(send_err) = emit_res {
+        error!("failed to send boop request to frontend: {}\n", send_err);
+    }
+}
+
+pub fn send_connection_status(window: &Window, server: &str, conn_status: ServerConnectionStatus) {
+    let status = conn_status.code();
+    debug!(
+        "sending connection status change {} of {} to frontend",
+        status, server
+    );
+    let reconnect = match conn_status {
+        ServerConnectionStatus::Reconnecting(info) => Some(info),
+        _ => None
+    };
+    let emit_res = window.emit_all("connection-state-changed", ConnectionStatusPayload {
+        server: String::from(server),
+        status,
+        reconnect
+    });
     if let Err(send_err) = emit_res {
         error!(
             "failed to send connection status change to frontend: {}\n",
@@ -1,13 +1,62 @@
-use std::fmt::Display;
+use {
+    rand::Rng,
+    serde::{
+        Deserialize,
+        Serialize
+    },
+    std::fmt::Display
+};
 
-#[derive(Debug, PartialEq)]
+// Arguments are separated by spaces. Arguments that are empty or contain
+// whitespace, control characters, quotes or backslashes are sent as quoted
+// strings with backslash escapes (\\, \", \n, \r, \t), e.g.
+// `CONNECT foo "my \"secret\" password"`. Everything else is sent as is, so
+// simple keys look exactly like they did before quoting was introduced.
+//
+// Boops can carry optional fields as name / value pairs after the partner key,
+// e.g. `BOOP alice emoji 🐶 note "come over" kind urgent`. Relays without the
+// boop-payload capability only ever see `BOOP alice`. Unknown fields are
+// ignored, so newer clients can add more of them.
+//
+// Relays with the ack capability get an `id` field with every boop and answer
+// with `DELIVERED <id>` once the partner got it, or `FAILED <id> <error>` if
+// it couldn't be delivered (e.g. `FAILED 3f2a NOT_AVAILABLE`).
+
+/// Version of the line protocol spoken by this client. Relays that don't
+/// understand HELLO are treated as version 1.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Optional protocol features this client implements and announces in its
+/// HELLO
+pub const CLIENT_CAPABILITIES: &[Capability] = &[
+    Capability::ChallengeLogin,
+    Capability::PresencePush,
+    Capability::PresenceBatch,
+    Capability::BoopPayload,
+    Capability::Ack
+];
+
+/// Longest note a boop can carry, in characters
+pub const BOOP_NOTE_MAX_CHARS: usize = 140;
+/// Longest emoji a boop can carry, in characters (emoji with skin tones or
+/// joined emoji consist of several)
+pub const BOOP_EMOJI_MAX_CHARS: usize = 16;
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum MessageType {
     // usually requests
-    CONNECT(String, String), // key, password
+    HELLO(u32, Vec<String>),         // protocol version, capabilities
+    CONNECT(String, Option<String>), // key, password (none for certificate logins)
+    AUTH(String, String),            // key, client nonce (challenge login)
+    PROOF(String),                   // client proof (challenge login)
     DISCONNECT,
     PING,
-    BOOP(String), // partner_key
-    AYT(String),  // partner_key
+    // partner_key, boop id (ack), optional fields (boop payload)
+    BOOP(String, Option<String>, BoopContent),
+    AYT(String),              // partner_key
+    SUBSCRIBE(Vec<String>),   // partner_keys, their status changes are pushed (presence push)
+    UNSUBSCRIBE(Vec<String>), // partner_keys
+    STATUS(Vec<String>),      // partner_keys, answered with one PRESENCE (presence batch)
 
     // usually responses
     HEY,
@@ -15,11 +64,34 @@ pub enum MessageType {
     BYE,
     PONG,
     ERROR(MessageErrorKind),
+    CHALLENGE(String, String, u32),   // nonce, salt, iterations
+    DELIVERED(String),                // boop id (ack)
+    FAILED(String, MessageErrorKind), // boop id, why it wasn't delivered (ack)
     ONLINE(String),
-    AFK(String)
+    AFK(String),
+    PRESENCE(Vec<(String, bool)>) // (partner_key, online) for every key of a STATUS
 }
 
-#[derive(Debug, PartialEq)]
+/// How a boop is meant
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BoopKind {
+    Plain,
+    Gentle,
+    Urgent,
+    ComeHere
+}
+
+/// The optional fields of a boop, a boop without any is sent as plain BOOP
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BoopContent {
+    emoji: Option<String>,
+    note:  Option<String>,
+    kind:  BoopKind
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum MessageErrorKind {
     NotAvailable,
     MalformedCommand,
@@ -27,10 +99,121 @@ pub enum MessageErrorKind {
     ProtocolMismatch
 }
 
+/// Optional protocol features that are negotiated with HELLO
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Capability {
+    BoopPayload,
+    PresencePush,
+    PresenceBatch,
+    Ack,
+    ChallengeLogin
+}
+
+impl Capability {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::BoopPayload => "boop-payload",
+            Capability::PresencePush => "presence-push",
+            Capability::PresenceBatch => "presence-batch",
+            Capability::Ack => "ack",
+            Capability::ChallengeLogin => "challenge-login"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Capability> {
+        match name {
+            "boop-payload" => Some(Capability::BoopPayload),
+            "presence-push" => Some(Capability::PresencePush),
+            "presence-batch" => Some(Capability::PresenceBatch),
+            "ack" => Some(Capability::Ack),
+            "challenge-login" => Some(Capability::ChallengeLogin),
+            _ => None
+        }
+    }
+}
+
+impl Default for BoopKind {
+    fn default() -> Self {
+        BoopKind::Plain
+    }
+}
+
+impl BoopKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BoopKind::Plain => "plain",
+            BoopKind::Gentle => "gentle",
+            BoopKind::Urgent => "urgent",
+            BoopKind::ComeHere => "come-here"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<BoopKind> {
+        match name {
+            "plain" => Some(BoopKind::Plain),
+            "gentle" => Some(BoopKind::Gentle),
+            "urgent" => Some(BoopKind::Urgent),
+            "come-here" => Some(BoopKind::ComeHere),
+            _ => None
+        }
+    }
+}
+
+impl BoopContent {
+    pub fn new(emoji: Option<String>, note: Option<String>, kind: BoopKind) -> Self {
+        BoopContent { emoji, note, kind }
+    }
+
+    pub fn emoji(&self) -> Option<String> {
+        self.emoji.clone()
+    }
+
+    pub fn note(&self) -> Option<String> {
+        self.note.clone()
+    }
+
+    pub fn kind(&self) -> BoopKind {
+        self.kind
+    }
+
+    pub fn is_plain(&self) -> bool {
+        *self == BoopContent::default()
+    }
+
+    /// Checks the length limits of emoji and note
+    pub fn check(&self) -> Result<(), String> {
+        if let Some(emoji) = &self.emoji {
+            if emoji.is_empty() || emoji.chars().count() > BOOP_EMOJI_MAX_CHARS {
+                return Err(format!(
+                    "the emoji has to be 1 to {} characters long",
+                    BOOP_EMOJI_MAX_CHARS
+                ));
+            }
+        }
+
+        if let Some(note) = &self.note {
+            if note.chars().count() > BOOP_NOTE_MAX_CHARS {
+                return Err(format!(
+                    "the note can't be longer than {} characters",
+                    BOOP_NOTE_MAX_CHARS
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Random id for an outgoing boop, the server refers to it in its acks
+pub fn new_boop_id() -> String {
+    format!("{:016x}", rand::thread_rng().gen::<u64>())
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ParserError {
     UnknownMessageType,
-    UnknownArguments
+    UnknownArguments,
+    MalformedQuoting
 }
 
 impl Display for ParserError {
@@ -40,23 +223,50 @@ impl Display for ParserError {
             ParserError::UnknownArguments => write!(
                 f,
                 "The provided arguments are not appropriate for the selected message type."
+            ),
+            ParserError::MalformedQuoting => write!(
+                f,
+                "An argument has an unterminated quote or an invalid escape sequence."
             )
         }
     }
 }
 
-impl Into<MessageErrorKind> for ParserError {
-    fn into(self) -> MessageErrorKind {
-        match self {
+impl From<ParserError> for MessageErrorKind {
+    fn from(err: ParserError) -> Self {
+        match err {
             ParserError::UnknownMessageType => MessageErrorKind::MalformedCommand,
-            ParserError::UnknownArguments => MessageErrorKind::MalformedArguments
+            ParserError::UnknownArguments => MessageErrorKind::MalformedArguments,
+            ParserError::MalformedQuoting => MessageErrorKind::MalformedArguments
         }
     }
 }
 
-fn connect(args: &Vec<&str>) -> Result<MessageType, ParserError> {
-    if args.len() == 2 {
+fn hello(args: &[&str]) -> Result<MessageType, ParserError> {
+    if let Ok(version) = args[0].parse::<u32>() {
+        let capabilities = args[1..].iter().map(|cap| String::from(*cap)).collect();
+        Ok(MessageType::HELLO(version, capabilities))
+    } else {
+        Err(ParserError::UnknownArguments)
+    }
+}
+
+fn connect(args: &[&str]) -> Result<MessageType, ParserError> {
+    if args.len() == 1 {
+        Ok(MessageType::CONNECT(String::from(args[0]), None))
+    } else if args.len() == 2 {
         Ok(MessageType::CONNECT(
+            String::from(args[0]),
+            Some(String::from(args[1]))
+        ))
+    } else {
+        Err(ParserError::UnknownArguments)
+    }
+}
+
+fn auth(args: &[&str]) -> Result<MessageType, ParserError> {
+    if args.len() == 2 {
+        Ok(MessageType::AUTH(
             String::from(args[0]),
             String::from(args[1])
         ))
@@ -65,15 +275,65 @@ fn connect(args: &Vec<&str>) -> Result<MessageType, ParserError> {
     }
 }
 
-fn boop(args: &Vec<&str>) -> Result<MessageType, ParserError> {
+fn proof(args: &[&str]) -> Result<MessageType, ParserError> {
     if args.len() == 1 {
-        Ok(MessageType::BOOP(String::from(args[0])))
+        Ok(MessageType::PROOF(String::from(args[0])))
     } else {
         Err(ParserError::UnknownArguments)
     }
 }
 
-fn ayt(args: &Vec<&str>) -> Result<MessageType, ParserError> {
+fn challenge(args: &[&str]) -> Result<MessageType, ParserError> {
+    if args.len() != 3 {
+        return Err(ParserError::UnknownArguments);
+    }
+
+    if let Ok(iterations) = args[2].parse::<u32>() {
+        Ok(MessageType::CHALLENGE(
+            String::from(args[0]),
+            String::from(args[1]),
+            iterations
+        ))
+    } else {
+        Err(ParserError::UnknownArguments)
+    }
+}
+
+fn boop(args: &[&str]) -> Result<MessageType, ParserError> {
+    // partner key, then name / value pairs
+    if args.len() % 2 != 1 {
+        return Err(ParserError::UnknownArguments);
+    }
+
+    let mut id = None;
+    let mut content = BoopContent::default();
+    let mut seen = Vec::new();
+    for field in args[1..].chunks(2) {
+        let (name, value) = (field[0].to_ascii_lowercase(), field[1]);
+        if seen.contains(&name) {
+            return Err(ParserError::UnknownArguments);
+        }
+
+        match name.as_str() {
+            "id" => id = Some(String::from(value)),
+            "emoji" => content.emoji = Some(String::from(value)),
+            "note" => content.note = Some(String::from(value)),
+            "kind" => {
+                content.kind = BoopKind::from_name(value).ok_or(ParserError::UnknownArguments)?
+            }
+            _ => {} // from a newer version
+        }
+        seen.push(name);
+    }
+
+    if content.check().is_err() {
+        return Err(ParserError::UnknownArguments);
+    }
+
+    Ok(MessageType::BOOP(String::from(args[0]), id, content))
+}
+
+fn ayt(args: &[&str]) -> Result<MessageType, ParserError> {
     if args.len() == 1 {
         Ok(MessageType::AYT(String::from(args[0])))
     } else {
@@ -81,7 +341,41 @@ fn ayt(args: &Vec<&str>) -> Result<MessageType, ParserError> {
     }
 }
 
-fn online(args: &Vec<&str>) -> Result<MessageType, ParserError> {
+fn subscribe(args: &[&str]) -> Result<MessageType, ParserError> {
+    Ok(MessageType::SUBSCRIBE(
+        args.iter().map(|key| String::from(*key)).collect()
+    ))
+}
+
+fn unsubscribe(args: &[&str]) -> Result<MessageType, ParserError> {
+    Ok(MessageType::UNSUBSCRIBE(
+        args.iter().map(|key| String::from(*key)).collect()
+    ))
+}
+
+fn status(args: &[&str]) -> Result<MessageType, ParserError> {
+    Ok(MessageType::STATUS(
+        args.iter().map(|key| String::from(*key)).collect()
+    ))
+}
+
+/// `PRESENCE alice ONLINE bob AFK`
+fn presence(args: &[&str]) -> Result<MessageType, ParserError> {
+    if args.len() % 2 != 0 {
+        return Err(ParserError::UnknownArguments);
+    }
+
+    args.chunks(2)
+        .map(|pair| match pair[1].to_ascii_uppercase().as_str() {
+            "ONLINE" => Ok((String::from(pair[0]), true)),
+            "AFK" => Ok((String::from(pair[0]), false)),
+            _ => Err(ParserError::UnknownArguments)
+        })
+        .collect::<Result<Vec<(String, bool)>, ParserError>>()
+        .map(MessageType::PRESENCE)
+}
+
+fn online(args: &[&str]) -> Result<MessageType, ParserError> {
     if args.len() == 1 {
         Ok(MessageType::ONLINE(String::from(args[0])))
     } else {
@@ -89,7 +383,7 @@ fn online(args: &Vec<&str>) -> Result<MessageType, ParserError> {
     }
 }
 
-fn afk(args: &Vec<&str>) -> Result<MessageType, ParserError> {
+fn afk(args: &[&str]) -> Result<MessageType, ParserError> {
     if args.len() == 1 {
         Ok(MessageType::AFK(String::from(args[0])))
     } else {
@@ -97,22 +391,45 @@ fn afk(args: &Vec<&str>) -> Result<MessageType, ParserError> {
     }
 }
 
-fn error(args: &Vec<&str>) -> Result<MessageType, ParserError> {
+fn delivered(args: &[&str]) -> Result<MessageType, ParserError> {
     if args.len() == 1 {
-        match args[0] {
-            "NOT_AVAILABLE" => Ok(MessageType::ERROR(MessageErrorKind::NotAvailable)),
-            "MALFORMED_COMMAND" => Ok(MessageType::ERROR(MessageErrorKind::MalformedCommand)),
-            "MALFORMED_ARGUMENTS" => Ok(MessageType::ERROR(MessageErrorKind::MalformedArguments)),
-            "PROTOCOL_MISMATCH" => Ok(MessageType::ERROR(MessageErrorKind::ProtocolMismatch)),
-            _ => Err(ParserError::UnknownArguments)
-        }
+        Ok(MessageType::DELIVERED(String::from(args[0])))
     } else {
         Err(ParserError::UnknownArguments)
     }
 }
 
+fn failed(args: &[&str]) -> Result<MessageType, ParserError> {
+    if args.len() == 2 {
+        Ok(MessageType::FAILED(
+            String::from(args[0]),
+            error_kind(args[1])?
+        ))
+    } else {
+        Err(ParserError::UnknownArguments)
+    }
+}
+
+fn error(args: &[&str]) -> Result<MessageType, ParserError> {
+    if args.len() == 1 {
+        Ok(MessageType::ERROR(error_kind(args[0])?))
+    } else {
+        Err(ParserError::UnknownArguments)
+    }
+}
+
+fn error_kind(text: &str) -> Result<MessageErrorKind, ParserError> {
+    match text {
+        "NOT_AVAILABLE" => Ok(MessageErrorKind::NotAvailable),
+        "MALFORMED_COMMAND" => Ok(MessageErrorKind::MalformedCommand),
+        "MALFORMED_ARGUMENTS" => Ok(MessageErrorKind::MalformedArguments),
+        "PROTOCOL_MISMATCH" => Ok(MessageErrorKind::ProtocolMismatch),
+        _ => Err(ParserError::UnknownArguments)
+    }
+}
+
 fn get_message_type_from_text(cmd: &str, args: Vec<&str>) -> Result<MessageType, ParserError> {
-    if args.len() == 0 {
+    if args.is_empty() {
         match cmd.to_ascii_uppercase().as_str() {
             "DISCONNECT" => Ok(MessageType::DISCONNECT),
             "PING" => Ok(MessageType::PING),
@@ -122,9 +439,19 @@ fn get_message_type_from_text(cmd: &str, args: Vec<&str>) -> Result<MessageType,
             "BYE" => Ok(MessageType::BYE),
 
             // catch errors
+            "HELLO" => Err(ParserError::UnknownArguments),
             "CONNECT" => Err(ParserError::UnknownArguments),
+            "AUTH" => Err(ParserError::UnknownArguments),
+            "PROOF" => Err(ParserError::UnknownArguments),
+            "CHALLENGE" => Err(ParserError::UnknownArguments),
             "BOOP" => Err(ParserError::UnknownArguments),
             "AYT" => Err(ParserError::UnknownArguments),
+            "SUBSCRIBE" => Err(ParserError::UnknownArguments),
+            "UNSUBSCRIBE" => Err(ParserError::UnknownArguments),
+            "STATUS" => Err(ParserError::UnknownArguments),
+            "PRESENCE" => Err(ParserError::UnknownArguments),
+            "DELIVERED" => Err(ParserError::UnknownArguments),
+            "FAILED" => Err(ParserError::UnknownArguments),
             "ERROR" => Err(ParserError::UnknownArguments),
             "ONLINE" => Err(ParserError::UnknownArguments),
             "AFK" => Err(ParserError::UnknownArguments),
@@ -132,9 +459,19 @@ fn get_message_type_from_text(cmd: &str, args: Vec<&str>) -> Result<MessageType,
         }
     } else {
         match cmd.to_ascii_uppercase().as_str() {
+            "HELLO" => hello(&args),
             "CONNECT" => connect(&args),
+            "AUTH" => auth(&args),
+            "PROOF" => proof(&args),
+            "CHALLENGE" => challenge(&args),
             "BOOP" => boop(&args),
             "AYT" => ayt(&args),
+            "SUBSCRIBE" => subscribe(&args),
+            "UNSUBSCRIBE" => unsubscribe(&args),
+            "STATUS" => status(&args),
+            "PRESENCE" => presence(&args),
+            "DELIVERED" => delivered(&args),
+            "FAILED" => failed(&args),
             "ERROR" => error(&args),
             "ONLINE" => online(&args),
             "AFK" => afk(&args),
@@ -151,31 +488,184 @@ fn get_message_type_from_text(cmd: &str, args: Vec<&str>) -> Result<MessageType,
     }
 }
 
-pub fn parse_message(msg: &String) -> Result<MessageType, ParserError> {
-    let mut cmd = msg.clone();
+pub fn parse_message(msg: &str) -> Result<MessageType, ParserError> {
+    let mut cmd = String::from(msg);
     // remove newline if it's still at the end
     if cmd.ends_with("\n") {
         cmd.remove(cmd.len() - 1); // remove newline char
     }
     cmd = String::from(cmd.trim());
-    let mut split: Vec<&str> = cmd.split(" ").collect();
-    get_message_type_from_text(split.remove(0), split)
+    let mut tokens = split_arguments(&cmd)?;
+    if tokens.is_empty() {
+        return Err(ParserError::UnknownMessageType);
+    }
+    let cmd_text = tokens.remove(0);
+    get_message_type_from_text(&cmd_text, tokens.iter().map(|arg| arg.as_str()).collect())
 }
 
 pub fn create_message_text(msg_type: MessageType) -> String {
     match msg_type {
-        MessageType::CONNECT(key, password) => format!("CONNECT {} {}\n", key, password),
+        MessageType::HELLO(version, capabilities) => {
+            format!("HELLO {}{}\n", version, encode_list(&capabilities))
+        }
+        MessageType::CONNECT(key, Some(password)) => format!(
+            "CONNECT {} {}\n",
+            encode_argument(&key),
+            encode_argument(&password)
+        ),
+        MessageType::CONNECT(key, None) => format!("CONNECT {}\n", encode_argument(&key)),
+        MessageType::AUTH(key, client_nonce) => format!(
+            "AUTH {} {}\n",
+            encode_argument(&key),
+            encode_argument(&client_nonce)
+        ),
+        MessageType::PROOF(proof) => format!("PROOF {}\n", encode_argument(&proof)),
+        MessageType::CHALLENGE(nonce, salt, iterations) => format!(
+            "CHALLENGE {} {} {}\n",
+            encode_argument(&nonce),
+            encode_argument(&salt),
+            iterations
+        ),
         MessageType::DISCONNECT => String::from("DISCONNECT\n"),
         MessageType::PING => String::from("PING\n"),
-        MessageType::BOOP(partner_key) => format!("BOOP {}\n", partner_key),
-        MessageType::AYT(partner_key) => format!("AYT {}\n", partner_key),
+        MessageType::BOOP(partner_key, id, content) => {
+            let mut text = format!("BOOP {}", encode_argument(&partner_key));
+            if let Some(id) = id {
+                text.push_str(&format!(" id {}", encode_argument(&id)));
+            }
+            if let Some(emoji) = content.emoji {
+                text.push_str(&format!(" emoji {}", encode_argument(&emoji)));
+            }
+            if let Some(note) = content.note {
+                text.push_str(&format!(" note {}", encode_argument(&note)));
+            }
+            if content.kind != BoopKind::Plain {
+                text.push_str(&format!(" kind {}", content.kind.name()));
+            }
+            text.push('\n');
+            text
+        }
+        MessageType::AYT(partner_key) => format!("AYT {}\n", encode_argument(&partner_key)),
+        MessageType::SUBSCRIBE(partner_keys) => {
+            format!("SUBSCRIBE{}\n", encode_list(&partner_keys))
+        }
+        MessageType::UNSUBSCRIBE(partner_keys) => {
+            format!("UNSUBSCRIBE{}\n", encode_list(&partner_keys))
+        }
         MessageType::HEY => String::from("HEY\n"),
         MessageType::NO => String::from("NO\n"),
         MessageType::BYE => String::from("BYE\n"),
         MessageType::PONG => String::from("PONG\n"),
         MessageType::ERROR(err_kind) => format!("ERROR {}\n", error_text(err_kind)),
-        MessageType::ONLINE(partner_key) => format!("ONLINE {}\n", partner_key),
-        MessageType::AFK(partner_key) => format!("AFK {}\n", partner_key)
+        MessageType::DELIVERED(id) => format!("DELIVERED {}\n", encode_argument(&id)),
+        MessageType::FAILED(id, err_kind) => {
+            format!("FAILED {} {}\n", encode_argument(&id), error_text(err_kind))
+        }
+        MessageType::ONLINE(partner_key) => format!("ONLINE {}\n", encode_argument(&partner_key)),
+        MessageType::AFK(partner_key) => format!("AFK {}\n", encode_argument(&partner_key)),
+        MessageType::STATUS(partner_keys) => format!("STATUS{}\n", encode_list(&partner_keys)),
+        MessageType::PRESENCE(statuses) => {
+            let mut text = String::from("PRESENCE");
+            for (partner_key, online) in statuses {
+                text.push(' ');
+                text.push_str(&encode_argument(&partner_key));
+                text.push_str(if online { " ONLINE" } else { " AFK" });
+            }
+            text.push('\n');
+            text
+        }
+    }
+}
+
+/// Checks whether a character can be part of an unquoted argument
+fn is_bare_char(c: char) -> bool {
+    !c.is_whitespace() && !c.is_control() && c != '"' && c != '\\'
+}
+
+/// Encodes a single argument for the wire, quoting it only if necessary
+fn encode_argument(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(is_bare_char) {
+        return String::from(arg);
+    }
+
+    let mut encoded = String::with_capacity(arg.len() + 2);
+    encoded.push('"');
+    for c in arg.chars() {
+        match c {
+            '"' => encoded.push_str("\\\""),
+            '\\' => encoded.push_str("\\\\"),
+            '\n' => encoded.push_str("\\n"),
+            '\r' => encoded.push_str("\\r"),
+            '\t' => encoded.push_str("\\t"),
+            _ => encoded.push(c)
+        }
+    }
+    encoded.push('"');
+
+    encoded
+}
+
+/// Encodes a variable number of arguments, each with a leading space
+fn encode_list(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| format!(" {}", encode_argument(arg)))
+        .collect()
+}
+
+/// Splits a message line into its (decoded) tokens. Any number of spaces
+/// separates two tokens.
+fn split_arguments(line: &str) -> Result<Vec<String>, ParserError> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        // skip separators
+        while chars.peek() == Some(&' ') {
+            chars.next();
+        }
+
+        let mut token = String::new();
+        match chars.next() {
+            None => return Ok(tokens),
+            Some('"') => {
+                // quoted argument, ends at the next unescaped quote
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('"') => token.push('"'),
+                            Some('\\') => token.push('\\'),
+                            Some('n') => token.push('\n'),
+                            Some('r') => token.push('\r'),
+                            Some('t') => token.push('\t'),
+                            _ => return Err(ParserError::MalformedQuoting)
+                        },
+                        Some(c) => token.push(c),
+                        None => return Err(ParserError::MalformedQuoting)
+                    }
+                }
+
+                // a closing quote has to end the token
+                if chars.peek().map_or(false, |c| *c != ' ') {
+                    return Err(ParserError::MalformedQuoting);
+                }
+            }
+            Some(c) => {
+                token.push(c);
+                while let Some(c) = chars.peek() {
+                    if *c == ' ' {
+                        break;
+                    } else if *c == '"' || *c == '\\' {
+                        // quotes are only allowed around the whole argument
+                        return Err(ParserError::MalformedQuoting);
+                    }
+                    token.push(*c);
+                    chars.next();
+                }
+            }
+        }
+
+        tokens.push(token);
     }
 }
 
@@ -197,10 +687,23 @@ pub fn error_text(err_kind: MessageErrorKind) -> String {
 
 #[cfg(test)]
 mod tests {
-    use crate::message::{
-        parse_message,
-        MessageType,
-        ParserError
+    use {
+        crate::message::{
+            create_message_text,
+            parse_message,
+            BoopContent,
+            BoopKind,
+            MessageErrorKind,
+            MessageType,
+            ParserError,
+            BOOP_EMOJI_MAX_CHARS,
+            BOOP_NOTE_MAX_CHARS
+        },
+        rand::{
+            rngs::StdRng,
+            Rng,
+            SeedableRng
+        }
     };
 
     #[test]
@@ -211,14 +714,101 @@ mod tests {
         assert!(test_res.is_ok());
         assert_eq!(
             test_res.unwrap(),
-            MessageType::CONNECT(String::from("foo"), String::from("bar"))
+            MessageType::CONNECT(String::from("foo"), Some(String::from("bar")))
         );
 
         // one value
         let teststring = String::from("BOOP foo\n");
         let test_res = parse_message(&teststring);
         assert!(test_res.is_ok());
-        assert_eq!(test_res.unwrap(), MessageType::BOOP(String::from("foo")));
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::BOOP(String::from("foo"), None, BoopContent::default())
+        );
+
+        // delivery acks
+        let teststring = String::from("DELIVERED 3f2a\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::DELIVERED(String::from("3f2a"))
+        );
+
+        let teststring = String::from("FAILED 3f2a NOT_AVAILABLE\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::FAILED(String::from("3f2a"), MessageErrorKind::NotAvailable)
+        );
+
+        // variable number of values
+        let teststring = String::from("HELLO 2 ack boop-payload\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::HELLO(2, vec![String::from("ack"), String::from("boop-payload")])
+        );
+
+        let teststring = String::from("SUBSCRIBE alice bob\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::SUBSCRIBE(vec![String::from("alice"), String::from("bob")])
+        );
+
+        let teststring = String::from("UNSUBSCRIBE alice\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::UNSUBSCRIBE(vec![String::from("alice")])
+        );
+
+        let teststring = String::from("PRESENCE alice ONLINE bob afk\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::PRESENCE(vec![
+                (String::from("alice"), true),
+                (String::from("bob"), false)
+            ])
+        );
+
+        let teststring = String::from("HELLO 1\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        assert_eq!(test_res.unwrap(), MessageType::HELLO(1, Vec::new()));
+
+        // optional value left out
+        let teststring = String::from("CONNECT foo\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_ok());
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::CONNECT(String::from("foo"), None)
+        );
+
+        // challenge login
+        let teststring = String::from("AUTH foo c0ffee\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::AUTH(String::from("foo"), String::from("c0ffee"))
+        );
+
+        let teststring = String::from("CHALLENGE c0ffee42 5a17 4096\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::CHALLENGE(String::from("c0ffee42"), String::from("5a17"), 4096)
+        );
+
+        let teststring = String::from("PROOF 0123abcd\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::PROOF(String::from("0123abcd"))
+        );
 
         // no values
         let teststring = String::from("PING\n");
@@ -232,7 +822,7 @@ mod tests {
         assert!(test_res.is_ok());
         assert_eq!(
             test_res.unwrap(),
-            MessageType::CONNECT(String::from("foo"), String::from("bar"))
+            MessageType::CONNECT(String::from("foo"), Some(String::from("bar")))
         );
 
         // no newline char
@@ -241,7 +831,7 @@ mod tests {
         assert!(test_res.is_ok());
         assert_eq!(
             test_res.unwrap(),
-            MessageType::CONNECT(String::from("foo"), String::from("bar"))
+            MessageType::CONNECT(String::from("foo"), Some(String::from("bar")))
         );
     }
 
@@ -259,12 +849,67 @@ mod tests {
         assert!(test_res.is_err());
         assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
 
-        // missing arguments / 2
-        let teststring = String::from("CONNECT foo\n");
+        // missing arguments / 1.2
+        let teststring = String::from("HELLO\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        // invalid version
+        let teststring = String::from("HELLO two ack\n");
         let test_res = parse_message(&teststring);
         assert!(test_res.is_err());
         assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
 
+        // invalid iteration count
+        let teststring = String::from("CHALLENGE c0ffee42 5a17 many\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        // missing arguments / 3
+        let teststring = String::from("CHALLENGE c0ffee42 5a17\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("AUTH foo\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("PROOF\n");
+        let test_res = parse_message(&teststring);
+        assert!(test_res.is_err());
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        // subscriptions need at least one key
+        let teststring = String::from("SUBSCRIBE\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        // presence comes in key / status pairs
+        let teststring = String::from("PRESENCE alice ONLINE bob\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("PRESENCE alice HERE\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        // acks need the boop id, failures a known reason
+        let teststring = String::from("DELIVERED\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("FAILED 3f2a\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
+        let teststring = String::from("FAILED 3f2a LOST_IN_SPACE\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+
         // too many arguments / 1
         let teststring = String::from("BOOP foo bar\n");
         let test_res = parse_message(&teststring);
@@ -296,9 +941,212 @@ mod tests {
         assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
 
         // empty arguments / 2
-        let teststring = String::from("CONNECT   bar\n");
+        let teststring = String::from("CONNECT   \n");
         let test_res = parse_message(&teststring);
         assert!(test_res.is_err());
         assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
     }
+
+    #[test]
+    fn test_parser_quoted() {
+        // spaces inside quotes
+        let teststring = String::from("CONNECT foo \"bar baz\"\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::CONNECT(String::from("foo"), Some(String::from("bar baz")))
+        );
+
+        // escapes
+        let teststring = String::from("BOOP \"a\\\"b\\\\c\\nd\\re\\tf\"\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::BOOP(
+                String::from("a\"b\\c\nd\re\tf"),
+                None,
+                BoopContent::default()
+            )
+        );
+
+        // empty argument
+        let teststring = String::from("CONNECT \"\" bar\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::CONNECT(String::new(), Some(String::from("bar")))
+        );
+
+        // multiple spaces between arguments
+        let teststring = String::from("CONNECT  foo   bar\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::CONNECT(String::from("foo"), Some(String::from("bar")))
+        );
+    }
+
+    #[test]
+    fn test_parser_malformed_quoting() {
+        let malformed = [
+            "BOOP \"foo\n",      // unterminated quote
+            "BOOP \"foo\\\"\n",  // escaped closing quote
+            "BOOP \"fo\\xo\"\n", // unknown escape sequence
+            "BOOP \"foo\"bar\n", // text after closing quote
+            "BOOP fo\"o\"\n",    // quote inside unquoted argument
+            "BOOP fo\\o\n"       // backslash inside unquoted argument
+        ];
+
+        for teststring in malformed {
+            let test_res = parse_message(&String::from(teststring));
+            assert_eq!(test_res.unwrap_err(), ParserError::MalformedQuoting);
+        }
+    }
+
+    #[test]
+    fn test_parser_boop_content() {
+        let ids = [None, Some(String::from("3f2a"))];
+        let emojis = [None, Some(String::from("🧑‍🤝‍🧑"))];
+        let notes = [None, Some(String::from("come \"over\"\nplease"))];
+        let kinds = [BoopKind::Plain, BoopKind::ComeHere];
+
+        // every combination of optional fields
+        for id in &ids {
+            for emoji in &emojis {
+                for note in &notes {
+                    for kind in kinds {
+                        let content = BoopContent::new(emoji.clone(), note.clone(), kind);
+                        let message =
+                            MessageType::BOOP(String::from("alice"), id.clone(), content.clone());
+                        let text = create_message_text(message.clone());
+
+                        // a boop without fields stays a plain BOOP for old relays
+                        assert_eq!(content.is_plain() && id.is_none(), text == "BOOP alice\n");
+                        assert_eq!(parse_message(&text), Ok(message));
+                    }
+                }
+            }
+        }
+
+        // fields can come in any order and case, unknown ones are skipped
+        let teststring = String::from("BOOP alice KIND gentle sound squeak emoji 🐶 ID 3f2a\n");
+        let test_res = parse_message(&teststring);
+        assert_eq!(
+            test_res.unwrap(),
+            MessageType::BOOP(
+                String::from("alice"),
+                Some(String::from("3f2a")),
+                BoopContent::new(Some(String::from("🐶")), None, BoopKind::Gentle)
+            )
+        );
+
+        let long_emoji = "🐶".repeat(BOOP_EMOJI_MAX_CHARS + 1);
+        let long_note = "a".repeat(BOOP_NOTE_MAX_CHARS + 1);
+        let malformed = [
+            String::from("BOOP alice emoji\n"),         // field without value
+            String::from("BOOP alice kind loud\n"),     // unknown kind
+            String::from("BOOP alice note a note b\n"), // repeated field
+            String::from("BOOP alice emoji \"\"\n"),    // empty emoji
+            String::from("BOOP alice id a id b\n"),     // repeated id
+            // too long
+            format!("BOOP alice emoji {}\n", long_emoji),
+            format!("BOOP alice note {}\n", long_note)
+        ];
+        for teststring in malformed {
+            let test_res = parse_message(&teststring);
+            assert_eq!(test_res.unwrap_err(), ParserError::UnknownArguments);
+        }
+    }
+
+    #[test]
+    fn test_create_message_text() {
+        // simple arguments aren't quoted
+        assert_eq!(
+            create_message_text(MessageType::CONNECT(
+                String::from("foo"),
+                Some(String::from("bar"))
+            )),
+            "CONNECT foo bar\n"
+        );
+        assert_eq!(
+            create_message_text(MessageType::CONNECT(String::from("foo"), None)),
+            "CONNECT foo\n"
+        );
+
+        // everything else is
+        assert_eq!(
+            create_message_text(MessageType::CONNECT(
+                String::from("foo"),
+                Some(String::from("my \"secret\"\npassword"))
+            )),
+            "CONNECT foo \"my \\\"secret\\\"\\npassword\"\n"
+        );
+        assert_eq!(
+            create_message_text(MessageType::BOOP(
+                String::new(),
+                None,
+                BoopContent::default()
+            )),
+            "BOOP \"\"\n"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // a few handpicked nasty characters plus completely random ones
+        const NASTY: [char; 16] = [
+            ' ', '\t', '\n', '\r', '"', '\\', '%', '\0', '\u{7f}', '\u{85}', '\u{a0}', '\u{200b}',
+            '\u{3000}', 'ß', '日', '🐶'
+        ];
+        let mut rng = StdRng::seed_from_u64(0xB00B);
+
+        for _ in 0..2000 {
+            let mut random_string = || {
+                let len = rng.gen_range(0..12);
+                (0..len)
+                    .map(|_| match rng.gen_range(0..3) {
+                        0 => NASTY[rng.gen_range(0..NASTY.len())],
+                        1 => rng.gen_range('a'..='z'),
+                        _ => rng.gen::<char>()
+                    })
+                    .collect::<String>()
+            };
+
+            let messages = [
+                MessageType::HELLO(2, vec![random_string(), random_string()]),
+                MessageType::CONNECT(random_string(), Some(random_string())),
+                MessageType::CONNECT(random_string(), None),
+                MessageType::AUTH(random_string(), random_string()),
+                MessageType::CHALLENGE(random_string(), random_string(), 4096),
+                MessageType::PROOF(random_string()),
+                MessageType::BOOP(random_string(), None, BoopContent::default()),
+                MessageType::BOOP(
+                    random_string(),
+                    Some(random_string()),
+                    BoopContent::new(
+                        Some(String::from("🐶")),
+                        Some(random_string()),
+                        BoopKind::Urgent
+                    )
+                ),
+                MessageType::AYT(random_string()),
+                MessageType::SUBSCRIBE(vec![random_string(), random_string()]),
+                MessageType::UNSUBSCRIBE(vec![random_string()]),
+                MessageType::STATUS(vec![random_string(), random_string()]),
+                MessageType::PRESENCE(vec![(random_string(), true), (random_string(), false)]),
+                MessageType::ONLINE(random_string()),
+                MessageType::AFK(random_string()),
+                MessageType::ERROR(MessageErrorKind::NotAvailable),
+                MessageType::DELIVERED(random_string()),
+                MessageType::FAILED(random_string(), MessageErrorKind::MalformedArguments),
+                MessageType::PING
+            ];
+
+            for message in messages {
+                let text = create_message_text(message.clone());
+                assert!(text.ends_with('\n') && !text[..text.len() - 1].contains('\n'));
+                assert_eq!(parse_message(&text), Ok(message));
+            }
+        }
+    }
 }
@@ -1,38 +1,68 @@
 use std::sync::Arc;
 
-use tauri::Window;
+use {
+    chrono::Utc,
+    rand::Rng,
+    serde::{
+        Deserialize,
+        Serialize
+    },
+    tokio::sync::mpsc
+};
 
 use crate::{
+    auth::{
+        ChallengeLogin,
+        ChallengeStep
+    },
+    config::{
+        BoopConfig,
+        LoginMode,
+        NetworkSettings
+    },
+    error::{
+        BoopError,
+        TlsErrorCategory
+    },
     message::{
         create_message_text,
         error_text,
         parse_message,
+        BoopContent,
+        Capability,
         MessageErrorKind,
-        MessageType
+        MessageType,
+        CLIENT_CAPABILITIES,
+        PROTOCOL_VERSION
+    },
+    partners::{
+        PartnerOnlineStatus,
+        PartnersHandle
+    },
+    queue::{
+        bounded,
+        BoundedReceiver,
+        BoundedSender,
+        OverflowPolicy
     },
-    partners::BoopPartner,
-    send_boop_to_frontend,
-    send_connection_status,
-    send_partners_update_event,
-    PartnerOnlineStatus,
-    ServerConnectionStatus
+    secrets::KeyHandle,
+    stats::{
+        ConnectionStats,
+        PingTracker
+    },
+    tls::{
+        client_config,
+        spki_fingerprint,
+        trust_anchors_for
+    }
 };
 
 use {
-    crate::{
-        ConfigState,
-        ConnectionInterface,
-        ConnectionState,
-        ControlMessage,
-        ControlRx,
-        ControlTx,
-        PartnersState,
-        SinkRx,
-        SinkTx,
-        TrustAnchors
-    },
     std::{
-        collections::HashMap,
+        collections::{
+            HashMap,
+            HashSet
+        },
         io::{
             self,
             Error
@@ -43,7 +73,6 @@ use {
         },
         time::Duration
     },
-    tauri::State,
     tokio::{
         io::{
             split,
@@ -56,153 +85,868 @@ use {
         net::TcpStream,
         sync::{
             mpsc::unbounded_channel,
+            watch,
             Mutex
         }
     },
     tokio_rustls::{
         client::TlsStream,
-        rustls::ClientConfig,
+        rustls::RootCertStore,
         TlsConnector
     }
 };
 
-const PING_INTERVAL: u64 = 5;
-const ALLOWED_PING_MISSED: u32 = 3;
-const PARTNER_CHECK_INTERVAL: u64 = 15;
+// defaults of the advanced network settings, in seconds
+pub const DEFAULT_PING_INTERVAL: u64 = 5;
+pub const DEFAULT_ALLOWED_PING_MISSED: u32 = 3;
+pub const DEFAULT_PARTNER_CHECK_INTERVAL: u64 = 15;
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+pub const DEFAULT_HANDSHAKE_TIMEOUT: u64 = 10;
+pub const DEFAULT_WRITE_TIMEOUT: u64 = 10; // a stalled write drops the connection
 
-type Writer = WriteHalf<TlsStream<TcpStream>>;
+const PRESENCE_BATCH_SIZE: usize = 50; // partner keys per SUBSCRIBE or STATUS line
+const RECONNECT_BASE_DELAY_MS: u64 = 1000;
+const RECONNECT_MAX_DELAY_MS: u64 = 5 * 60 * 1000;
+const SINK_CAPACITY: usize = 64; // messages waiting for the connection
+const CONTROL_CAPACITY: usize = 4;
 
-pub async fn connect_to_server<'a>(
-    conn_state: State<'_, ConnectionState>,
-    config_state: State<'_, ConfigState>,
-    partners_state: State<'_, PartnersState>,
-    trust_anchors: State<'_, TrustAnchors>,
-    window: Arc<Window>
-) -> Result<bool, Box<dyn std::error::Error + 'a>> {
-    send_connection_status(&window, ServerConnectionStatus::AttemptingConnection);
+type Reader = BufReader<ReadHalf<TlsStream<TcpStream>>>;
 
-    // lock config and get necessary data
-    let (addresses, domain);
-    let (user, password);
+/// Write half of the connection, writes that take longer than `timeout` fail
+struct Writer {
+    half:    WriteHalf<TlsStream<TcpStream>>,
+    timeout: Duration
+}
+
+/// An established and logged in connection to the server
+struct Session {
+    reader:    Reader,
+    writehalf: Writer,
+    features:  ServerFeatures,
+    settings:  NetworkSettings // as they were when the session was opened
+}
+
+/// The connection's ends of the channels in a `ConnectionInterface`
+struct InterfaceChannels {
+    sink_rx:     SinkRx,
+    control_rx:  ControlRx,
+    features_tx: watch::Sender<ServerFeatures>
+}
+
+enum LoginOutcome {
+    Accepted(ServerFeatures),
+    Rejected,
+    // the server closed the connection instead of answering the HELLO
+    HelloRefused
+}
+
+#[derive(Debug)]
+pub enum ControlMessage {
+    CloseConnection
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ServerConnectionStatus {
+    Disconnected,
+    AttemptingConnection,
+    Connected,
+    Reconnecting(ReconnectInfo)
+}
+
+impl ServerConnectionStatus {
+    /// Numeric status code as understood by the frontend
+    pub fn code(&self) -> i8 {
+        match self {
+            ServerConnectionStatus::Disconnected => -1,
+            ServerConnectionStatus::AttemptingConnection => 0,
+            ServerConnectionStatus::Connected => 1,
+            ServerConnectionStatus::Reconnecting(_) => 2
+        }
+    }
+}
+
+/// Progress of the automatic reconnection after a lost connection
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconnectInfo {
+    attempt:        u32,
+    next_retry:     Option<i64>, // unix timestamp in milliseconds
+    give_up_reason: Option<String>
+}
+
+impl ReconnectInfo {
+    pub fn retrying(attempt: u32, next_retry: i64) -> Self {
+        ReconnectInfo {
+            attempt,
+            next_retry: Some(next_retry),
+            give_up_reason: None
+        }
+    }
+
+    pub fn giving_up(attempt: u32, reason: &str) -> Self {
+        ReconnectInfo {
+            attempt,
+            next_retry: None,
+            give_up_reason: Some(String::from(reason))
+        }
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub fn next_retry(&self) -> Option<i64> {
+        self.next_retry
+    }
+
+    pub fn give_up_reason(&self) -> Option<String> {
+        self.give_up_reason.clone()
+    }
+}
+
+/// Everything the connection reports back to its owner (the desktop app or
+/// the command-line client)
+#[derive(Debug, PartialEq, Clone)]
+pub enum ConnectionEvent {
+    StatusChanged(ServerConnectionStatus),
+    Booped(String, BoopContent), // partner_key, emoji, note and kind
+    BoopRequested(String),       // user_key of someone who isn't a partner
+    BoopDelivery(String, BoopDeliveryStatus), // boop id
+    PartnerStatusChanged(String, PartnerOnlineStatus),
+    /// the server's certificate was pinned on first use, the config should
+    /// be saved
+    CertificatePinned(String),
+    /// round trip times of the last pings, reported at every ping
+    QualityReport(ConnectionStats)
+}
+
+/// What became of an outgoing boop. Boops on servers without acks stay `Sent`.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BoopDeliveryStatus {
+    Queued,       // waiting in the outbox or the connection's queue
+    Expired,      // waited in the outbox for too long
+    Sent,         // written to the connection
+    Delivered,    // acked by the server
+    NotAvailable, // the partner isn't online
+    Failed        // the server couldn't deliver it for another reason
+}
+
+/// Shorthand for the transmit half of the message channel. The channel is
+/// bounded and rejects messages while it's full.
+pub type SinkTx = BoundedSender<MessageType>;
+/// Bounded as well, only the latest control messages are kept
+pub type ControlTx = BoundedSender<ControlMessage>;
+pub type EventTx = mpsc::UnboundedSender<ConnectionEvent>;
+/// Events of several connections, together with the id of their server
+pub type ServerEventTx = mpsc::UnboundedSender<(String, ConnectionEvent)>;
+
+/// Shorthand for the receive half of the message channel.
+pub type SinkRx = BoundedReceiver<MessageType>;
+pub type ControlRx = BoundedReceiver<ControlMessage>;
+pub type EventRx = mpsc::UnboundedReceiver<ConnectionEvent>;
+pub type ServerEventRx = mpsc::UnboundedReceiver<(String, ConnectionEvent)>;
+
+/// Slot for the connection to one server
+pub type ConnectionHandle = Arc<Mutex<Option<ConnectionInterface>>>;
+
+/// Protocol version and optional features both the server and this client
+/// support, negotiated during the handshake
+#[derive(Debug, PartialEq, Clone)]
+pub struct ServerFeatures {
+    version:      u32,
+    capabilities: HashSet<Capability>
+}
+
+impl ServerFeatures {
+    /// Features of a relay that doesn't know about HELLO
+    pub fn legacy() -> Self {
+        ServerFeatures {
+            version:      1,
+            capabilities: HashSet::new()
+        }
+    }
+
+    /// Features from the server's HELLO, limited to what this client
+    /// supports
+    pub fn negotiated(server_version: u32, server_capabilities: &[String]) -> Self {
+        let capabilities = server_capabilities
+            .iter()
+            .filter_map(|name| Capability::from_name(name))
+            .filter(|capability| CLIENT_CAPABILITIES.contains(capability))
+            .collect();
+
+        ServerFeatures {
+            version: server_version.min(PROTOCOL_VERSION),
+            capabilities
+        }
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+/// Handle to a running connection, kept by the owner to send messages and
+/// control the connection
+pub struct ConnectionInterface {
+    pub sink:            SinkTx,
+    pub control_channel: ControlTx,
+    /// features of the current session, updated after every reconnect
+    pub features:        watch::Receiver<ServerFeatures>
+}
+
+/// Opens a new connection (closing the current one) and logs in. Fails with
+/// `BoopError::LoginRejected` if the server refused the credentials and with
+/// `BoopError::NotConnected` if the attempt was cancelled by
+/// `disconnect_from_server` or another connect. `trust_anchors` are the OS
+/// certificates, the CA files from the config are added to them.
+pub async fn connect_to_server(
+    conn_handle: &Mutex<Option<ConnectionInterface>>,
+    config_handle: Arc<Mutex<BoopConfig>>,
+    key_handle: KeyHandle,
+    partners_handle: PartnersHandle,
+    trust_anchors: RootCertStore,
+    events: EventTx
+) -> Result<(), BoopError> {
+    send_event(
+        &events,
+        ConnectionEvent::StatusChanged(ServerConnectionStatus::AttemptingConnection)
+    );
+
+    // create connection interface, the features are known after the login
+    let (sink_tx, sink_rx): (SinkTx, SinkRx) = bounded(SINK_CAPACITY, OverflowPolicy::Reject);
+    let (control_tx, mut control_rx): (ControlTx, ControlRx) =
+        bounded(CONTROL_CAPACITY, OverflowPolicy::DropOldest);
+    let (features_tx, features_rx) = watch::channel(ServerFeatures::legacy());
+    let own_control = control_tx.clone();
+
+    // close the current connection (or cancel its reconnection attempts) and put
+    // the new interface in the slot right away. The slot isn't locked while
+    // connecting, so a disconnect can cancel the attempt through the control
+    // channel.
     {
-        let app_settings = config_state.0.lock().await;
+        let mut interface_option = conn_handle.lock().await;
+        close_connection(&mut interface_option);
+        *interface_option = Some(ConnectionInterface {
+            control_channel: control_tx,
+            sink:            sink_tx,
+            features:        features_rx
+        });
+    }
 
-        // parse address and domain + resolve dns for next steps
-        (addresses, domain) = resolve_server_addresses(&app_settings.server_address())?;
-        user = app_settings.user_name();
-        password = app_settings.password();
+    // open connection and log in
+    let res = tokio::select! {
+        res = login(&config_handle, &key_handle, &trust_anchors, &events) => res,
+        _ = control_rx.recv() => {
+            info!("connection attempt cancelled");
+            Err(BoopError::NotConnected)
+        }
+    };
+    let (session, connector) = match res {
+        Ok(logged_in) => logged_in,
+        Err(err) => {
+            // clear the slot, unless another connect took it over in the meantime
+            let mut interface_option = conn_handle.lock().await;
+            let own_slot = interface_option.as_ref().map_or(false, |interface| {
+                interface.control_channel.same_channel(&own_control)
+            });
+            if own_slot {
+                *interface_option = None;
+            }
+            return Err(err);
+        }
+    };
+    drop(own_control);
+    let _ = features_tx.send(session.features.clone());
+
+    // change connection status
+    send_event(
+        &events,
+        ConnectionEvent::StatusChanged(ServerConnectionStatus::Connected)
+    );
+
+    // start background activity, the supervisor keeps the connection alive until
+    // it's closed on purpose
+    tokio::spawn(supervise_connection(
+        session,
+        config_handle,
+        key_handle,
+        connector,
+        partners_handle,
+        InterfaceChannels {
+            sink_rx,
+            control_rx,
+            features_tx
+        },
+        events
+    ));
+
+    Ok(())
+}
+
+/// Builds the TLS config, opens the connection and logs in
+async fn login(
+    config_handle: &Arc<Mutex<BoopConfig>>,
+    key_handle: &KeyHandle,
+    trust_anchors: &RootCertStore,
+    events: &EventTx
+) -> Result<(Session, TlsConnector), BoopError> {
+    let tls_config = {
+        let config = config_handle.lock().await;
+        client_config(&config, trust_anchors_for(&config, trust_anchors)?)?
+    };
+    let connector = TlsConnector::from(Arc::new(tls_config));
+
+    match open_session(config_handle, key_handle, &connector, events).await? {
+        Some(session) => Ok((session, connector)),
+        None => Err(BoopError::LoginRejected)
+    }
+}
+
+/// Connections to several servers at the same time, by server id. Every
+/// server has its own slot and lock, so a slow or unreachable server doesn't
+/// hold up the others.
+#[derive(Default)]
+pub struct ConnectionMap(Mutex<HashMap<String, ConnectionHandle>>);
+
+impl ConnectionMap {
+    /// The slot of a server, for `connect_to_server`. Created on first use.
+    pub async fn handle(&self, server: &str) -> ConnectionHandle {
+        let mut slots = self.0.lock().await;
+        Arc::clone(slots.entry(String::from(server)).or_default())
+    }
+
+    /// Whether there is a connection to the server (it might be reconnecting)
+    pub async fn is_connected(&self, server: &str) -> bool {
+        let slot = match self.0.lock().await.get(server).cloned() {
+            Some(slot) => slot,
+            None => return false
+        };
+
+        let connected = match slot.try_lock() {
+            Ok(interface) => interface.is_some(),
+            Err(_) => true // connecting right now
+        };
+        connected
+    }
+
+    /// Sends a message to a server
+    pub async fn send(&self, server: &str, message: MessageType) -> Result<(), BoopError> {
+        let slot = self
+            .0
+            .lock()
+            .await
+            .get(server)
+            .cloned()
+            .ok_or(BoopError::NotConnected)?;
+        let interface = slot.lock().await;
+        let interface = interface.as_ref().ok_or(BoopError::NotConnected)?;
+        send_to_sink(server, interface, message)
     }
 
-    // lock current connection interface, close the connection, clear the handle and
-    // keep the lock to make sure no other process tries to access the
-    // connection during this connect call send close message to the current
-    // stream handler -> will close the existing connection
-    let mut interface_option = conn_state.0.lock().await;
+    /// Tells a server with presence push about added and removed partners.
+    /// Servers without it are polled for every partner anyway, and a server
+    /// that isn't connected gets the whole partner set after logging in.
+    pub async fn update_subscriptions(
+        &self,
+        server: &str,
+        added: Vec<String>,
+        removed: Vec<String>
+    ) -> Result<(), BoopError> {
+        let slot = match self.0.lock().await.get(server).cloned() {
+            Some(slot) => slot,
+            None => return Ok(())
+        };
+        let interface = slot.lock().await;
+        let interface = match interface.as_ref() {
+            Some(interface) => interface,
+            None => return Ok(())
+        };
+        if !interface
+            .features
+            .borrow()
+            .supports(Capability::PresencePush)
+        {
+            return Ok(());
+        }
+
+        if !added.is_empty() {
+            send_to_sink(server, interface, MessageType::SUBSCRIBE(added))?;
+        }
+        if !removed.is_empty() {
+            send_to_sink(server, interface, MessageType::UNSUBSCRIBE(removed))?;
+        }
+
+        Ok(())
+    }
+
+    /// Closes the connection to a server and forgets its slot
+    pub async fn disconnect(&self, server: &str) {
+        let slot = self.0.lock().await.remove(server);
+        if let Some(slot) = slot {
+            disconnect_from_server(&slot).await;
+        }
+    }
+
+    pub async fn disconnect_all(&self) {
+        let slots: Vec<ConnectionHandle> =
+            self.0.lock().await.drain().map(|(_, slot)| slot).collect();
+        for slot in slots {
+            disconnect_from_server(&slot).await;
+        }
+    }
+}
+
+fn send_to_sink(
+    server: &str,
+    interface: &ConnectionInterface,
+    message: MessageType
+) -> Result<(), BoopError> {
+    if interface.sink.is_closed() {
+        warn!(
+            "tried to send to {}, but the sink channel was closed",
+            server
+        );
+        return Err(BoopError::SinkClosed);
+    }
+
+    interface.sink.send(message).map_err(|err| {
+        error!("failed to send message to the sink of {}: {}", server, err);
+        err
+    })
+}
+
+/// Event channel for one connection, its events are passed on to `events`
+/// with the server id. Stops once the connection drops its end.
+pub fn events_of_server(server: &str, events: ServerEventTx) -> EventTx {
+    let (event_tx, mut event_rx) = unbounded_channel();
+    let server = String::from(server);
+
+    tokio::spawn(async move {
+        while let Some(event) = event_rx.recv().await {
+            if events.send((server.clone(), event)).is_err() {
+                debug!("connection event was dropped, nobody is listening");
+            }
+        }
+    });
+
+    event_tx
+}
+
+/// Closes the current connection (or cancels its reconnection attempts) if
+/// there is one.
+pub async fn disconnect_from_server(conn_handle: &Mutex<Option<ConnectionInterface>>) {
+    let mut interface_option = conn_handle.lock().await;
+    close_connection(&mut interface_option);
+}
+
+fn close_connection(interface_option: &mut Option<ConnectionInterface>) {
     if let Some(conn_interface) = &*interface_option {
         // check if channel is still open (the connection might have been terminated
         // unexpectedly before with the control channel going out of scope)
         if !conn_interface.control_channel.is_closed() {
-            conn_interface
+            let close_res = conn_interface
                 .control_channel
-                .send(ControlMessage::CloseConnection)?;
+                .send(ControlMessage::CloseConnection);
+            if let Err(err) = close_res {
+                error!("failed to close connection as requested: {}", err);
+            }
         }
     }
     // drop the interface -> its locked so existing connections should be
     // interrupted until the new interface is built
     *interface_option = None;
+}
+
+fn send_event(events: &EventTx, event: ConnectionEvent) {
+    if let Err(err) = events.send(event) {
+        debug!("connection event was dropped, nobody is listening: {}", err);
+    }
+}
+
+/// Resolves the configured server, opens the TLS connection and logs in.
+/// Returns `None` if the server refused the login credentials.
+async fn open_session(
+    config_handle: &Arc<Mutex<BoopConfig>>,
+    key_handle: &KeyHandle,
+    connector: &TlsConnector,
+    events: &EventTx
+) -> Result<Option<Session>, BoopError> {
+    // lock config and get necessary data
+    let (addresses, domain);
+    let (user, password);
+    let pin_certificate;
+    let settings;
+    {
+        let app_settings = config_handle.lock().await;
+
+        // parse address and domain + resolve dns for next steps
+        (addresses, domain) = resolve_server_addresses(&app_settings.server_address())?;
+        user = app_settings.user_name();
+        // with a certificate login, the client certificate identifies the user
+        password = match app_settings.login_mode() {
+            LoginMode::Password => Some(app_settings.password(&*key_handle.lock().await)?),
+            LoginMode::Certificate => None
+        };
+        pin_certificate = app_settings.pin_certificate();
+        settings = match app_settings.network().validate() {
+            Ok(()) => app_settings.network(),
+            Err(err) => {
+                warn!("{}, using the default network settings", err);
+                NetworkSettings::default()
+            }
+        };
+    }
+
+    // relays that predate HELLO might just hang up on it -> try again without
+    let mut negotiate = true;
+    let handshake_timeout = Duration::from_secs(settings.handshake_timeout());
+    loop {
+        let stream = open_tls_stream(&addresses, &domain, connector, &settings).await?;
+        if pin_certificate {
+            // before anything (like the password) is sent
+            check_certificate_pin(&stream, config_handle, events).await?;
+        }
+
+        // split stream and create reader for connection
+        let (readhalf, writehalf) = split(stream);
+        let mut reader = BufReader::new(readhalf);
+        let mut writehalf = Writer {
+            half:    writehalf,
+            timeout: Duration::from_secs(settings.write_timeout())
+        };
 
-    let config = ClientConfig::builder()
-        .with_safe_defaults()
-        .with_root_certificates(trust_anchors.0.clone())
-        .with_no_client_auth();
-    let connector = TlsConnector::from(Arc::new(config));
+        // handshake with server / login, a server that doesn't answer (or makes the
+        // proof take forever) gets the same time as for the TLS handshake
+        let login = handshake(
+            &mut reader,
+            &mut writehalf,
+            user.clone(),
+            password.clone(),
+            negotiate
+        );
+        let outcome = match tokio::time::timeout(handshake_timeout, login).await {
+            Ok(res) => res?,
+            Err(_) => {
+                return Err(BoopError::Io(format!(
+                    "the server didn't finish the login within {} seconds",
+                    settings.handshake_timeout()
+                )))
+            }
+        };
+        match outcome {
+            LoginOutcome::Accepted(features) => {
+                return Ok(Some(Session {
+                    reader,
+                    writehalf,
+                    features,
+                    settings
+                }))
+            }
+            LoginOutcome::Rejected => return Ok(None),
+            LoginOutcome::HelloRefused => {
+                warn!("server closed the connection after HELLO, retrying without negotiation");
+                negotiate = false;
+            }
+        }
+    }
+}
 
+async fn open_tls_stream(
+    addresses: &[SocketAddr],
+    domain: &str,
+    connector: &TlsConnector,
+    settings: &NetworkSettings
+) -> Result<TlsStream<TcpStream>, BoopError> {
     // connect to socket -> try all resolved ip addresses from the hostname
     let ip_count = addresses.len();
     debug!("hostname resolved into {} IP addresses", ip_count);
     let mut iter = addresses.iter();
+    let mut last_err = None;
     let stream = loop {
         if let Some(addr) = iter.next() {
-            // try connection with current ip
-            let conn_result = TcpStream::connect(&addr).await;
+            // try connection with current ip, giving up after the connect timeout
+            let connect_timeout = Duration::from_secs(settings.connect_timeout());
+            let conn_result = tokio::time::timeout(connect_timeout, TcpStream::connect(&addr))
+                .await
+                .unwrap_or_else(|_| Err(Error::from(io::ErrorKind::TimedOut)));
             if let Ok(stream) = conn_result {
                 // yay, connection succeeded -> return it for use in next steps
                 break stream;
             } else if let Err(err) = conn_result {
                 // oh no, connection failed -> log individual error and continue
                 error!("connection to {} failed: {}", addr, err);
+                last_err = Some(err);
             }
         } else {
             // cancel connection attempt because none of the ips worked
-            Err(format!(
-                "Tried {} resolved IP addresses, but failed to make connection to server",
-                ip_count
-            ))?;
+            return Err(BoopError::Io(format!(
+                "tried {} resolved IP addresses, but failed to make connection to server ({})",
+                ip_count,
+                last_err.map_or(String::from("no addresses"), |err| err.to_string())
+            )));
         }
     };
 
-    debug!("{}", &domain);
-    let domain = rustls::ServerName::try_from(domain.as_str())
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid dnsname"))?;
+    debug!("{}", domain);
+    let domain = rustls::ServerName::try_from(domain)
+        .map_err(|_| BoopError::Dns(String::from(domain), String::from("invalid dnsname")))?;
 
     // handshake tls etc
-    let stream = connector.connect(domain, stream).await?;
-
-    // create connection interface
-    let (sink_tx, sink_rx): (SinkTx, SinkRx) = unbounded_channel();
-    let (control_tx, control_rx): (ControlTx, ControlRx) = unbounded_channel();
-
-    // put connection interface in state
-    *interface_option = Some(ConnectionInterface {
-        control_channel: control_tx,
-        sink:            sink_tx
-    });
+    let handshake_timeout = Duration::from_secs(settings.handshake_timeout());
+    match tokio::time::timeout(handshake_timeout, connector.connect(domain, stream)).await {
+        Ok(res) => Ok(res?),
+        Err(_) => Err(BoopError::Tls(
+            TlsErrorCategory::Handshake,
+            format!(
+                "the server didn't finish the handshake within {} seconds",
+                settings.handshake_timeout()
+            )
+        ))
+    }
+}
 
-    // split stream and create reader for connection
-    let (readhalf, mut writehalf) = split(stream);
-    let reader = BufReader::new(readhalf);
+/// Compares the key of the server's certificate with the pinned one. On first
+/// use, the key is pinned.
+async fn check_certificate_pin(
+    stream: &TlsStream<TcpStream>,
+    config_handle: &Arc<Mutex<BoopConfig>>,
+    events: &EventTx
+) -> Result<(), BoopError> {
+    let presented = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .and_then(spki_fingerprint)
+        .ok_or_else(|| {
+            BoopError::Tls(
+                TlsErrorCategory::Certificate,
+                String::from("couldn't read the key of the server's certificate")
+            )
+        })?;
 
-    // handshake with server / login
-    let (login_correct, mut reader) = handshake(reader, &mut writehalf, user, password).await?;
-    if !login_correct {
-        return Ok(false);
+    let mut config = config_handle.lock().await;
+    match config.pinned_fingerprint() {
+        Some(pinned) if pinned == presented => Ok(()),
+        Some(pinned) => {
+            warn!(
+                "server certificate changed, pinned key {}, presented key {}",
+                pinned, presented
+            );
+            Err(BoopError::CertificateChanged(pinned, presented))
+        }
+        None => {
+            info!("pinning server certificate key {}", presented);
+            config.set_pinned_fingerprint(Some(presented.clone()));
+            send_event(events, ConnectionEvent::CertificatePinned(presented));
+            Ok(())
+        }
     }
+}
 
-    // clone partners handle to use in the connection thread
-    let partners_handle = Arc::clone(&partners_state.0);
+/// Runs the read/write loop of an established session and transparently
+/// reconnects with a jittered exponential backoff whenever the connection is
+/// lost. Stops once the connection is closed on purpose, the control channel
+/// is dropped (by `disconnect` or a new `connect`) or the server refuses the
+/// login.
+async fn supervise_connection(
+    session: Session,
+    config_handle: Arc<Mutex<BoopConfig>>,
+    key_handle: KeyHandle,
+    connector: TlsConnector,
+    partners_handle: PartnersHandle,
+    channels: InterfaceChannels,
+    events: EventTx
+) {
+    let InterfaceChannels {
+        mut sink_rx,
+        mut control_rx,
+        features_tx
+    } = channels;
+    let mut session = session;
 
-    // change connection status
-    send_connection_status(&window, ServerConnectionStatus::Connected);
+    loop {
+        let Session {
+            mut reader,
+            writehalf,
+            features,
+            settings
+        } = session;
 
-    // start background activity
-    tokio::spawn(async move {
         if let Err(err) = rw_loop(
             &mut reader,
             writehalf,
-            partners_handle,
-            sink_rx,
-            control_rx,
-            &window
+            &features,
+            &settings,
+            &partners_handle,
+            &mut sink_rx,
+            &mut control_rx,
+            &events
         )
         .await
         {
-            // log error
+            // log error and try to get the connection back
             error!("disconnected after connection I/O error: {}", err);
         } else {
             info!("closed connection as expected");
+            break;
         }
 
-        // change connection status in frontend
-        send_connection_status(&window, ServerConnectionStatus::Disconnected);
-    });
+        match reconnect(
+            &config_handle,
+            &key_handle,
+            &connector,
+            &mut control_rx,
+            &events
+        )
+        .await
+        {
+            Some(new_session) => {
+                // the server might have been up- or downgraded in the meantime
+                let _ = features_tx.send(new_session.features.clone());
+                session = new_session;
+                send_event(
+                    &events,
+                    ConnectionEvent::StatusChanged(ServerConnectionStatus::Connected)
+                );
+            }
+            None => break
+        }
+    }
+
+    // boops still waiting in the sink won't be sent anymore
+    while let Some(message) = sink_rx.try_recv() {
+        if let MessageType::BOOP(partner_key, Some(id), _) = message {
+            debug!("boop {} to {} was never sent", id, partner_key);
+            send_event(
+                &events,
+                ConnectionEvent::BoopDelivery(id, BoopDeliveryStatus::Failed)
+            );
+        }
+    }
+
+    // change connection status in frontend
+    send_event(
+        &events,
+        ConnectionEvent::StatusChanged(ServerConnectionStatus::Disconnected)
+    );
+}
+
+/// Tries to re-establish a lost session until it succeeds, the login is
+/// refused or the reconnection is cancelled through the control channel.
+async fn reconnect(
+    config_handle: &Arc<Mutex<BoopConfig>>,
+    key_handle: &KeyHandle,
+    connector: &TlsConnector,
+    control_rx: &mut ControlRx,
+    events: &EventTx
+) -> Option<Session> {
+    let mut attempt: u32 = 0;
+
+    loop {
+        attempt += 1;
 
-    Ok(true)
+        // announce the next attempt and wait for the backoff delay to pass
+        let delay = backoff_delay(attempt);
+        let next_retry = Utc::now().timestamp_millis() + delay.as_millis() as i64;
+        info!(
+            "reconnection attempt {} in {} ms",
+            attempt,
+            delay.as_millis()
+        );
+        send_event(
+            events,
+            ConnectionEvent::StatusChanged(ServerConnectionStatus::Reconnecting(
+                ReconnectInfo::retrying(attempt, next_retry)
+            ))
+        );
+
+        // a closed control channel or a close message both mean that the
+        // connection isn't wanted anymore -> cancel
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {},
+            _ = control_rx.recv() => {
+                info!("reconnection cancelled");
+                return None;
+            }
+        }
+
+        tokio::select! {
+            res = open_session(config_handle, key_handle, connector, events) => {
+                match res {
+                    Ok(Some(session)) => {
+                        info!("reconnected after {} attempts", attempt);
+                        return Some(session);
+                    }
+                    Ok(None) => {
+                        // retrying with the same credentials is pointless
+                        warn!("server refused the login while reconnecting, giving up");
+                        send_event(
+                            events,
+                            ConnectionEvent::StatusChanged(ServerConnectionStatus::Reconnecting(
+                                ReconnectInfo::giving_up(attempt, "login rejected by server")
+                            ))
+                        );
+                        return None;
+                    }
+                    Err(BoopError::CertificateChanged(..)) => {
+                        // has to be accepted by the user first
+                        warn!("server certificate changed while reconnecting, giving up");
+                        send_event(
+                            events,
+                            ConnectionEvent::StatusChanged(ServerConnectionStatus::Reconnecting(
+                                ReconnectInfo::giving_up(attempt, "server certificate changed")
+                            ))
+                        );
+                        return None;
+                    }
+                    Err(err @ BoopError::CredentialsLocked) | Err(err @ BoopError::Credentials(_)) => {
+                        // the user has to unlock or enter the password again
+                        warn!("password unavailable while reconnecting, giving up: {}", err);
+                        send_event(
+                            events,
+                            ConnectionEvent::StatusChanged(ServerConnectionStatus::Reconnecting(
+                                ReconnectInfo::giving_up(attempt, "password unavailable")
+                            ))
+                        );
+                        return None;
+                    }
+                    Err(err) => {
+                        error!("reconnection attempt {} failed: {}", attempt, err);
+                    }
+                }
+            },
+            _ = control_rx.recv() => {
+                info!("reconnection cancelled");
+                return None;
+            }
+        }
+    }
+}
+
+/// Exponential backoff with "equal jitter": at least half of the capped
+/// exponential delay, plus a random share of the other half
+fn backoff_delay(attempt: u32) -> Duration {
+    let exponential =
+        RECONNECT_BASE_DELAY_MS.saturating_mul(1 << attempt.saturating_sub(1).min(16));
+    let half = exponential.min(RECONNECT_MAX_DELAY_MS) / 2;
+
+    Duration::from_millis(half + rand::thread_rng().gen_range(0..=half))
 }
 
 fn resolve_server_addresses(
     server_address: &String
-) -> Result<(Vec<SocketAddr>, String), io::Error> {
-    let addresses: Vec<SocketAddr> = server_address.to_socket_addrs()?.collect();
+) -> Result<(Vec<SocketAddr>, String), BoopError> {
+    let addresses: Vec<SocketAddr> = server_address
+        .to_socket_addrs()
+        .map_err(|err| BoopError::Dns(server_address.clone(), err.to_string()))?
+        .collect();
 
     // split off port
     let colon_search_result = server_address.rfind(":");
@@ -213,119 +957,311 @@ fn resolve_server_addresses(
         Ok((addresses, domain))
     } else {
         error!("server address doesn't include port");
-        Err(io::Error::from(io::ErrorKind::NotFound))
+        Err(BoopError::Dns(
+            server_address.clone(),
+            String::from("missing port")
+        ))
     }
 }
 
-async fn handshake<'a>(
-    mut reader: BufReader<ReadHalf<TlsStream<TcpStream>>>,
+async fn handshake(
+    reader: &mut Reader,
     writehalf: &mut Writer,
     user: String,
-    password: String
-) -> Result<(bool, BufReader<ReadHalf<TlsStream<TcpStream>>>), io::Error> {
-    // set up receiver so we don't miss the message
-    let read_thread_handle = tokio::spawn(async move {
-        loop {
-            let mut buf = String::new();
-            let read = reader.read_line(&mut buf).await;
-            if let Ok(n) = read {
-                if n == 0 {
-                    // EOF / stream closed
-                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
-                }
+    password: Option<String>,
+    negotiate: bool
+) -> Result<LoginOutcome, io::Error> {
+    // agree on protocol version and features first (if wanted)
+    let features = if negotiate {
+        match negotiate_features(reader, writehalf).await? {
+            Some(features) => features,
+            None => return Ok(LoginOutcome::HelloRefused)
+        }
+    } else {
+        ServerFeatures::legacy()
+    };
 
-                let parse_res = parse_message(&buf);
-                if let Err(err) = parse_res {
-                    error!("couldn't parse the received server message: {}", err);
-                    return Err(io::Error::from(io::ErrorKind::InvalidData));
-                }
-                let received_message = parse_res.unwrap();
-                return if received_message == MessageType::HEY {
-                    Ok((true, reader))
-                } else if received_message == MessageType::NO {
-                    Ok((false, reader))
-                } else {
-                    error!(
-                        "protocol mismatch by server, expected HEY/NO, got: {}",
-                        create_message_text(received_message)
-                    );
-                    Err(io::Error::from(io::ErrorKind::InvalidData))
-                };
-            } else {
-                // other read error
-                read?;
-            }
+    // the password only crosses the wire if the server can't do challenge logins
+    let accepted = match password {
+        Some(password) if features.supports(Capability::ChallengeLogin) => {
+            challenge_login(reader, writehalf, user, password).await?
         }
-    });
+        password => connect_login(reader, writehalf, user, password).await?
+    };
 
+    if accepted {
+        Ok(LoginOutcome::Accepted(features))
+    } else {
+        Ok(LoginOutcome::Rejected)
+    }
+}
+
+/// Logs in with a plain CONNECT, returns whether the server accepted it
+async fn connect_login(
+    reader: &mut Reader,
+    writehalf: &mut Writer,
+    user: String,
+    password: Option<String>
+) -> Result<bool, io::Error> {
     // send login message
     send_message(writehalf, MessageType::CONNECT(user, password)).await?;
 
     // wait for server response and return
-    let res = read_thread_handle.await;
-    if let Err(err) = res {
-        panic!(
-            "something weird happening while doing the protocol handshake: {}",
-            err
+    let received_message = read_handshake_message(reader).await?;
+    if received_message == MessageType::HEY {
+        Ok(true)
+    } else if received_message == MessageType::NO {
+        Ok(false)
+    } else {
+        let received = create_message_text(received_message);
+        error!(
+            "protocol mismatch by server, expected HEY/NO, got: {}",
+            received
         );
+        Err(BoopError::protocol_violation(format!(
+            "expected HEY or NO, got {}",
+            received.trim_end()
+        )))
+    }
+}
+
+/// Proves the password to the server without sending it, returns whether the
+/// server accepted the proof
+async fn challenge_login(
+    reader: &mut Reader,
+    writehalf: &mut Writer,
+    user: String,
+    password: String
+) -> Result<bool, io::Error> {
+    let mut login = ChallengeLogin::new(user, password);
+    send_message(writehalf, login.start()).await?;
+
+    loop {
+        let received_message = read_handshake_message(reader).await?;
+        match login.receive(received_message.clone()) {
+            Ok(ChallengeStep::Prove(input)) => {
+                // PBKDF2 with the server's iteration count would stall the runtime
+                let proof = tokio::task::spawn_blocking(move || input.compute())
+                    .await
+                    .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+                send_message(writehalf, proof).await?
+            }
+            Ok(ChallengeStep::Accepted) => return Ok(true),
+            Ok(ChallengeStep::Rejected) => return Ok(false),
+            Err(err) => {
+                error!(
+                    "challenge login failed on {}: {}",
+                    create_message_text(received_message).trim_end(),
+                    err
+                );
+                return Err(BoopError::protocol_violation(err.to_string()));
+            }
+        }
+    }
+}
+
+/// Announces this client's protocol version and capabilities. Returns `None`
+/// if the server closed the connection instead of answering.
+async fn negotiate_features(
+    reader: &mut Reader,
+    writehalf: &mut Writer
+) -> Result<Option<ServerFeatures>, io::Error> {
+    let capabilities = CLIENT_CAPABILITIES
+        .iter()
+        .map(|capability| String::from(capability.name()))
+        .collect();
+    send_message(
+        writehalf,
+        MessageType::HELLO(PROTOCOL_VERSION, capabilities)
+    )
+    .await?;
+
+    match read_handshake_message(reader).await {
+        Ok(MessageType::HELLO(version, capabilities)) => {
+            let features = ServerFeatures::negotiated(version, &capabilities);
+            info!(
+                "negotiated protocol version {} with capabilities {:?}",
+                features.version(),
+                features.capabilities
+            );
+            Ok(Some(features))
+        }
+        Ok(MessageType::ERROR(err)) => {
+            // old relay that doesn't know HELLO, but keeps the connection open
+            info!(
+                "server doesn't support protocol negotiation ({}), using legacy protocol",
+                error_text(err)
+            );
+            Ok(Some(ServerFeatures::legacy()))
+        }
+        Ok(received_message) => {
+            let received = create_message_text(received_message);
+            error!(
+                "protocol mismatch by server, expected HELLO, got: {}",
+                received
+            );
+            Err(BoopError::protocol_violation(format!(
+                "expected HELLO, got {}",
+                received.trim_end()
+            )))
+        }
+        Err(err)
+            if err.kind() == io::ErrorKind::UnexpectedEof
+                || err.kind() == io::ErrorKind::ConnectionReset =>
+        {
+            Ok(None)
+        }
+        Err(err) => Err(err)
+    }
+}
+
+/// Reads the next message while logging in, a closed stream is an error here
+async fn read_handshake_message(reader: &mut Reader) -> Result<MessageType, io::Error> {
+    let mut buf = String::new();
+    if reader.read_line(&mut buf).await? == 0 {
+        // EOF / stream closed
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
     }
 
-    res.unwrap()
+    parse_message(&buf).map_err(|err| {
+        error!("couldn't parse the received server message: {}", err);
+        BoopError::protocol_violation(format!("couldn't parse the message: {}", err))
+    })
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn rw_loop(
-    reader: &mut BufReader<ReadHalf<TlsStream<TcpStream>>>,
+    reader: &mut Reader,
     mut writehalf: Writer,
-    partners_handle: Arc<Mutex<HashMap<String, (BoopPartner, PartnerOnlineStatus)>>>,
-    mut sink_rx: SinkRx,
-    mut control_rx: ControlRx,
-    window: &Window
+    features: &ServerFeatures,
+    settings: &NetworkSettings,
+    partners_handle: &PartnersHandle,
+    sink_rx: &mut SinkRx,
+    control_rx: &mut ControlRx,
+    events: &EventTx
 ) -> io::Result<()> {
     // create watchdog for pings
-    let mut ping_watchdog = tokio::time::interval(Duration::from_secs(PING_INTERVAL));
+    let mut ping_watchdog = tokio::time::interval(Duration::from_secs(settings.ping_interval()));
     ping_watchdog.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay); // if tick is missed, fire next tick asap and then wait the full afk timeout
                                                                                     // again
 
-    let mut partner_watchdog = tokio::time::interval(Duration::from_secs(PARTNER_CHECK_INTERVAL));
+    let mut partner_watchdog =
+        tokio::time::interval(Duration::from_secs(settings.partner_check_interval()));
     partner_watchdog.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay); // if tick is missed, fire next tick asap and then wait the full afk timeout
                                                                                        // again
 
-    let mut missed_pongs: u32 = 0;
+    let mut pings = PingTracker::default();
+
+    // relays with presence push report status changes on their own, the others
+    // are asked for every partner at each interval
+    let presence_push = features.supports(Capability::PresencePush);
+    if presence_push {
+        subscribe_to_partners(partners_handle, &mut writehalf).await?;
+    }
 
     loop {
         let mut buf = String::new();
         tokio::select! {
           _ = ping_watchdog.tick() => {
-            send_pings_and_check_misses(&mut missed_pongs, &mut writehalf).await?;
+            send_pings_and_check_misses(&mut pings, settings.allowed_ping_missed(), &mut writehalf, events).await?;
           },
-          _ = partner_watchdog.tick() => {
-            check_partner_availability(&partners_handle, &mut writehalf).await?
+          _ = partner_watchdog.tick(), if !presence_push => {
+            check_partner_availability(partners_handle, features, &mut writehalf).await?
           },
           Some(msg) = sink_rx.recv() => {
-            send_message(&mut writehalf, msg).await?;
+            send_outgoing(&mut writehalf, msg, features, events).await?;
           },
           Some(control_msg) = control_rx.recv() => {
             // handles all logic involved with the control channel from the mainthread to
             // the connection loop
             match control_msg {
                 ControlMessage::CloseConnection => {
+                    // flush messages that were queued before the close request (e.g. a
+                    // boop sent right before disconnecting)
+                    while let Some(msg) = sink_rx.try_recv() {
+                        send_outgoing(&mut writehalf, msg, features, events).await?;
+                    }
                     send_message(&mut writehalf, MessageType::DISCONNECT).await?;
                     return Ok(());
                 }
             }
           },
           res = reader.read_line(&mut buf) => {
-            handle_message_input(res, &buf, &partners_handle, &mut missed_pongs, &mut writehalf, window).await?;
+            handle_message_input(res, &buf, partners_handle, &mut pings, &mut writehalf, events).await?;
           }
         }
     }
 }
 
+/// Sends a message from the sink and reports boops as sent, or as failed if
+/// the write didn't go through
+async fn send_outgoing(
+    writehalf: &mut Writer,
+    message: MessageType,
+    features: &ServerFeatures,
+    events: &EventTx
+) -> io::Result<()> {
+    // the boop id is kept even if the server doesn't get it, the owner still
+    // wants to know the boop went out
+    let boop_id = match &message {
+        MessageType::BOOP(_, Some(id), _) => Some(id.clone()),
+        _ => None
+    };
+
+    if let Err(err) = send_message(writehalf, supported_message(message, features)).await {
+        // the boop is lost with the connection
+        if let Some(id) = boop_id {
+            send_event(
+                events,
+                ConnectionEvent::BoopDelivery(id, BoopDeliveryStatus::Failed)
+            );
+        }
+        return Err(err);
+    }
+    if let Some(id) = boop_id {
+        send_event(
+            events,
+            ConnectionEvent::BoopDelivery(id, BoopDeliveryStatus::Sent)
+        );
+    }
+    Ok(())
+}
+
+/// Strips what the server doesn't understand from an outgoing message. Boops
+/// lose their id on relays without acks and their emoji, note and kind on
+/// relays without boop payloads.
+fn supported_message(message: MessageType, features: &ServerFeatures) -> MessageType {
+    match message {
+        MessageType::BOOP(partner_key, id, content) => {
+            let id = if features.supports(Capability::Ack) {
+                id
+            } else {
+                None
+            };
+            let content = if !content.is_plain() && !features.supports(Capability::BoopPayload) {
+                debug!("server doesn't support boop payloads, sending a plain boop");
+                BoopContent::default()
+            } else {
+                content
+            };
+            MessageType::BOOP(partner_key, id, content)
+        }
+        message => message
+    }
+}
+
 async fn send_pings_and_check_misses(
-    missed_pongs: &mut u32,
-    writehalf: &mut Writer
+    pings: &mut PingTracker,
+    allowed_missed: u32,
+    writehalf: &mut Writer,
+    events: &EventTx
 ) -> io::Result<()> {
-    if *missed_pongs > ALLOWED_PING_MISSED {
+    // report the quality of the last interval before starting the next one
+    if pings.started() {
+        send_event(events, ConnectionEvent::QualityReport(pings.stats()));
+    }
+
+    if pings.missed() > allowed_missed {
         // too many misses -> disconnect
         Err(io::Error::new(
             io::ErrorKind::BrokenPipe,
@@ -335,41 +1271,96 @@ async fn send_pings_and_check_misses(
         // none or acceptable number of misses -> send new ping
         debug!(
             "sent ping to server, current pong miss counter: {}",
-            missed_pongs
+            pings.missed()
         );
-        *missed_pongs += 1;
+        pings.ping_sent(Utc::now().timestamp_millis());
         send_message(writehalf, MessageType::PING).await
     }
 }
 
 async fn check_partner_availability(
-    partners_handle: &Arc<Mutex<HashMap<String, (BoopPartner, PartnerOnlineStatus)>>>,
+    partners_handle: &PartnersHandle,
+    features: &ServerFeatures,
     writehalf: &mut Writer
 ) -> io::Result<()> {
-    // locks partners map
-    let partners = partners_handle.lock().await;
+    let partner_keys = partner_keys(partners_handle).await;
 
-    // sends "are you there?" msg for every known partner
-    for partner_key in partners.keys() {
-        debug!("asked for online status of partner: {}", partner_key);
-        send_message(writehalf, MessageType::AYT(partner_key.clone())).await?;
+    if features.supports(Capability::PresenceBatch) {
+        // asks for the online status of many partners at once
+        for batch in partner_keys.chunks(PRESENCE_BATCH_SIZE) {
+            debug!("asked for online status of {} partners", batch.len());
+            send_message(writehalf, MessageType::STATUS(batch.to_vec())).await?;
+        }
+    } else {
+        // sends "are you there?" msg for every known partner
+        for partner_key in partner_keys {
+            debug!("asked for online status of partner: {}", partner_key);
+            send_message(writehalf, MessageType::AYT(partner_key)).await?;
+        }
     }
 
     // none of the previous requests failed -> seems okay
     Ok(())
 }
 
+/// Subscribes to the status changes of every known partner, the server
+/// answers with their current status
+async fn subscribe_to_partners(
+    partners_handle: &PartnersHandle,
+    writehalf: &mut Writer
+) -> io::Result<()> {
+    let partner_keys = partner_keys(partners_handle).await;
+    for batch in partner_keys.chunks(PRESENCE_BATCH_SIZE) {
+        debug!("subscribed to the status of {} partners", batch.len());
+        send_message(writehalf, MessageType::SUBSCRIBE(batch.to_vec())).await?;
+    }
+
+    Ok(())
+}
+
+/// Sorted keys of all partners. The partners are only locked while copying
+/// them, not while the messages about them are written to a slow socket.
+async fn partner_keys(partners_handle: &PartnersHandle) -> Vec<String> {
+    let mut partner_keys: Vec<String> = partners_handle.lock().await.keys().cloned().collect();
+    partner_keys.sort();
+    partner_keys
+}
+
+/// Saves the reported online status of partners and tells the owner about
+/// every change. Keys that aren't partners are ignored.
+async fn update_partner_statuses(
+    partners_handle: &PartnersHandle,
+    statuses: Vec<(String, PartnerOnlineStatus)>,
+    events: &EventTx
+) {
+    let mut partners = partners_handle.lock().await;
+    for (partner_key, status) in statuses {
+        if let Some(entry) = partners.get_mut(&partner_key) {
+            if entry.1 != status {
+                // change state
+                entry.1 = status;
+
+                // update frontend if necessary
+                send_event(
+                    events,
+                    ConnectionEvent::PartnerStatusChanged(partner_key, status)
+                );
+            }
+        }
+    }
+}
+
 async fn handle_message_input(
     res: io::Result<usize>,
-    buf: &String,
-    partners_handle: &Arc<Mutex<HashMap<String, (BoopPartner, PartnerOnlineStatus)>>>,
-    missed_pongs: &mut u32,
+    buf: &str,
+    partners_handle: &PartnersHandle,
+    pings: &mut PingTracker,
     writehalf: &mut Writer,
-    window: &Window
+    events: &EventTx
 ) -> io::Result<()> {
     match res {
         Ok(n) => {
-            if false && n == 0 {
+            if n == 0 {
                 // EOF while reading
                 debug!("received 0 buffer length");
                 return Err(Error::from(io::ErrorKind::UnexpectedEof));
@@ -378,65 +1369,81 @@ async fn handle_message_input(
             let parse_result = parse_message(buf);
             if let Ok(msg) = parse_result {
                 match msg {
-                    MessageType::BOOP(partner_key) => {
+                    MessageType::BOOP(partner_key, _, content) => {
                         // log boop to logger
                         info!("got booped by {}", &partner_key);
-                        // transmit boop to frontend
-                        send_boop_to_frontend(window, partner_key);
+                        // only partners may boop, everything else is a request the user has
+                        // to consent to first
+                        if partners_handle.lock().await.contains_key(&partner_key) {
+                            send_event(events, ConnectionEvent::Booped(partner_key, content));
+                        } else {
+                            send_event(events, ConnectionEvent::BoopRequested(partner_key));
+                        }
                     }
                     MessageType::BYE => {
-                        // server says goodbye after disconnect
-                        return Ok(());
+                        // the server ends the session, nothing after this is read
+                        return Err(Error::new(
+                            io::ErrorKind::ConnectionAborted,
+                            "the server closed the session"
+                        ));
                     }
                     MessageType::PONG => {
-                        *missed_pongs = 0;
+                        if let Some(rtt) = pings.pong_received(Utc::now().timestamp_millis()) {
+                            debug!("got pong after {} ms", rtt);
+                        }
                     }
                     MessageType::ERROR(err) => {
                         error!("server reported error: {}", error_text(err));
                     }
+                    MessageType::DELIVERED(id) => {
+                        send_event(
+                            events,
+                            ConnectionEvent::BoopDelivery(id, BoopDeliveryStatus::Delivered)
+                        );
+                    }
+                    MessageType::FAILED(id, err) => {
+                        debug!("boop {} wasn't delivered: {}", id, error_text(err.clone()));
+                        let status = if err == MessageErrorKind::NotAvailable {
+                            BoopDeliveryStatus::NotAvailable
+                        } else {
+                            BoopDeliveryStatus::Failed
+                        };
+                        send_event(events, ConnectionEvent::BoopDelivery(id, status));
+                    }
                     MessageType::ONLINE(partner_key) => {
-                        let mut partners = partners_handle.lock().await;
-                        if let Some(entry) = partners.get_mut(&partner_key) {
-                            if entry.1 != PartnerOnlineStatus::Online {
-                                // change state
-                                entry.1 = PartnerOnlineStatus::Online;
-
-                                // update frontend if necessary
-                                send_partners_update_event(
-                                    window,
-                                    &partner_key,
-                                    PartnerOnlineStatus::Online
-                                );
-                            }
-                        }
+                        let statuses = vec![(partner_key, PartnerOnlineStatus::Online)];
+                        update_partner_statuses(partners_handle, statuses, events).await;
                     }
                     MessageType::AFK(partner_key) => {
-                        let mut partners = partners_handle.lock().await;
-                        if let Some(entry) = partners.get_mut(&partner_key) {
-                            if entry.1 != PartnerOnlineStatus::Afk {
-                                // change state
-                                entry.1 = PartnerOnlineStatus::Afk;
-
-                                // update frontend if necessary
-                                send_partners_update_event(
-                                    window,
-                                    &partner_key,
-                                    PartnerOnlineStatus::Afk
-                                );
-                            }
-                        }
+                        let statuses = vec![(partner_key, PartnerOnlineStatus::Afk)];
+                        update_partner_statuses(partners_handle, statuses, events).await;
+                    }
+                    MessageType::PRESENCE(presence) => {
+                        let statuses = presence
+                            .into_iter()
+                            .map(|(partner_key, online)| {
+                                if online {
+                                    (partner_key, PartnerOnlineStatus::Online)
+                                } else {
+                                    (partner_key, PartnerOnlineStatus::Afk)
+                                }
+                            })
+                            .collect();
+                        update_partner_statuses(partners_handle, statuses, events).await;
                     }
-                    _ => {
+                    unexpected => {
                         // against protocol -> disconnect
-                        return send_error_and_close(writehalf, MessageErrorKind::ProtocolMismatch)
-                            .await;
+                        send_error_and_close(writehalf, MessageErrorKind::ProtocolMismatch).await?;
+                        return Err(BoopError::protocol_violation(format!(
+                            "unexpected message {}",
+                            create_message_text(unexpected).trim_end()
+                        )));
                     }
                 }
             } else {
                 // not standard-compliant
                 send_error_and_close(writehalf, parse_result.unwrap_err().into()).await?;
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
+                return Err(BoopError::protocol_violation(
                     "couldn't parse the received message"
                 ));
             }
@@ -454,10 +1461,22 @@ async fn send_error_and_close(writehalf: &mut Writer, err: MessageErrorKind) ->
 
 async fn send_message_and_close(writehalf: &mut Writer, message: MessageType) -> io::Result<()> {
     send_message(writehalf, message).await?;
-    writehalf.shutdown().await
+    writehalf.half.shutdown().await
 }
 
+/// Writes a message, a write that doesn't finish within the writer's timeout
+/// fails with `TimedOut` so a wedged connection gets torn down
 async fn send_message(writehalf: &mut Writer, message: MessageType) -> io::Result<()> {
     let msg_text = create_message_text(message);
-    writehalf.write_all(msg_text.as_bytes()).await
+    let write = writehalf.half.write_all(msg_text.as_bytes());
+    match tokio::time::timeout(writehalf.timeout, write).await {
+        Ok(res) => res,
+        Err(_) => {
+            warn!(
+                "writing to the server took longer than {} seconds",
+                writehalf.timeout.as_secs()
+            );
+            Err(Error::from(io::ErrorKind::TimedOut))
+        }
+    }
 }
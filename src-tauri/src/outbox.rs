@@ -0,0 +1,172 @@
+use {
+    crate::{
+        files::save_file,
+        message::BoopContent,
+        schema::{
+            Migration,
+            Versioned
+        }
+    },
+    serde::{
+        Deserialize,
+        Serialize
+    },
+    std::{
+        io,
+        path::PathBuf,
+        sync::Arc
+    },
+    tokio::sync::Mutex
+};
+
+pub const OUTBOX_FILENAME: &str = "boop.outbox.json";
+
+/// How long a queued boop waits for the connection by default, in minutes
+pub const DEFAULT_OUTBOX_EXPIRY: u32 = 10;
+
+pub type OutboxHandle = Arc<Mutex<Outbox>>;
+
+/// A boop that was made while the server wasn't connected
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedBoop {
+    id:          String, // boop id, reported in the delivery events
+    partner_key: String,
+    content:     BoopContent,
+    queued_at:   i64 // unix timestamp in millis
+}
+
+/// Boops waiting for the connection, oldest first
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Outbox {
+    boops: Vec<QueuedBoop>
+}
+
+impl QueuedBoop {
+    pub fn new(id: String, partner_key: String, content: BoopContent, queued_at: i64) -> Self {
+        QueuedBoop {
+            id,
+            partner_key,
+            content,
+            queued_at
+        }
+    }
+
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    pub fn partner_key(&self) -> String {
+        self.partner_key.clone()
+    }
+
+    pub fn content(&self) -> BoopContent {
+        self.content.clone()
+    }
+
+    pub fn queued_at(&self) -> i64 {
+        self.queued_at
+    }
+}
+
+impl Versioned for Outbox {
+    const MIGRATIONS: &'static [Migration] = &[];
+    const VERSION: u32 = 1;
+}
+
+impl Outbox {
+    pub fn boops(&self) -> &Vec<QueuedBoop> {
+        &self.boops
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.boops.is_empty()
+    }
+
+    pub fn push(&mut self, boop: QueuedBoop) {
+        self.boops.push(boop);
+    }
+
+    /// Takes the boop with the given id out of the outbox
+    pub fn cancel(&mut self, id: &str) -> Option<QueuedBoop> {
+        let index = self.boops.iter().position(|boop| boop.id == id)?;
+        Some(self.boops.remove(index))
+    }
+
+    /// Removes and returns the boops that were queued more than `expiry`
+    /// minutes before `now`
+    pub fn remove_expired(&mut self, now: i64, expiry: u32) -> Vec<QueuedBoop> {
+        let oldest = now - i64::from(expiry) * 60 * 1000;
+        let (expired, waiting) = self
+            .boops
+            .drain(..)
+            .partition(|boop| boop.queued_at < oldest);
+        self.boops = waiting;
+        expired
+    }
+
+    /// Empties the outbox, oldest boop first
+    pub fn take_all(&mut self) -> Vec<QueuedBoop> {
+        std::mem::take(&mut self.boops)
+    }
+}
+
+/// Queues a boop and saves the outbox
+pub async fn queue_boop(
+    outbox_handle: &OutboxHandle,
+    outbox_file: &PathBuf,
+    boop: QueuedBoop
+) -> io::Result<()> {
+    let mut outbox = outbox_handle.lock().await;
+    outbox.push(boop);
+    save_file(outbox_file, &*outbox).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        message::BoopContent,
+        outbox::{
+            Outbox,
+            QueuedBoop
+        }
+    };
+
+    fn queued(id: &str, queued_at: i64) -> QueuedBoop {
+        QueuedBoop::new(
+            String::from(id),
+            String::from("alice"),
+            BoopContent::default(),
+            queued_at
+        )
+    }
+
+    #[test]
+    fn test_remove_expired() {
+        let minute = 60 * 1000;
+        let mut outbox = Outbox::default();
+        outbox.push(queued("old", 0));
+        outbox.push(queued("edge", 5 * minute));
+        outbox.push(queued("new", 12 * minute));
+
+        // a boop exactly as old as the expiry is still sent
+        let expired = outbox.remove_expired(15 * minute, 10);
+        assert_eq!(expired, vec![queued("old", 0)]);
+
+        let ids: Vec<String> = outbox.take_all().iter().map(|boop| boop.id()).collect();
+        assert_eq!(ids, vec!["edge", "new"]);
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut outbox = Outbox::default();
+        outbox.push(queued("a", 1));
+        outbox.push(queued("b", 2));
+
+        assert_eq!(outbox.cancel("a"), Some(queued("a", 1)));
+        assert!(outbox.cancel("a").is_none());
+        assert_eq!(outbox.boops(), &vec![queued("b", 2)]);
+    }
+}
@@ -1,10 +1,25 @@
-use serde::{
-    Deserialize,
-    Serialize
+use {
+    crate::schema::{
+        Migration,
+        Versioned
+    },
+    serde::{
+        Deserialize,
+        Serialize
+    },
+    std::{
+        collections::HashMap,
+        sync::Arc
+    },
+    tokio::sync::Mutex
 };
 
 pub const PARTNERS_FILENAME: &str = "boop.partners.json"; // TODO: change to same directory later
 
+/// Partners by user key, together with their last known online status
+pub type PartnersMap = HashMap<String, (BoopPartner, PartnerOnlineStatus)>;
+pub type PartnersHandle = Arc<Mutex<PartnersMap>>;
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct BoopPartner {
@@ -12,7 +27,23 @@ pub struct BoopPartner {
     user_key: String
 }
 
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum PartnerOnlineStatus {
+    Afk = -1,
+    Unknown = 0,
+    Online = 1
+}
+
+impl Versioned for Vec<BoopPartner> {
+    const MIGRATIONS: &'static [Migration] = &[];
+    const VERSION: u32 = 1;
+}
+
 impl BoopPartner {
+    pub fn new(nickname: String, user_key: String) -> Self {
+        BoopPartner { nickname, user_key }
+    }
+
     pub fn nickname(&self) -> String {
         self.nickname.clone()
     }
@@ -21,3 +52,14 @@ impl BoopPartner {
         self.user_key.clone()
     }
 }
+
+/// Builds the partners map from the saved partner list, all partners start
+/// with an unknown online status
+pub fn build_partners_map(partners: Vec<BoopPartner>) -> PartnersMap {
+    let mut partners_map = HashMap::new();
+    for partner in partners {
+        partners_map.insert(partner.user_key(), (partner, PartnerOnlineStatus::Unknown));
+    }
+
+    partners_map
+}
@@ -0,0 +1,323 @@
+use {
+    crate::{
+        config::CONFIG_FILENAME,
+        error::BoopError,
+        files::{
+            backup_path,
+            get_config_file_path,
+            get_profile_file_path,
+            BACKUP_GENERATIONS
+        },
+        outbox::OUTBOX_FILENAME,
+        partners::PARTNERS_FILENAME,
+        requests::REQUESTS_FILENAME,
+        schema::{
+            Migration,
+            Versioned
+        }
+    },
+    serde::{
+        Deserialize,
+        Serialize
+    },
+    std::{
+        fs,
+        io,
+        path::PathBuf
+    }
+};
+
+pub const PROFILES_FILENAME: &str = "boop.profiles.json";
+
+/// The profile of configs from before profiles existed, its files stay where
+/// they always were
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// A named set of server, credentials, partners and boop requests
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    id:   String, // never changes, names the profile's directory
+    name: String
+}
+
+/// All profiles and which one is in use
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Profiles {
+    active:   String,
+    profiles: Vec<Profile>
+}
+
+/// Where the files of a profile are saved
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProfilePaths {
+    pub config:   PathBuf,
+    pub partners: PathBuf,
+    pub requests: PathBuf,
+    pub outbox:   PathBuf,
+    dir:          Option<PathBuf> // the profile's own directory
+}
+
+impl Profile {
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+impl Default for Profiles {
+    fn default() -> Self {
+        Profiles {
+            active:   String::from(DEFAULT_PROFILE),
+            profiles: vec![Profile {
+                id:   String::from(DEFAULT_PROFILE),
+                name: String::from("Default")
+            }]
+        }
+    }
+}
+
+impl Versioned for Profiles {
+    const MIGRATIONS: &'static [Migration] = &[];
+    const VERSION: u32 = 1;
+}
+
+impl Profiles {
+    pub fn list(&self) -> &Vec<Profile> {
+        &self.profiles
+    }
+
+    pub fn active(&self) -> String {
+        self.active.clone()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.id == id)
+    }
+
+    /// Adds a profile, its id is derived from the name
+    pub fn create(&mut self, name: &str) -> Result<Profile, BoopError> {
+        let name = self.check_name(name, None)?;
+
+        let base = profile_id(&name);
+        let mut id = base.clone();
+        let mut suffix = 2;
+        while self.get(&id).is_some() {
+            id = format!("{}-{}", base, suffix);
+            suffix += 1;
+        }
+
+        let profile = Profile { id, name };
+        self.profiles.push(profile.clone());
+        Ok(profile)
+    }
+
+    pub fn rename(&mut self, id: &str, name: &str) -> Result<(), BoopError> {
+        let name = self.check_name(name, Some(id))?;
+        let profile = self
+            .profiles
+            .iter_mut()
+            .find(|profile| profile.id == id)
+            .ok_or_else(|| unknown_profile(id))?;
+
+        profile.name = name;
+        Ok(())
+    }
+
+    /// Removes a profile, the active one has to be switched away from first
+    pub fn delete(&mut self, id: &str) -> Result<Profile, BoopError> {
+        if id == self.active {
+            return Err(BoopError::Profile(String::from(
+                "the active profile can't be deleted, switch to another one first"
+            )));
+        }
+
+        let index = self
+            .profiles
+            .iter()
+            .position(|profile| profile.id == id)
+            .ok_or_else(|| unknown_profile(id))?;
+        Ok(self.profiles.remove(index))
+    }
+
+    pub fn switch(&mut self, id: &str) -> Result<(), BoopError> {
+        if self.get(id).is_none() {
+            return Err(unknown_profile(id));
+        }
+
+        self.active = String::from(id);
+        Ok(())
+    }
+
+    /// Trimmed name if it's not empty and no other profile has it
+    fn check_name(&self, name: &str, id: Option<&str>) -> Result<String, BoopError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(BoopError::Profile(String::from(
+                "the profile name can't be empty"
+            )));
+        }
+
+        let taken = self
+            .profiles
+            .iter()
+            .any(|profile| Some(profile.id.as_str()) != id && profile.name == name);
+        if taken {
+            return Err(BoopError::Profile(format!(
+                "there already is a profile called \"{}\"",
+                name
+            )));
+        }
+
+        Ok(String::from(name))
+    }
+}
+
+impl ProfilePaths {
+    /// Paths of the profile's files in the config directory, the default
+    /// profile uses the files from before profiles existed
+    pub fn new(id: &str) -> Self {
+        let path = |filename| {
+            if id == DEFAULT_PROFILE {
+                get_config_file_path(filename)
+            } else {
+                get_profile_file_path(id, filename)
+            }
+        };
+
+        let config = path(CONFIG_FILENAME);
+        let dir = if id == DEFAULT_PROFILE {
+            None
+        } else {
+            config.parent().map(PathBuf::from)
+        };
+
+        ProfilePaths {
+            config,
+            partners: path(PARTNERS_FILENAME),
+            requests: path(REQUESTS_FILENAME),
+            outbox: path(OUTBOX_FILENAME),
+            dir
+        }
+    }
+
+    /// Deletes the profile's files, their backups and the profile's directory
+    pub fn remove_files(&self) -> io::Result<()> {
+        for file in [&self.config, &self.partners, &self.requests, &self.outbox] {
+            let backups = (1..=BACKUP_GENERATIONS).map(|generation| backup_path(file, generation));
+            for path in std::iter::once(file.clone()).chain(backups) {
+                match fs::remove_file(&path) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                    _ => {}
+                }
+            }
+        }
+
+        match &self.dir {
+            Some(dir) => fs::remove_dir(dir),
+            None => Ok(())
+        }
+    }
+}
+
+/// File system safe id for a profile name
+fn profile_id(name: &str) -> String {
+    let mut id = String::new();
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            id.push(c);
+        } else if !id.is_empty() && !id.ends_with('-') {
+            id.push('-');
+        }
+    }
+
+    let id = id.trim_end_matches('-');
+    if id.is_empty() {
+        String::from("profile")
+    } else {
+        String::from(id)
+    }
+}
+
+fn unknown_profile(id: &str) -> BoopError {
+    BoopError::Profile(format!("there is no profile \"{}\"", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::BoopError,
+        profiles::{
+            profile_id,
+            Profiles,
+            DEFAULT_PROFILE
+        }
+    };
+
+    #[test]
+    fn test_profile_id() {
+        assert_eq!(profile_id("Work"), "work");
+        assert_eq!(profile_id("  My Relay (old) "), "my-relay-old");
+        assert_eq!(profile_id("../.."), "profile");
+        assert_eq!(profile_id("Über"), "ber");
+    }
+
+    #[test]
+    fn test_create_and_rename() {
+        let mut profiles = Profiles::default();
+        assert_eq!(profiles.active(), DEFAULT_PROFILE);
+
+        let work = profiles.create(" Work ").unwrap();
+        assert_eq!(work.id(), "work");
+        assert_eq!(work.name(), "Work");
+
+        // names are unique, ids as well
+        assert!(matches!(
+            profiles.create("Work"),
+            Err(BoopError::Profile(_))
+        ));
+        assert!(matches!(profiles.create(" "), Err(BoopError::Profile(_))));
+        assert_eq!(profiles.create("work!").unwrap().id(), "work-2");
+
+        // the id stays when renaming
+        profiles.rename("work", "Office").unwrap();
+        assert_eq!(profiles.get("work").unwrap().name(), "Office");
+        assert!(profiles.rename("work", "Office").is_ok());
+        assert!(matches!(
+            profiles.rename("work", "work!"),
+            Err(BoopError::Profile(_))
+        ));
+        assert!(matches!(
+            profiles.rename("home", "Home"),
+            Err(BoopError::Profile(_))
+        ));
+    }
+
+    #[test]
+    fn test_switch_and_delete() {
+        let mut profiles = Profiles::default();
+        profiles.create("Home").unwrap();
+
+        assert!(matches!(
+            profiles.switch("work"),
+            Err(BoopError::Profile(_))
+        ));
+        profiles.switch("home").unwrap();
+        assert_eq!(profiles.active(), "home");
+
+        // the active profile stays
+        assert!(matches!(
+            profiles.delete("home"),
+            Err(BoopError::Profile(_))
+        ));
+        assert_eq!(
+            profiles.delete(DEFAULT_PROFILE).unwrap().id(),
+            DEFAULT_PROFILE
+        );
+        assert_eq!(profiles.list().len(), 1);
+    }
+}
@@ -0,0 +1,214 @@
+use {
+    crate::error::BoopError,
+    std::{
+        collections::VecDeque,
+        sync::{
+            Arc,
+            Mutex
+        }
+    },
+    tokio::sync::Notify
+};
+
+/// What happens to a message sent into a full queue
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OverflowPolicy {
+    DropOldest, // the oldest queued message makes room for the new one
+    Reject      // the new message is refused with `BoopError::SinkFull`
+}
+
+struct Shared<T> {
+    state:    Mutex<State<T>>,
+    notify:   Notify,
+    capacity: usize,
+    policy:   OverflowPolicy
+}
+
+struct State<T> {
+    messages:  VecDeque<T>,
+    senders:   usize,
+    receiving: bool // the receiver wasn't dropped yet
+}
+
+/// Sending half of a bounded queue, can be cloned
+pub struct BoundedSender<T> {
+    shared: Arc<Shared<T>>
+}
+
+/// Receiving half of a bounded queue
+pub struct BoundedReceiver<T> {
+    shared: Arc<Shared<T>>
+}
+
+/// Creates a queue that holds at most `capacity` messages, `policy` decides
+/// what happens to messages sent while it's full
+pub fn bounded<T>(
+    capacity: usize,
+    policy: OverflowPolicy
+) -> (BoundedSender<T>, BoundedReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            messages:  VecDeque::with_capacity(capacity),
+            senders:   1,
+            receiving: true
+        }),
+        notify: Notify::new(),
+        capacity,
+        policy
+    });
+
+    (
+        BoundedSender {
+            shared: shared.clone()
+        },
+        BoundedReceiver { shared }
+    )
+}
+
+impl<T> BoundedSender<T> {
+    /// Queues a message. Fails with `BoopError::SinkClosed` if the receiver
+    /// is gone and with `BoopError::SinkFull` if the queue is full and
+    /// rejects messages.
+    pub fn send(&self, message: T) -> Result<(), BoopError> {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            if !state.receiving {
+                return Err(BoopError::SinkClosed);
+            }
+
+            if state.messages.len() >= self.shared.capacity {
+                if self.shared.policy == OverflowPolicy::Reject {
+                    return Err(BoopError::SinkFull);
+                }
+                warn!("queue is full, dropped the oldest message");
+                state.messages.pop_front();
+            }
+            state.messages.push_back(message);
+        }
+
+        self.shared.notify.notify_one();
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        !self.shared.state.lock().unwrap().receiving
+    }
+
+    /// Whether both senders belong to the same queue
+    pub fn same_channel(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+}
+
+impl<T> Clone for BoundedSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        BoundedSender {
+            shared: self.shared.clone()
+        }
+    }
+}
+
+impl<T> Drop for BoundedSender<T> {
+    fn drop(&mut self) {
+        let last = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.senders -= 1;
+            state.senders == 0
+        };
+
+        // wake the receiver so it sees the queue was closed
+        if last {
+            self.shared.notify.notify_one();
+        }
+    }
+}
+
+impl<T> BoundedReceiver<T> {
+    /// Waits for the next message. Returns `None` once all senders are gone
+    /// and the queue is empty.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if let Some(message) = state.messages.pop_front() {
+                    return Some(message);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    /// Takes the next message if there is one, without waiting
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.shared.state.lock().unwrap().messages.pop_front()
+    }
+}
+
+impl<T> Drop for BoundedReceiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.receiving = false;
+        state.messages.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::BoopError,
+        queue::{
+            bounded,
+            OverflowPolicy
+        }
+    };
+
+    #[tokio::test]
+    async fn test_reject_when_full() {
+        let (tx, mut rx) = bounded(2, OverflowPolicy::Reject);
+        assert_eq!(tx.send(1), Ok(()));
+        assert_eq!(tx.send(2), Ok(()));
+        assert_eq!(tx.send(3), Err(BoopError::SinkFull));
+
+        // taking a message makes room again
+        assert_eq!(rx.recv().await, Some(1));
+        assert_eq!(tx.send(4), Ok(()));
+        assert_eq!(rx.try_recv(), Some(2));
+        assert_eq!(rx.try_recv(), Some(4));
+        assert_eq!(rx.try_recv(), None);
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_when_full() {
+        let (tx, mut rx) = bounded(2, OverflowPolicy::DropOldest);
+        for message in 1..=4 {
+            assert_eq!(tx.send(message), Ok(()));
+        }
+        assert_eq!(rx.recv().await, Some(3));
+        assert_eq!(rx.recv().await, Some(4));
+    }
+
+    #[tokio::test]
+    async fn test_closed_ends() {
+        let (tx, mut rx) = bounded(2, OverflowPolicy::Reject);
+        let tx_clone = tx.clone();
+        tx.send(1).unwrap();
+        drop(tx);
+
+        // a clone keeps the queue open
+        assert_eq!(rx.recv().await, Some(1));
+        tokio::spawn(async move {
+            tx_clone.send(2).unwrap();
+        });
+        assert_eq!(rx.recv().await, Some(2));
+        assert_eq!(rx.recv().await, None);
+
+        let (tx, rx) = bounded(2, OverflowPolicy::Reject);
+        drop(rx);
+        assert!(tx.is_closed());
+        assert_eq!(tx.send(1), Err(BoopError::SinkClosed));
+    }
+}
@@ -0,0 +1,208 @@
+use {
+    crate::message::BoopContent,
+    std::{
+        collections::HashMap,
+        sync::Arc
+    },
+    tokio::sync::Mutex
+};
+
+/// Boops in a row with one partner before the limit kicks in, by default
+pub const DEFAULT_BOOP_BURST: u32 = 5;
+/// Seconds until the limit allows another boop, by default
+pub const DEFAULT_BOOP_REFILL: u32 = 10;
+
+/// Boops in a row received from one partner before they're collapsed. Fixed,
+/// so turning off the limit for sent boops doesn't open the door to floods.
+pub const INCOMING_BOOP_BURST: u32 = 5;
+/// Seconds until another received boop is shown on its own
+pub const INCOMING_BOOP_REFILL: u32 = 10;
+
+pub type LimiterHandle = Arc<Mutex<BoopLimiter>>;
+pub type FloodHandle = Arc<Mutex<FloodGuard>>;
+
+/// Token bucket of one partner, every boop takes a token
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens:  f64,
+    updated: i64 // unix timestamp in millis
+}
+
+/// Token bucket limiter with a bucket per partner. A bucket holds up to
+/// `burst` tokens and gets a token back every `refill` seconds.
+#[derive(Debug, Default)]
+pub struct BoopLimiter {
+    buckets: HashMap<String, TokenBucket>
+}
+
+impl BoopLimiter {
+    /// Takes a token for a boop with `partner_key`. Fails with the number of
+    /// milliseconds until the next token if the bucket is empty. A `burst` of
+    /// 0 turns the limit off.
+    pub fn try_take(
+        &mut self,
+        partner_key: &str,
+        now: i64,
+        burst: u32,
+        refill: u32
+    ) -> Result<(), i64> {
+        if burst == 0 {
+            return Ok(());
+        }
+
+        let burst = f64::from(burst);
+        let refill_ms = f64::from(refill) * 1000.0;
+        let bucket = self
+            .buckets
+            .entry(String::from(partner_key))
+            .or_insert(TokenBucket {
+                tokens:  burst,
+                updated: now
+            });
+
+        let elapsed = (now - bucket.updated).max(0) as f64;
+        bucket.tokens = if refill_ms > 0.0 {
+            (bucket.tokens + elapsed / refill_ms).min(burst)
+        } else {
+            burst
+        };
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) * refill_ms).ceil() as i64)
+        }
+    }
+}
+
+/// What happens to a received boop
+#[derive(Debug, PartialEq)]
+pub enum Incoming {
+    Show(BoopContent), // within the limit
+    Collapse(i64),     // first boop over the limit, the summary is due in that many millis
+    Counted            // added to the summary that's already due
+}
+
+/// Collapses floods of received boops into one summary per partner. The
+/// summary is shown once the limit allows the next boop and takes its token.
+#[derive(Debug, Default)]
+pub struct FloodGuard {
+    limiter: BoopLimiter,
+    pending: HashMap<String, (u32, BoopContent)> // boops and latest content by partner
+}
+
+impl FloodGuard {
+    pub fn receive(&mut self, partner_key: &str, content: BoopContent, now: i64) -> Incoming {
+        if let Some(pending) = self.pending.get_mut(partner_key) {
+            pending.0 += 1;
+            pending.1 = content;
+            return Incoming::Counted;
+        }
+
+        match self
+            .limiter
+            .try_take(partner_key, now, INCOMING_BOOP_BURST, INCOMING_BOOP_REFILL)
+        {
+            Ok(_) => Incoming::Show(content),
+            Err(wait) => {
+                self.pending.insert(String::from(partner_key), (1, content));
+                Incoming::Collapse(wait)
+            }
+        }
+    }
+
+    /// Takes the summary of a partner's flood, or fails with the milliseconds
+    /// until the limit allows it
+    pub fn summary(
+        &mut self,
+        partner_key: &str,
+        now: i64
+    ) -> Result<Option<(u32, BoopContent)>, i64> {
+        if !self.pending.contains_key(partner_key) {
+            return Ok(None);
+        }
+
+        self.limiter
+            .try_take(partner_key, now, INCOMING_BOOP_BURST, INCOMING_BOOP_REFILL)?;
+        Ok(self.pending.remove(partner_key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        message::BoopContent,
+        ratelimit::{
+            BoopLimiter,
+            FloodGuard,
+            Incoming,
+            INCOMING_BOOP_BURST
+        }
+    };
+
+    #[test]
+    fn test_burst_and_refill() {
+        let mut limiter = BoopLimiter::default();
+        for _ in 0..3 {
+            assert_eq!(limiter.try_take("alice", 0, 3, 10), Ok(()));
+        }
+        assert_eq!(limiter.try_take("alice", 0, 3, 10), Err(10_000));
+
+        // every partner has their own bucket
+        assert_eq!(limiter.try_take("bob", 0, 3, 10), Ok(()));
+
+        // half a token after 5 seconds isn't enough
+        assert_eq!(limiter.try_take("alice", 5_000, 3, 10), Err(5_000));
+        assert_eq!(limiter.try_take("alice", 10_000, 3, 10), Ok(()));
+        assert!(limiter.try_take("alice", 10_000, 3, 10).is_err());
+
+        // the bucket never holds more than the burst
+        for _ in 0..3 {
+            assert_eq!(limiter.try_take("alice", 1_000_000, 3, 10), Ok(()));
+        }
+        assert!(limiter.try_take("alice", 1_000_000, 3, 10).is_err());
+    }
+
+    #[test]
+    fn test_limit_turned_off() {
+        let mut limiter = BoopLimiter::default();
+        for _ in 0..100 {
+            assert_eq!(limiter.try_take("alice", 0, 0, 10), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_flood_collapsed() {
+        let mut floods = FloodGuard::default();
+        let content = BoopContent::default();
+        for _ in 0..INCOMING_BOOP_BURST {
+            assert_eq!(
+                floods.receive("alice", content.clone(), 0),
+                Incoming::Show(content.clone())
+            );
+        }
+
+        assert_eq!(
+            floods.receive("alice", content.clone(), 0),
+            Incoming::Collapse(10_000)
+        );
+        assert_eq!(
+            floods.receive("alice", content.clone(), 1_000),
+            Incoming::Counted
+        );
+        assert_eq!(floods.summary("bob", 1_000), Ok(None));
+
+        // the summary waits for a token and takes it
+        assert_eq!(floods.summary("alice", 5_000), Err(5_000));
+        assert_eq!(
+            floods.summary("alice", 10_000),
+            Ok(Some((2, content.clone())))
+        );
+        assert_eq!(
+            floods.receive("alice", content.clone(), 10_000),
+            Incoming::Collapse(10_000)
+        );
+    }
+}
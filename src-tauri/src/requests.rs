@@ -0,0 +1,249 @@
+use {
+    crate::{
+        files::save_file,
+        schema::{
+            Migration,
+            Versioned
+        }
+    },
+    chrono::Utc,
+    serde::{
+        Deserialize,
+        Serialize
+    },
+    std::{
+        io,
+        path::PathBuf,
+        sync::Arc,
+        time::Duration
+    },
+    tokio::sync::Mutex
+};
+
+pub const REQUESTS_FILENAME: &str = "boop.requests.json";
+
+/// Requests kept in the inbox, the one seen least recently makes room for a
+/// new one
+pub const MAX_REQUESTS: usize = 100;
+/// Seconds repeated boops by the same stranger are collected before the inbox
+/// is saved
+const SAVE_DELAY: u64 = 10;
+
+pub type RequestsHandle = Arc<Mutex<RequestsInbox>>;
+
+/// Boops by someone who isn't registered as a partner (yet)
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BoopRequest {
+    user_key:   String,
+    first_seen: i64, // unix timestamp in millis
+    last_seen:  i64, // unix timestamp in millis
+    count:      u32
+}
+
+/// Pending boop requests and the keys that aren't allowed to send any
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestsInbox {
+    requests:       Vec<BoopRequest>,
+    blocked:        Vec<String>,
+    #[serde(skip)]
+    save_scheduled: bool
+}
+
+/// What became of a boop by someone who isn't a partner
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RequestOutcome {
+    Blocked,
+    New,      // a new request in the inbox
+    Repeated  // counted in the sender's existing request
+}
+
+impl BoopRequest {
+    pub fn user_key(&self) -> String {
+        self.user_key.clone()
+    }
+
+    pub fn first_seen(&self) -> i64 {
+        self.first_seen
+    }
+
+    pub fn last_seen(&self) -> i64 {
+        self.last_seen
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+}
+
+impl Versioned for RequestsInbox {
+    const MIGRATIONS: &'static [Migration] = &[];
+    const VERSION: u32 = 1;
+}
+
+impl RequestsInbox {
+    pub fn requests(&self) -> &Vec<BoopRequest> {
+        &self.requests
+    }
+
+    pub fn is_blocked(&self, user_key: &str) -> bool {
+        self.blocked.iter().any(|blocked| blocked == user_key)
+    }
+
+    /// Adds a boop by `user_key` to the inbox, boops by the same key are merged
+    /// into one request. A full inbox drops the request seen least recently.
+    pub fn record(&mut self, user_key: &str, now: i64) -> RequestOutcome {
+        if self.is_blocked(user_key) {
+            return RequestOutcome::Blocked;
+        }
+
+        if let Some(request) = self.requests.iter_mut().find(|r| r.user_key == user_key) {
+            request.last_seen = now;
+            request.count += 1;
+            return RequestOutcome::Repeated;
+        }
+
+        if self.requests.len() >= MAX_REQUESTS {
+            let stalest = self
+                .requests
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, request)| request.last_seen)
+                .map(|(index, _)| index);
+            if let Some(index) = stalest {
+                let dropped = self.requests.remove(index);
+                debug!("inbox is full, dropped request by {}", dropped.user_key);
+            }
+        }
+        self.requests.push(BoopRequest {
+            user_key:   String::from(user_key),
+            first_seen: now,
+            last_seen:  now,
+            count:      1
+        });
+        RequestOutcome::New
+    }
+
+    /// Removes the request by `user_key` from the inbox
+    pub fn remove(&mut self, user_key: &str) -> Option<BoopRequest> {
+        let index = self.requests.iter().position(|r| r.user_key == user_key)?;
+        Some(self.requests.remove(index))
+    }
+
+    /// Drops the request by `user_key` and ignores all further boops by them
+    pub fn block(&mut self, user_key: &str) {
+        let _ = self.remove(user_key);
+        if !self.is_blocked(user_key) {
+            self.blocked.push(String::from(user_key));
+        }
+    }
+}
+
+/// Puts a boop by someone who isn't a partner into the inbox and saves it.
+/// Repeated boops by the same sender are saved together after `SAVE_DELAY`,
+/// so a flood doesn't write the file for every boop. Returns whether the
+/// request should be shown (the sender isn't blocked).
+pub async fn record_boop_request(
+    requests_handle: &RequestsHandle,
+    requests_file: &PathBuf,
+    user_key: &str
+) -> io::Result<bool> {
+    let mut inbox = requests_handle.lock().await;
+    match inbox.record(user_key, Utc::now().timestamp_millis()) {
+        RequestOutcome::Blocked => {
+            debug!("dropped boop by blocked key {}", user_key);
+            Ok(false)
+        }
+        RequestOutcome::New => {
+            save_file(requests_file, &*inbox).await?;
+            Ok(true)
+        }
+        RequestOutcome::Repeated => {
+            if !inbox.save_scheduled {
+                inbox.save_scheduled = true;
+                tokio::spawn(save_later(
+                    Arc::clone(requests_handle),
+                    requests_file.clone()
+                ));
+            }
+            Ok(true)
+        }
+    }
+}
+
+async fn save_later(requests_handle: RequestsHandle, requests_file: PathBuf) {
+    tokio::time::sleep(Duration::from_secs(SAVE_DELAY)).await;
+
+    let mut inbox = requests_handle.lock().await;
+    inbox.save_scheduled = false;
+    if let Err(err) = save_file(&requests_file, &*inbox).await {
+        error!("failed to save boop requests to disk: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::requests::{
+        RequestOutcome,
+        RequestsInbox,
+        MAX_REQUESTS
+    };
+
+    #[test]
+    fn test_record_merges_requests() {
+        let mut inbox = RequestsInbox::default();
+        assert_eq!(inbox.record("alice", 1), RequestOutcome::New);
+        assert_eq!(inbox.record("bob", 2), RequestOutcome::New);
+        assert_eq!(inbox.record("alice", 3), RequestOutcome::Repeated);
+
+        assert_eq!(inbox.requests().len(), 2);
+        let alice = &inbox.requests()[0];
+        assert_eq!(alice.user_key(), "alice");
+        assert_eq!(alice.first_seen(), 1);
+        assert_eq!(alice.last_seen(), 3);
+        assert_eq!(alice.count(), 2);
+    }
+
+    #[test]
+    fn test_blocked_keys_are_dropped() {
+        let mut inbox = RequestsInbox::default();
+        assert_eq!(inbox.record("mallory", 1), RequestOutcome::New);
+
+        inbox.block("mallory");
+        assert!(inbox.requests().is_empty());
+        assert_eq!(inbox.record("mallory", 2), RequestOutcome::Blocked);
+        assert!(inbox.requests().is_empty());
+
+        // blocking twice doesn't add a second entry
+        inbox.block("mallory");
+        assert_eq!(inbox.blocked.len(), 1);
+    }
+
+    #[test]
+    fn test_full_inbox_drops_stalest() {
+        let mut inbox = RequestsInbox::default();
+        for i in 0..MAX_REQUESTS {
+            inbox.record(&format!("stranger{}", i), i as i64);
+        }
+        // the first one booped again, so the second one is the stalest
+        inbox.record("stranger0", 1000);
+
+        assert_eq!(inbox.record("newcomer", 1001), RequestOutcome::New);
+        assert_eq!(inbox.requests().len(), MAX_REQUESTS);
+        let keys: Vec<String> = inbox.requests().iter().map(|r| r.user_key()).collect();
+        assert!(keys.contains(&String::from("stranger0")));
+        assert!(!keys.contains(&String::from("stranger1")));
+        assert!(keys.contains(&String::from("newcomer")));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut inbox = RequestsInbox::default();
+        inbox.record("alice", 1);
+
+        assert_eq!(inbox.remove("alice").unwrap().user_key(), "alice");
+        assert!(inbox.remove("alice").is_none());
+        assert!(!inbox.is_blocked("alice"));
+    }
+}
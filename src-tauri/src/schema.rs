@@ -0,0 +1,213 @@
+use {
+    serde::{
+        de::DeserializeOwned,
+        Serialize
+    },
+    serde_json::{
+        json,
+        Value
+    },
+    std::fmt::Display
+};
+
+// Saved files are wrapped in an envelope with the schema version of their
+// data: `{ "version": 2, "data": ... }`. Files from before versioning contain
+// just the data and count as version 1. Older data is upgraded one version
+// at a time by the type's migrations before it's deserialized.
+
+/// Upgrades data from one schema version to the next
+pub type Migration = fn(Value) -> Result<Value, String>;
+
+/// Data that is saved with a schema version
+pub trait Versioned: Serialize + DeserializeOwned + Default {
+    /// Current schema version, starting at 1
+    const VERSION: u32;
+
+    /// `MIGRATIONS[i]` upgrades version `i + 1` to version `i + 2`, so there
+    /// is one migration for every version before the current one
+    const MIGRATIONS: &'static [Migration];
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SchemaError {
+    NewerVersion(u32),
+    Migration(u32, String), // version that couldn't be upgraded, reason
+    Invalid(String)
+}
+
+impl Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaError::NewerVersion(version) => write!(
+                f,
+                "The data was saved by a newer version (schema version {}).",
+                version
+            ),
+            SchemaError::Migration(version, reason) => write!(
+                f,
+                "The data couldn't be upgraded from schema version {}: {}",
+                version, reason
+            ),
+            SchemaError::Invalid(reason) => write!(f, "The data is invalid: {}", reason)
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// Wraps the data in an envelope with the current schema version
+pub fn to_versioned<T: Versioned>(object: &T) -> Result<Value, serde_json::Error> {
+    Ok(json!({
+        "version": T::VERSION,
+        "data": serde_json::to_value(object)?
+    }))
+}
+
+/// Reads versioned (or unversioned) data and upgrades it to the current
+/// schema version
+pub fn from_versioned<T: Versioned>(value: Value) -> Result<T, SchemaError> {
+    upgrade(value).map(|(data, _)| data)
+}
+
+/// Same as `from_versioned`, but also returns the schema version the data was
+/// saved with
+pub fn upgrade<T: Versioned>(value: Value) -> Result<(T, u32), SchemaError> {
+    let (saved_version, mut data) = split_envelope(value)?;
+    let mut version = saved_version;
+    if version > T::VERSION {
+        return Err(SchemaError::NewerVersion(version));
+    }
+
+    while version < T::VERSION {
+        let migrate = T::MIGRATIONS.get(version as usize - 1).ok_or_else(|| {
+            SchemaError::Migration(version, String::from("no migration available"))
+        })?;
+        data = migrate(data).map_err(|reason| SchemaError::Migration(version, reason))?;
+        version += 1;
+        info!("upgraded data to schema version {}", version);
+    }
+
+    let data = serde_json::from_value(data).map_err(|err| SchemaError::Invalid(err.to_string()))?;
+    Ok((data, saved_version))
+}
+
+/// Splits the envelope into version and data, unversioned data is version 1
+fn split_envelope(value: Value) -> Result<(u32, Value), SchemaError> {
+    let is_envelope = value.as_object().map_or(false, |object| {
+        object.len() == 2 && object.contains_key("version") && object.contains_key("data")
+    });
+    if !is_envelope {
+        return Ok((1, value));
+    }
+
+    let version = value["version"]
+        .as_u64()
+        .filter(|version| *version >= 1 && *version <= u32::MAX as u64)
+        .ok_or_else(|| SchemaError::Invalid(String::from("invalid schema version")))?;
+
+    match value {
+        Value::Object(mut object) => Ok((version as u32, object.remove("data").unwrap())),
+        _ => unreachable!()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::schema::{
+            from_versioned,
+            to_versioned,
+            Migration,
+            SchemaError,
+            Versioned
+        },
+        serde::{
+            Deserialize,
+            Serialize
+        },
+        serde_json::{
+            json,
+            Value
+        }
+    };
+
+    // version 1: { "name": "..." }
+    // version 2: { "fullName": "..." }
+    // version 3: { "fullName": "...", "tags": [] }
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+    #[serde(rename_all = "camelCase")]
+    struct Sample {
+        full_name: String,
+        tags:      Vec<String>
+    }
+
+    fn rename_name(mut data: Value) -> Result<Value, String> {
+        let name = data
+            .as_object_mut()
+            .and_then(|object| object.remove("name"))
+            .ok_or_else(|| String::from("name is missing"))?;
+        data["fullName"] = name;
+        Ok(data)
+    }
+
+    fn add_tags(mut data: Value) -> Result<Value, String> {
+        data["tags"] = json!([]);
+        Ok(data)
+    }
+
+    impl Versioned for Sample {
+        const MIGRATIONS: &'static [Migration] = &[rename_name, add_tags];
+        const VERSION: u32 = 3;
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let sample = Sample {
+            full_name: String::from("foo"),
+            tags:      vec![String::from("bar")]
+        };
+
+        let versioned = to_versioned(&sample).unwrap();
+        assert_eq!(versioned["version"], 3);
+        assert_eq!(from_versioned::<Sample>(versioned), Ok(sample));
+    }
+
+    #[test]
+    fn test_migration_chain() {
+        let expected = Sample {
+            full_name: String::from("foo"),
+            tags:      Vec::new()
+        };
+
+        // unversioned data is version 1
+        assert_eq!(from_versioned(json!({ "name": "foo" })), Ok(expected));
+        assert_eq!(
+            from_versioned::<Sample>(json!({ "version": 2, "data": { "fullName": "foo" } }))
+                .unwrap()
+                .tags,
+            Vec::<String>::new()
+        );
+
+        // a failing migration names the version it started from
+        assert_eq!(
+            from_versioned::<Sample>(json!({ "version": 1, "data": { "title": "foo" } })),
+            Err(SchemaError::Migration(1, String::from("name is missing")))
+        );
+    }
+
+    #[test]
+    fn test_unknown_versions() {
+        assert_eq!(
+            from_versioned::<Sample>(json!({ "version": 4, "data": {} })),
+            Err(SchemaError::NewerVersion(4))
+        );
+        assert!(matches!(
+            from_versioned::<Sample>(json!({ "version": 0, "data": {} })),
+            Err(SchemaError::Invalid(_))
+        ));
+        assert!(matches!(
+            from_versioned::<Sample>(json!({ "version": "two", "data": {} })),
+            Err(SchemaError::Invalid(_))
+        ));
+    }
+}
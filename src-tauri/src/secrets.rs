@@ -0,0 +1,402 @@
+use {
+    crate::{
+        auth::{
+            from_hex,
+            to_hex
+        },
+        error::BoopError
+    },
+    ring::{
+        aead,
+        pbkdf2,
+        rand::{
+            SecureRandom,
+            SystemRandom
+        }
+    },
+    serde::{
+        Deserialize,
+        Serialize
+    },
+    std::{
+        fs,
+        io::{
+            self,
+            Write
+        },
+        num::NonZeroU32,
+        path::{
+            Path,
+            PathBuf
+        },
+        sync::Arc
+    },
+    tokio::sync::Mutex
+};
+
+// Secrets (the login password) are encrypted with ChaCha20-Poly1305. The key
+// is either a random key in a file only the user can read, or derived from a
+// master passphrase with PBKDF2-HMAC-SHA256. Only the salt is stored for the
+// latter, so the passphrase has to be entered again after every start. The
+// derived key is kept for the session, so PBKDF2 only runs when unlocking (on
+// a blocking thread) and secrets sealed later reuse its salt.
+
+pub const KEY_FILENAME: &str = "boop.key";
+
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const PASSPHRASE_ITERATIONS: u32 = 100_000;
+
+pub type KeyHandle = Arc<Mutex<KeySource>>;
+
+/// Where the keys for encrypted secrets come from
+#[derive(Clone)]
+pub struct KeySource {
+    key_file:       PathBuf,
+    passphrase_key: Option<DerivedKey> // set once the user unlocked the secrets
+}
+
+/// Key derived from the master passphrase with a salt
+#[derive(Clone)]
+struct DerivedKey {
+    salt: Vec<u8>,
+    key:  [u8; KEY_LEN]
+}
+
+/// An encrypted secret as stored in the config. Secrets with a salt are
+/// encrypted with the master passphrase, all others with the key file.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptedSecret {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    salt:       Option<String>, // hex
+    nonce:      String, // hex
+    ciphertext: String  // hex, including the tag
+}
+
+impl KeySource {
+    pub fn new(key_file: PathBuf) -> Self {
+        KeySource {
+            key_file,
+            passphrase_key: None
+        }
+    }
+
+    /// Key source with the master passphrase entered, its key is derived for
+    /// `salt` (the salt of the saved secret) or for a new salt. PBKDF2 runs on
+    /// a blocking thread.
+    pub async fn with_passphrase(
+        &self,
+        passphrase: String,
+        salt: Option<Vec<u8>>
+    ) -> Result<Self, BoopError> {
+        let salt = match salt {
+            Some(salt) => salt,
+            None => {
+                let mut salt = vec![0u8; SALT_LEN];
+                fill_random(&SystemRandom::new(), &mut salt)?;
+                salt
+            }
+        };
+
+        let derived = tokio::task::spawn_blocking(move || {
+            let key = derive_key(&passphrase, &salt);
+            DerivedKey { salt, key }
+        })
+        .await
+        .map_err(|err| BoopError::Credentials(format!("the key derivation failed: {}", err)))?;
+
+        Ok(KeySource {
+            key_file:       self.key_file.clone(),
+            passphrase_key: Some(derived)
+        })
+    }
+
+    /// Key source without the master passphrase
+    pub fn without_passphrase(&self) -> Self {
+        KeySource::new(self.key_file.clone())
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.passphrase_key.is_some()
+    }
+
+    /// Key of the master passphrase for a secret sealed with `salt`. A secret
+    /// with another salt needs the passphrase again.
+    fn passphrase_key(&self, salt: &[u8]) -> Result<[u8; KEY_LEN], BoopError> {
+        match &self.passphrase_key {
+            Some(derived) if derived.salt == salt => Ok(derived.key),
+            _ => Err(BoopError::CredentialsLocked)
+        }
+    }
+
+    fn file_key(&self, create: bool) -> Result<[u8; KEY_LEN], BoopError> {
+        let read = if create {
+            read_or_create_key_file(&self.key_file)
+        } else {
+            read_key_file(&self.key_file)
+        };
+
+        read.map_err(|err| {
+            BoopError::Credentials(format!(
+                "failed to read the key file {}: {}",
+                self.key_file.to_string_lossy(),
+                err
+            ))
+        })
+    }
+}
+
+impl EncryptedSecret {
+    /// Encrypts a secret with the master passphrase (which has to be entered)
+    /// or with the key file (which is created if it doesn't exist yet)
+    pub fn seal(
+        secret: &str,
+        keys: &KeySource,
+        with_passphrase: bool
+    ) -> Result<EncryptedSecret, BoopError> {
+        let rng = SystemRandom::new();
+
+        let (salt, key) = if with_passphrase {
+            let derived = keys
+                .passphrase_key
+                .as_ref()
+                .ok_or(BoopError::CredentialsLocked)?;
+            (Some(to_hex(&derived.salt)), derived.key)
+        } else {
+            (None, keys.file_key(true)?)
+        };
+
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        fill_random(&rng, &mut nonce)?;
+
+        let mut in_out = secret.as_bytes().to_vec();
+        aead_key(&key)
+            .seal_in_place_append_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::empty(),
+                &mut in_out
+            )
+            .map_err(|_| BoopError::Credentials(String::from("encryption failed")))?;
+
+        Ok(EncryptedSecret {
+            salt,
+            nonce: to_hex(&nonce),
+            ciphertext: to_hex(&in_out)
+        })
+    }
+
+    pub fn open(&self, keys: &KeySource) -> Result<String, BoopError> {
+        let key = match &self.salt {
+            Some(salt) => keys.passphrase_key(&from_hex(salt).ok_or_else(malformed)?)?,
+            None => keys.file_key(false)?
+        };
+
+        let nonce = from_hex(&self.nonce)
+            .and_then(|nonce| aead::Nonce::try_assume_unique_for_key(&nonce).ok())
+            .ok_or_else(malformed)?;
+        let mut in_out = from_hex(&self.ciphertext).ok_or_else(malformed)?;
+
+        let plaintext = aead_key(&key)
+            .open_in_place(nonce, aead::Aad::empty(), &mut in_out)
+            .map_err(|_| {
+                BoopError::Credentials(String::from(if self.salt.is_some() {
+                    "wrong master passphrase"
+                } else {
+                    "the key file doesn't match the saved password"
+                }))
+            })?;
+
+        String::from_utf8(plaintext.to_vec()).map_err(|_| malformed())
+    }
+
+    pub fn uses_passphrase(&self) -> bool {
+        self.salt.is_some()
+    }
+
+    /// Salt of the master passphrase key, `None` for secrets sealed with the
+    /// key file
+    pub fn salt(&self) -> Option<Vec<u8>> {
+        self.salt.as_ref().and_then(|salt| from_hex(salt))
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; KEY_LEN] {
+    let mut key = [0u8; KEY_LEN];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PASSPHRASE_ITERATIONS).unwrap(),
+        salt,
+        passphrase.as_bytes(),
+        &mut key
+    );
+    key
+}
+
+fn aead_key(key: &[u8; KEY_LEN]) -> aead::LessSafeKey {
+    // can't fail, the key has the right length
+    aead::LessSafeKey::new(aead::UnboundKey::new(&aead::CHACHA20_POLY1305, key).unwrap())
+}
+
+fn fill_random(rng: &SystemRandom, buf: &mut [u8]) -> Result<(), BoopError> {
+    rng.fill(buf)
+        .map_err(|_| BoopError::Credentials(String::from("no secure random numbers available")))
+}
+
+fn malformed() -> BoopError {
+    BoopError::Credentials(String::from("the saved password is damaged"))
+}
+
+fn read_key_file(path: &Path) -> io::Result<[u8; KEY_LEN]> {
+    let contents = fs::read(path)?;
+    if contents.len() != KEY_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "key file has the wrong length"
+        ));
+    }
+
+    let mut key = [0u8; KEY_LEN];
+    key.copy_from_slice(&contents);
+    Ok(key)
+}
+
+fn read_or_create_key_file(path: &Path) -> io::Result<[u8; KEY_LEN]> {
+    match read_key_file(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        res => return res
+    }
+
+    let mut contents = [0u8; KEY_LEN];
+    SystemRandom::new()
+        .fill(&mut contents)
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "no secure random numbers available"))?;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        // only readable by the user
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    file.write_all(&contents)?;
+    file.sync_all()?;
+    info!("created key file {}", path.to_string_lossy());
+
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{
+            error::BoopError,
+            secrets::{
+                EncryptedSecret,
+                KeySource
+            }
+        },
+        std::{
+            env,
+            fs,
+            path::PathBuf
+        }
+    };
+
+    fn temp_key_file(name: &str) -> PathBuf {
+        let file = env::temp_dir().join(format!("boop-key-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&file);
+        file
+    }
+
+    #[test]
+    fn test_key_file_round_trip() {
+        let key_file = temp_key_file("round-trip");
+        let keys = KeySource::new(key_file.clone());
+
+        let sealed = EncryptedSecret::seal("my \"secret\" password", &keys, false).unwrap();
+        assert!(!sealed.uses_passphrase());
+        assert!(!sealed.ciphertext.contains("secret"));
+        assert_eq!(sealed.open(&keys).unwrap(), "my \"secret\" password");
+
+        // the same secret encrypts differently every time
+        let again = EncryptedSecret::seal("my \"secret\" password", &keys, false).unwrap();
+        assert_ne!(again.ciphertext, sealed.ciphertext);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&key_file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // another key file can't open it
+        fs::remove_file(&key_file).unwrap();
+        EncryptedSecret::seal("other", &keys, false).unwrap();
+        assert!(matches!(sealed.open(&keys), Err(BoopError::Credentials(_))));
+
+        let _ = fs::remove_file(&key_file);
+    }
+
+    #[test]
+    fn test_missing_key_file() {
+        let keys = KeySource::new(temp_key_file("missing"));
+        let sealed = EncryptedSecret {
+            salt:       None,
+            nonce:      String::from("000000000000000000000000"),
+            ciphertext: String::from("00")
+        };
+
+        // opening never creates a new key
+        assert!(matches!(sealed.open(&keys), Err(BoopError::Credentials(_))));
+        assert!(!keys.key_file.exists());
+    }
+
+    #[tokio::test]
+    async fn test_passphrase() {
+        let keys = KeySource::new(temp_key_file("passphrase"));
+        assert_eq!(
+            EncryptedSecret::seal("secret", &keys, true),
+            Err(BoopError::CredentialsLocked)
+        );
+
+        let unlocked = keys
+            .with_passphrase(String::from("correct horse"), None)
+            .await
+            .unwrap();
+        let sealed = EncryptedSecret::seal("secret", &unlocked, true).unwrap();
+        assert!(sealed.uses_passphrase());
+        assert_eq!(sealed.open(&unlocked).unwrap(), "secret");
+
+        // sealing again reuses the derived key
+        let again = EncryptedSecret::seal("other secret", &unlocked, true).unwrap();
+        assert_eq!(again.salt(), sealed.salt());
+        assert_eq!(again.open(&unlocked).unwrap(), "other secret");
+
+        // unlocking for the saved salt opens it
+        let reopened = keys
+            .with_passphrase(String::from("correct horse"), sealed.salt())
+            .await
+            .unwrap();
+        assert_eq!(sealed.open(&reopened).unwrap(), "secret");
+
+        // locked, wrong passphrase or unlocked for another salt
+        assert_eq!(sealed.open(&keys), Err(BoopError::CredentialsLocked));
+        let wrong = keys
+            .with_passphrase(String::from("battery staple"), sealed.salt())
+            .await
+            .unwrap();
+        assert!(matches!(
+            sealed.open(&wrong),
+            Err(BoopError::Credentials(_))
+        ));
+        let other_salt = keys
+            .with_passphrase(String::from("correct horse"), None)
+            .await
+            .unwrap();
+        assert_eq!(sealed.open(&other_salt), Err(BoopError::CredentialsLocked));
+    }
+}
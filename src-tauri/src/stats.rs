@@ -0,0 +1,207 @@
+use {
+    serde::{
+        Deserialize,
+        Serialize
+    },
+    std::collections::VecDeque
+};
+
+/// Round trips the connection stats are computed from
+pub const STATS_WINDOW: usize = 20;
+
+/// Quality of a connection over the last `STATS_WINDOW` pings, times in
+/// milliseconds. The times are `None` until the first pong arrived.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionStats {
+    samples: usize,
+    min_rtt: Option<i64>,
+    avg_rtt: Option<i64>,
+    max_rtt: Option<i64>,
+    jitter:  Option<i64>, // mean difference between consecutive round trips
+    missed:  u32,         // pings still waiting for their pong
+    lost:    u32          // pings of this connection that never got one
+}
+
+/// Times the pings of a connection. Pings don't carry an id, so a pong is
+/// taken as the answer to the latest ping, the pings before it lost their
+/// pong.
+#[derive(Debug, Default)]
+pub struct PingTracker {
+    pending: Vec<i64>,      // send times of the unanswered pings, unix millis
+    rtts:    VecDeque<i64>, // last round trips, oldest first
+    lost:    u32
+}
+
+impl ConnectionStats {
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    pub fn min_rtt(&self) -> Option<i64> {
+        self.min_rtt
+    }
+
+    pub fn avg_rtt(&self) -> Option<i64> {
+        self.avg_rtt
+    }
+
+    pub fn max_rtt(&self) -> Option<i64> {
+        self.max_rtt
+    }
+
+    pub fn jitter(&self) -> Option<i64> {
+        self.jitter
+    }
+
+    pub fn missed(&self) -> u32 {
+        self.missed
+    }
+
+    pub fn lost(&self) -> u32 {
+        self.lost
+    }
+}
+
+impl PingTracker {
+    /// Records a ping sent at `now`
+    pub fn ping_sent(&mut self, now: i64) {
+        self.pending.push(now);
+    }
+
+    /// Records a pong received at `now` and returns the round trip. `None`
+    /// if no ping was waiting for it.
+    pub fn pong_received(&mut self, now: i64) -> Option<i64> {
+        let sent = self.pending.pop()?;
+        self.lost += self.pending.len() as u32;
+        self.pending.clear();
+
+        let rtt = (now - sent).max(0);
+        if self.rtts.len() == STATS_WINDOW {
+            self.rtts.pop_front();
+        }
+        self.rtts.push_back(rtt);
+        Some(rtt)
+    }
+
+    /// Pings still waiting for their pong
+    pub fn missed(&self) -> u32 {
+        self.pending.len() as u32
+    }
+
+    /// Whether a ping was sent yet
+    pub fn started(&self) -> bool {
+        !self.pending.is_empty() || !self.rtts.is_empty()
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        let samples = self.rtts.len();
+        let missed = self.missed();
+        if samples == 0 {
+            return ConnectionStats {
+                missed,
+                lost: self.lost,
+                ..Default::default()
+            };
+        }
+
+        let jitter = if samples > 1 {
+            let differences: i64 = self
+                .rtts
+                .iter()
+                .zip(self.rtts.iter().skip(1))
+                .map(|(previous, rtt)| (rtt - previous).abs())
+                .sum();
+            Some(differences / (samples as i64 - 1))
+        } else {
+            None
+        };
+
+        ConnectionStats {
+            samples,
+            min_rtt: self.rtts.iter().min().copied(),
+            avg_rtt: Some(self.rtts.iter().sum::<i64>() / samples as i64),
+            max_rtt: self.rtts.iter().max().copied(),
+            jitter,
+            missed,
+            lost: self.lost
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stats::{
+        PingTracker,
+        STATS_WINDOW
+    };
+
+    #[test]
+    fn test_round_trips() {
+        let mut tracker = PingTracker::default();
+        assert!(!tracker.started());
+        assert_eq!(tracker.pong_received(0), None);
+
+        tracker.ping_sent(0);
+        assert_eq!(tracker.missed(), 1);
+        assert_eq!(tracker.pong_received(40), Some(40));
+        tracker.ping_sent(100);
+        assert_eq!(tracker.pong_received(200), Some(100));
+        tracker.ping_sent(300);
+        assert_eq!(tracker.pong_received(370), Some(70));
+
+        let stats = tracker.stats();
+        assert_eq!(stats.samples(), 3);
+        assert_eq!(stats.min_rtt(), Some(40));
+        assert_eq!(stats.avg_rtt(), Some(70));
+        assert_eq!(stats.max_rtt(), Some(100));
+        assert_eq!(stats.jitter(), Some(45));
+        assert_eq!(stats.missed(), 0);
+        assert_eq!(stats.lost(), 0);
+
+        tracker.ping_sent(400);
+        assert_eq!(tracker.stats().missed(), 1);
+    }
+
+    #[test]
+    fn test_lost_pong() {
+        let mut tracker = PingTracker::default();
+        tracker.ping_sent(0);
+        tracker.ping_sent(5_000);
+        assert_eq!(tracker.missed(), 2);
+
+        // the first pong never came, the second one answers the latest ping
+        assert_eq!(tracker.pong_received(5_030), Some(30));
+        assert_eq!(tracker.missed(), 0);
+        assert_eq!(tracker.pong_received(5_040), None);
+
+        // later round trips are measured against their own ping
+        for i in 2..10 {
+            tracker.ping_sent(i * 5_000);
+            assert_eq!(tracker.pong_received(i * 5_000 + 30), Some(30));
+        }
+        let stats = tracker.stats();
+        assert_eq!(stats.max_rtt(), Some(30));
+        assert_eq!(stats.missed(), 0);
+        assert_eq!(stats.lost(), 1);
+    }
+
+    #[test]
+    fn test_stats_window() {
+        let mut tracker = PingTracker::default();
+        tracker.ping_sent(0);
+        assert_eq!(tracker.stats().avg_rtt(), None);
+        assert_eq!(tracker.stats().missed(), 1);
+        tracker.pong_received(1000);
+
+        // the slow first round trip drops out of the window
+        for i in 1..=STATS_WINDOW as i64 {
+            tracker.ping_sent(i * 1000);
+            tracker.pong_received(i * 1000 + 10);
+        }
+        let stats = tracker.stats();
+        assert_eq!(stats.samples(), STATS_WINDOW);
+        assert_eq!(stats.max_rtt(), Some(10));
+        assert_eq!(stats.jitter(), Some(0));
+    }
+}
@@ -0,0 +1,299 @@
+use {
+    crate::{
+        config::{
+            BoopConfig,
+            LoginMode
+        },
+        error::{
+            BoopError,
+            TlsErrorCategory
+        }
+    },
+    ring::digest,
+    rustls_native_certs::load_native_certs,
+    rustls_pemfile::Item,
+    std::{
+        fs::File,
+        io::{
+            self,
+            BufReader
+        },
+        sync::Arc,
+        time::SystemTime
+    },
+    tokio_rustls::rustls::{
+        client::{
+            ServerCertVerified,
+            ServerCertVerifier
+        },
+        Certificate,
+        ClientConfig,
+        PrivateKey,
+        RootCertStore,
+        ServerName
+    }
+};
+
+/// Loads the CA certificates the operating system trusts
+pub fn init_trust_anchors() -> Result<RootCertStore, io::Error> {
+    let certs = load_native_certs()?;
+    debug!("found {} OS certificates", certs.len());
+
+    let mut roots = RootCertStore::empty();
+    for cert in certs {
+        if let Err(err) = roots.add(&Certificate(cert.0)) {
+            warn!("skipped invalid OS certificate: {}", err);
+        }
+    }
+
+    Ok(roots)
+}
+
+/// Trust anchors for a connection: the OS certificates (unless the config
+/// only trusts its own CA files) plus the CA files from the config
+pub fn trust_anchors_for(
+    config: &BoopConfig,
+    os_trust_anchors: &RootCertStore
+) -> Result<RootCertStore, BoopError> {
+    let mut roots = if config.only_custom_cas() {
+        RootCertStore::empty()
+    } else {
+        os_trust_anchors.clone()
+    };
+
+    for ca_file in config.ca_files() {
+        let certs = File::open(&ca_file)
+            .and_then(|file| rustls_pemfile::certs(&mut BufReader::new(file)))
+            .map_err(|err| BoopError::Io(format!("failed to read CA file {}: {}", ca_file, err)))?;
+        if certs.is_empty() {
+            return Err(BoopError::Tls(
+                TlsErrorCategory::Certificate,
+                format!("CA file {} doesn't contain any PEM certificates", ca_file)
+            ));
+        }
+
+        let (added, ignored) = roots.add_parsable_certificates(&certs);
+        debug!(
+            "added {} CA certificates from {} ({} ignored)",
+            added, ca_file, ignored
+        );
+    }
+
+    Ok(roots)
+}
+
+/// Builds the TLS client config for the given settings. With certificate
+/// pinning, the server's certificate isn't checked against any CA (so
+/// self-signed certificates work), the connection checks its fingerprint
+/// against the pinned one instead. A configured client certificate is
+/// presented to the server.
+pub fn client_config(
+    config: &BoopConfig,
+    trust_anchors: RootCertStore
+) -> Result<ClientConfig, BoopError> {
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(trust_anchors);
+    let mut tls_config = match client_identity(config)? {
+        Some((chain, key)) => builder
+            .with_single_cert(chain, key)
+            .map_err(|err| BoopError::ClientCertificate(err.to_string()))?,
+        None if config.login_mode() == LoginMode::Certificate => {
+            return Err(BoopError::ClientCertificate(String::from(
+                "logging in with a certificate needs a client certificate and key"
+            )))
+        }
+        None => builder.with_no_client_auth()
+    };
+
+    if config.pin_certificate() {
+        tls_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(PinningVerifier));
+    }
+
+    Ok(tls_config)
+}
+
+/// SHA-256 fingerprint of the certificate's public key (SPKI), as colon
+/// separated hex
+pub fn spki_fingerprint(cert: &Certificate) -> Option<String> {
+    let spki = subject_public_key_info(&cert.0)?;
+    let hash = digest::digest(&digest::SHA256, spki);
+
+    Some(
+        hash.as_ref()
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<String>>()
+            .join(":")
+    )
+}
+
+/// Reads the client certificate chain and its private key from the PEM files
+/// in the config, `None` if there is no client certificate
+fn client_identity(
+    config: &BoopConfig
+) -> Result<Option<(Vec<Certificate>, PrivateKey)>, BoopError> {
+    let (cert_file, key_file) = match (config.client_certificate(), config.client_key()) {
+        (Some(cert_file), Some(key_file)) => (cert_file, key_file),
+        (None, None) => return Ok(None),
+        _ => {
+            return Err(BoopError::ClientCertificate(String::from(
+                "the client certificate and its key have to be set together"
+            )))
+        }
+    };
+
+    let chain = File::open(&cert_file)
+        .and_then(|file| rustls_pemfile::certs(&mut BufReader::new(file)))
+        .map_err(|err| {
+            BoopError::ClientCertificate(format!("failed to read {}: {}", cert_file, err))
+        })?;
+    if chain.is_empty() {
+        return Err(BoopError::ClientCertificate(format!(
+            "{} doesn't contain any PEM certificates",
+            cert_file
+        )));
+    }
+
+    let key = read_private_key(&key_file)?;
+    Ok(Some((chain.into_iter().map(Certificate).collect(), key)))
+}
+
+/// Reads the first private key (PKCS#8, RSA or EC) from a PEM file
+fn read_private_key(key_file: &str) -> Result<PrivateKey, BoopError> {
+    let read_err = |err: io::Error| {
+        BoopError::ClientCertificate(format!("failed to read {}: {}", key_file, err))
+    };
+
+    let mut reader = BufReader::new(File::open(key_file).map_err(read_err)?);
+    loop {
+        match rustls_pemfile::read_one(&mut reader).map_err(read_err)? {
+            Some(Item::PKCS8Key(key)) | Some(Item::RSAKey(key)) | Some(Item::ECKey(key)) => {
+                return Ok(PrivateKey(key))
+            }
+            // skip certificates in the same file
+            Some(_) => {}
+            None => {
+                return Err(BoopError::ClientCertificate(format!(
+                    "{} doesn't contain a PEM private key",
+                    key_file
+                )))
+            }
+        }
+    }
+}
+
+/// Accepts any certificate the server presents. The handshake still makes
+/// sure the server owns the certificate's key, the key itself is checked
+/// against the pinned fingerprint before anything is sent.
+struct PinningVerifier;
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Finds the DER encoded SubjectPublicKeyInfo in a DER encoded certificate
+fn subject_public_key_info(cert: &[u8]) -> Option<&[u8]> {
+    // Certificate ::= SEQUENCE { tbsCertificate SEQUENCE { ... }, ... }
+    let (_, certificate, _) = der_element(cert, 0x30)?;
+    let (_, mut tbs, _) = der_element(certificate, 0x30)?;
+
+    // optional explicit version ([0])
+    if tbs.first() == Some(&0xA0) {
+        tbs = der_element(tbs, 0xA0)?.2;
+    }
+
+    // serialNumber, signature, issuer, validity, subject
+    for tag in [0x02, 0x30, 0x30, 0x30, 0x30] {
+        tbs = der_element(tbs, tag)?.2;
+    }
+
+    // subjectPublicKeyInfo, including its header
+    let (whole, ..) = der_element(tbs, 0x30)?;
+    Some(whole)
+}
+
+/// Splits the next DER element with the expected tag off `input`. Returns
+/// the whole element, its contents and the rest of the input.
+fn der_element(input: &[u8], tag: u8) -> Option<(&[u8], &[u8], &[u8])> {
+    if *input.first()? != tag {
+        return None;
+    }
+
+    let first = *input.get(1)? as usize;
+    let (header, length) = if first < 0x80 {
+        (2, first)
+    } else {
+        // long form, the low bits tell how many length bytes follow
+        let count = first & 0x7F;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let length = input
+            .get(2..2 + count)?
+            .iter()
+            .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+        (2 + count, length)
+    };
+
+    let end = header.checked_add(length)?;
+    if end > input.len() {
+        return None;
+    }
+
+    Some((&input[..end], &input[header..end], &input[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::tls::{
+            spki_fingerprint,
+            subject_public_key_info
+        },
+        tokio_rustls::rustls::Certificate
+    };
+
+    #[test]
+    fn test_subject_public_key_info() {
+        let generated =
+            rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        let der = generated.serialize_der().unwrap();
+
+        assert_eq!(
+            subject_public_key_info(&der),
+            Some(generated.get_key_pair().public_key_der().as_slice())
+        );
+        assert_eq!(subject_public_key_info(&der[..der.len() / 2]), None);
+        assert_eq!(subject_public_key_info(&[]), None);
+    }
+
+    #[test]
+    fn test_fingerprint_follows_the_key() {
+        let generated =
+            rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        let cert = Certificate(generated.serialize_der().unwrap());
+        let fingerprint = spki_fingerprint(&cert).unwrap();
+
+        // 32 bytes as colon separated hex
+        assert_eq!(fingerprint.len(), 32 * 3 - 1);
+        // a new certificate with a new key has a different fingerprint
+        let other = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        assert_ne!(
+            spki_fingerprint(&Certificate(other.serialize_der().unwrap())),
+            Some(fingerprint)
+        );
+    }
+}
@@ -79,7 +79,7 @@ const MOJIS: [&str; 87] = [
     "(b ᵔ▽ᵔ)b",
     "(๑˃ᴗ˂)ﻭ",
     "(๑˘︶˘๑)",
-    "( ˙꒳​˙ )",
+    "( ˙꒳\u{200B}˙ )",
     "(*꒦ິ꒳꒦ີ)",
     "°˖✧◝(⁰▿⁰)◜✧˖°",
     "(´･ᴗ･ ` )",
@@ -0,0 +1,1515 @@
+mod support;
+
+use std::{
+    env,
+    fs,
+    sync::Arc,
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use {
+    boop_snoot::{
+        auth::MAX_ITERATIONS,
+        config::BoopConfig,
+        error::{
+            BoopError,
+            TlsErrorCategory
+        },
+        message::{
+            BoopContent,
+            BoopKind,
+            Capability,
+            MessageErrorKind,
+            MessageType,
+            CLIENT_CAPABILITIES,
+            PROTOCOL_VERSION
+        },
+        network::{
+            connect_to_server,
+            disconnect_from_server,
+            events_of_server,
+            BoopDeliveryStatus,
+            ConnectionEvent,
+            ConnectionInterface,
+            ConnectionMap,
+            EventRx,
+            ServerConnectionStatus,
+            ServerEventRx,
+            ServerFeatures
+        },
+        partners::{
+            PartnerOnlineStatus,
+            PartnersHandle
+        },
+        secrets::{
+            KeyHandle,
+            KeySource
+        }
+    },
+    serde_json::json,
+    support::{
+        partners,
+        plain_keys,
+        wait_for_event,
+        wait_for_event_within,
+        HelloReply,
+        MockRelay,
+        Received,
+        RelayBehaviour,
+        WAIT_TIMEOUT
+    },
+    tokio::{
+        net::TcpListener,
+        sync::{
+            mpsc::unbounded_channel,
+            Mutex
+        },
+        time::timeout
+    },
+    tokio_rustls::rustls::RootCertStore
+};
+
+struct Client {
+    conn_handle: Mutex<Option<ConnectionInterface>>,
+    events:      EventRx,
+    logged_in:   bool
+}
+
+async fn connect(relay: &MockRelay, partners_handle: PartnersHandle) -> Client {
+    let conn_handle = Mutex::new(None);
+    let (event_tx, events) = unbounded_channel();
+
+    let logged_in = match connect_to_server(
+        &conn_handle,
+        Arc::new(Mutex::new(relay.config("foo", "bar"))),
+        plain_keys(),
+        partners_handle,
+        relay.trust_anchors(),
+        event_tx
+    )
+    .await
+    {
+        Ok(_) => true,
+        Err(BoopError::LoginRejected) => false,
+        Err(err) => panic!("connection to the mock relay failed: {}", err)
+    };
+
+    Client {
+        conn_handle,
+        events,
+        logged_in
+    }
+}
+
+async fn try_connect(config: BoopConfig, trust_anchors: RootCertStore) -> Result<(), BoopError> {
+    try_connect_with(&Arc::new(Mutex::new(config)), trust_anchors)
+        .await
+        .0
+}
+
+async fn try_connect_with(
+    config_handle: &Arc<Mutex<BoopConfig>>,
+    trust_anchors: RootCertStore
+) -> (Result<(), BoopError>, EventRx) {
+    try_connect_with_keys(config_handle, plain_keys(), trust_anchors).await
+}
+
+async fn try_connect_with_keys(
+    config_handle: &Arc<Mutex<BoopConfig>>,
+    key_handle: KeyHandle,
+    trust_anchors: RootCertStore
+) -> (Result<(), BoopError>, EventRx) {
+    let (event_tx, events) = unbounded_channel();
+    let res = connect_to_server(
+        &Mutex::new(None),
+        Arc::clone(config_handle),
+        key_handle,
+        partners(&[]),
+        trust_anchors,
+        event_tx
+    )
+    .await;
+    (res, events)
+}
+
+async fn features(client: &Client) -> ServerFeatures {
+    let connection_interface = client.conn_handle.lock().await;
+    let features = connection_interface
+        .as_ref()
+        .unwrap()
+        .features
+        .borrow()
+        .clone();
+    features
+}
+
+fn is_status(event: &ConnectionEvent, status: ServerConnectionStatus) -> bool {
+    *event == ConnectionEvent::StatusChanged(status)
+}
+
+/// Like `wait_for_event`, for the events of a given server
+async fn wait_for_server_event(
+    events: &mut ServerEventRx,
+    server: &str,
+    predicate: impl Fn(&ConnectionEvent) -> bool
+) {
+    timeout(WAIT_TIMEOUT, async {
+        loop {
+            let (from, event) = events.recv().await.expect("event channel closed");
+            if from == server && predicate(&event) {
+                return;
+            }
+        }
+    })
+    .await
+    .expect("expected connection event didn't arrive in time")
+}
+
+#[tokio::test]
+async fn test_login_success() {
+    let mut relay = MockRelay::start(RelayBehaviour::default()).await;
+    let mut client = connect(&relay, partners(&[])).await;
+
+    assert!(client.logged_in);
+    let capabilities = CLIENT_CAPABILITIES
+        .iter()
+        .map(|capability| String::from(capability.name()))
+        .collect();
+    assert_eq!(relay.next_message().await, Received {
+        connection: 1,
+        message:    MessageType::HELLO(2, capabilities)
+    });
+    assert_eq!(relay.next_message().await, Received {
+        connection: 1,
+        message:    MessageType::CONNECT(String::from("foo"), Some(String::from("bar")))
+    });
+    wait_for_event(&mut client.events, |event| {
+        is_status(event, ServerConnectionStatus::Connected)
+    })
+    .await;
+
+    // closing the connection says goodbye to the relay
+    disconnect_from_server(&client.conn_handle).await;
+    relay
+        .wait_for(|received| received.message == MessageType::DISCONNECT)
+        .await;
+    wait_for_event(&mut client.events, |event| {
+        is_status(event, ServerConnectionStatus::Disconnected)
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_feature_negotiation() {
+    let relay = MockRelay::start(RelayBehaviour {
+        hello: HelloReply::Answer(vec![String::from("ack"), String::from("teleport")]),
+        ..Default::default()
+    })
+    .await;
+    let client = connect(&relay, partners(&[])).await;
+    assert!(client.logged_in);
+
+    let features = features(&client).await;
+    assert_eq!(features.version(), PROTOCOL_VERSION);
+    // only capabilities this client knows about count
+    for capability in [Capability::Ack, Capability::BoopPayload] {
+        assert_eq!(
+            features.supports(capability),
+            CLIENT_CAPABILITIES.contains(&capability) && capability == Capability::Ack
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_legacy_relay_with_error_reply() {
+    let mut relay = MockRelay::start(RelayBehaviour {
+        hello: HelloReply::Error,
+        ..Default::default()
+    })
+    .await;
+    let client = connect(&relay, partners(&[])).await;
+    assert!(client.logged_in);
+
+    // login continues on the same connection
+    assert!(matches!(
+        relay.next_message().await.message,
+        MessageType::HELLO(..)
+    ));
+    assert!(matches!(relay.next_message().await, Received {
+        connection: 1,
+        message:    MessageType::CONNECT(..)
+    }));
+    assert_eq!(features(&client).await, ServerFeatures::legacy());
+}
+
+#[tokio::test]
+async fn test_legacy_relay_hanging_up() {
+    let mut relay = MockRelay::start(RelayBehaviour {
+        hello: HelloReply::Close,
+        ..Default::default()
+    })
+    .await;
+    let client = connect(&relay, partners(&[])).await;
+    assert!(client.logged_in);
+
+    // login is retried on a new connection without HELLO
+    assert!(matches!(relay.next_message().await, Received {
+        connection: 1,
+        message:    MessageType::HELLO(..)
+    }));
+    assert!(matches!(relay.next_message().await, Received {
+        connection: 2,
+        message:    MessageType::CONNECT(..)
+    }));
+    assert_eq!(features(&client).await, ServerFeatures::legacy());
+}
+
+#[tokio::test]
+async fn test_protocol_violation_in_handshake() {
+    let relay = MockRelay::start(RelayBehaviour {
+        hello: HelloReply::Unexpected,
+        ..Default::default()
+    })
+    .await;
+
+    let res = try_connect(relay.config("foo", "bar"), relay.trust_anchors()).await;
+    assert!(matches!(res, Err(BoopError::Protocol(reason)) if reason.contains("HELLO")));
+}
+
+#[tokio::test]
+async fn test_login_rejected() {
+    let relay = MockRelay::start(RelayBehaviour {
+        reject_login: true,
+        ..Default::default()
+    })
+    .await;
+    let client = connect(&relay, partners(&[])).await;
+
+    assert!(!client.logged_in);
+    assert!(client.conn_handle.lock().await.is_none());
+}
+
+#[tokio::test]
+async fn test_challenge_login() {
+    let mut relay = MockRelay::start(RelayBehaviour {
+        hello: HelloReply::Answer(vec![String::from("challenge-login")]),
+        password: String::from("bar"),
+        ..Default::default()
+    })
+    .await;
+    let client = connect(&relay, partners(&[])).await;
+    assert!(client.logged_in);
+
+    // the password never crosses the wire
+    assert!(matches!(
+        relay.next_message().await.message,
+        MessageType::HELLO(..)
+    ));
+    assert!(matches!(
+        relay.next_message().await.message,
+        MessageType::AUTH(user, _) if user == "foo"
+    ));
+    assert!(matches!(
+        relay.next_message().await.message,
+        MessageType::PROOF(_)
+    ));
+}
+
+#[tokio::test]
+async fn test_slow_proof_times_out() {
+    let mut relay = MockRelay::start(RelayBehaviour {
+        hello: HelloReply::Answer(vec![String::from("challenge-login")]),
+        iterations: Some(MAX_ITERATIONS),
+        ..Default::default()
+    })
+    .await;
+    let config = relay.config_with(json!({
+        "network": { "handshakeTimeout": 1 }
+    }));
+
+    // the proof is computed off the runtime, so the timeout isn't stuck behind it
+    let started = Instant::now();
+    let err = try_connect(config, relay.trust_anchors())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, BoopError::Io(reason) if reason.contains("login")));
+    assert!(started.elapsed() < Duration::from_secs(2));
+    relay
+        .wait_for(|received| matches!(received.message, MessageType::AUTH(..)))
+        .await;
+}
+
+#[tokio::test]
+async fn test_challenge_login_wrong_password() {
+    let relay = MockRelay::start(RelayBehaviour {
+        hello: HelloReply::Answer(vec![String::from("challenge-login")]),
+        password: String::from("baz"),
+        ..Default::default()
+    })
+    .await;
+    let client = connect(&relay, partners(&[])).await;
+    assert!(!client.logged_in);
+}
+
+#[tokio::test]
+async fn test_encrypted_password() {
+    let mut relay = MockRelay::start(RelayBehaviour::default()).await;
+    let key_file = env::temp_dir().join(format!("boop-client-key-{}", std::process::id()));
+    let keys = KeySource::new(key_file.clone());
+
+    let mut config = relay.config("foo", "bar");
+    config.encrypt_password(&keys, false).unwrap();
+    // nothing that's saved contains the password
+    assert!(!config.has_plaintext_password());
+    assert!(!serde_json::to_string(&config).unwrap().contains("bar"));
+    assert!(!serde_json::to_string(&config.redacted())
+        .unwrap()
+        .contains("bar"));
+
+    let (res, _) = try_connect_with_keys(
+        &Arc::new(Mutex::new(config)),
+        Arc::new(Mutex::new(keys)),
+        relay.trust_anchors()
+    )
+    .await;
+    let _ = fs::remove_file(&key_file);
+
+    assert_eq!(res, Ok(()));
+    let login = relay
+        .wait_for(|received| matches!(received.message, MessageType::CONNECT(..)))
+        .await;
+    assert_eq!(
+        login.message,
+        MessageType::CONNECT(String::from("foo"), Some(String::from("bar")))
+    );
+}
+
+#[tokio::test]
+async fn test_locked_password() {
+    let mut relay = MockRelay::start(RelayBehaviour::default()).await;
+    let keys = KeySource::new(env::temp_dir().join("boop-unused-key"));
+
+    let mut config = relay.config("foo", "bar");
+    let unlocked = keys
+        .with_passphrase(String::from("open sesame"), None)
+        .await
+        .unwrap();
+    config.encrypt_password(&unlocked, true).unwrap();
+
+    // the master passphrase wasn't entered, so nothing is sent
+    let (res, _) = try_connect_with_keys(
+        &Arc::new(Mutex::new(config)),
+        Arc::new(Mutex::new(keys)),
+        relay.trust_anchors()
+    )
+    .await;
+    assert_eq!(res, Err(BoopError::CredentialsLocked));
+    assert_eq!(relay.try_next_message(), None);
+}
+
+#[tokio::test]
+async fn test_untrusted_certificate() {
+    let relay = MockRelay::start(RelayBehaviour::default()).await;
+    let err = try_connect(relay.config("foo", "bar"), RootCertStore::empty())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        err,
+        BoopError::Tls(TlsErrorCategory::Certificate, _)
+    ));
+    // the frontend gets the kind and a readable message
+    let serialized = serde_json::to_value(&err).unwrap();
+    assert_eq!(serialized["kind"], "tlsCertificate");
+    assert_eq!(serialized["message"], err.to_string());
+}
+
+#[tokio::test]
+async fn test_custom_ca_file() {
+    let relay = MockRelay::start(RelayBehaviour::default()).await;
+    let ca_file = env::temp_dir().join(format!("boop-relay-ca-{}.pem", std::process::id()));
+    fs::write(&ca_file, relay.certificate_pem()).unwrap();
+
+    let config = relay.config_with(json!({
+        "caFiles": [ca_file.to_string_lossy()],
+        "onlyCustomCas": true
+    }));
+    let res = try_connect(config, RootCertStore::empty()).await;
+    let _ = fs::remove_file(&ca_file);
+
+    assert_eq!(res, Ok(()));
+}
+
+#[tokio::test]
+async fn test_only_custom_cas_ignores_os_certificates() {
+    let relay = MockRelay::start(RelayBehaviour::default()).await;
+    let config = relay.config_with(json!({ "onlyCustomCas": true }));
+
+    // the relay's certificate is only one of the "OS" certificates here
+    let err = try_connect(config, relay.trust_anchors())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        BoopError::Tls(TlsErrorCategory::Certificate, _)
+    ));
+}
+
+#[tokio::test]
+async fn test_certificate_pinned_on_first_use() {
+    let relay = MockRelay::start(RelayBehaviour::default()).await;
+    let config_handle = Arc::new(Mutex::new(
+        relay.config_with(json!({ "pinCertificate": true }))
+    ));
+
+    // self-signed and not in any trust anchors
+    let (res, mut events) = try_connect_with(&config_handle, RootCertStore::empty()).await;
+    assert_eq!(res, Ok(()));
+    let fingerprint = match wait_for_event(&mut events, |event| {
+        matches!(event, ConnectionEvent::CertificatePinned(_))
+    })
+    .await
+    {
+        ConnectionEvent::CertificatePinned(fingerprint) => fingerprint,
+        _ => unreachable!()
+    };
+    assert_eq!(
+        config_handle.lock().await.pinned_fingerprint(),
+        Some(fingerprint)
+    );
+
+    // the pinned certificate is accepted from now on
+    let (res, _) = try_connect_with(&config_handle, RootCertStore::empty()).await;
+    assert_eq!(res, Ok(()));
+}
+
+#[tokio::test]
+async fn test_changed_certificate_blocks_connection() {
+    let mut relay = MockRelay::start(RelayBehaviour::default()).await;
+    let config_handle = Arc::new(Mutex::new(relay.config_with(json!({
+        "pinCertificate": true,
+        "pinnedFingerprint": "00:11:22"
+    }))));
+
+    let (res, _) = try_connect_with(&config_handle, relay.trust_anchors()).await;
+    let err = res.unwrap_err();
+    let presented = match &err {
+        BoopError::CertificateChanged(pinned, presented) => {
+            assert_eq!(pinned, "00:11:22");
+            presented.clone()
+        }
+        err => panic!("expected a changed certificate, got {:?}", err)
+    };
+    // the frontend needs the new fingerprint to accept it
+    assert_eq!(
+        serde_json::to_value(&err).unwrap()["fingerprint"],
+        presented
+    );
+    // nothing (like the password) was sent to the server
+    assert!(relay.try_next_message().is_none());
+
+    // after the user accepted the new certificate
+    config_handle
+        .lock()
+        .await
+        .set_pinned_fingerprint(Some(presented));
+    let (res, _) = try_connect_with(&config_handle, RootCertStore::empty()).await;
+    assert_eq!(res, Ok(()));
+}
+
+#[tokio::test]
+async fn test_certificate_login() {
+    let mut relay = MockRelay::start_requiring_client_certificates(RelayBehaviour::default()).await;
+    let (certificate_pem, key_pem) = relay.issue_client_certificate("foo");
+    let prefix = env::temp_dir().join(format!("boop-client-{}", std::process::id()));
+    let certificate_file = prefix.with_extension("crt");
+    let key_file = prefix.with_extension("key");
+    fs::write(&certificate_file, certificate_pem).unwrap();
+    fs::write(&key_file, key_pem).unwrap();
+
+    let config = relay.config_with(json!({
+        "loginMode": "certificate",
+        "clientCertificate": certificate_file.to_string_lossy(),
+        "clientKey": key_file.to_string_lossy()
+    }));
+    let res = try_connect(config, relay.trust_anchors()).await;
+    let _ = fs::remove_file(&certificate_file);
+    let _ = fs::remove_file(&key_file);
+
+    assert_eq!(res, Ok(()));
+    // the certificate replaces the password
+    let login = relay
+        .wait_for(|received| matches!(received.message, MessageType::CONNECT(..)))
+        .await;
+    assert_eq!(
+        login.message,
+        MessageType::CONNECT(String::from("foo"), None)
+    );
+}
+
+#[tokio::test]
+async fn test_certificate_login_needs_certificate() {
+    let relay = MockRelay::start_requiring_client_certificates(RelayBehaviour::default()).await;
+    let config = relay.config_with(json!({ "loginMode": "certificate" }));
+
+    let err = try_connect(config, relay.trust_anchors())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, BoopError::ClientCertificate(_)));
+}
+
+#[tokio::test]
+async fn test_server_address_without_port() {
+    let config: BoopConfig = serde_json::from_value(json!({
+        "serverAddress": "localhost",
+        "user": "foo",
+        "password": "bar"
+    }))
+    .unwrap();
+    let err = try_connect(config, RootCertStore::empty())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, BoopError::Dns(server, _) if server == "localhost"));
+}
+
+#[tokio::test]
+async fn test_reconnect_after_connection_loss() {
+    let mut relay = MockRelay::start(RelayBehaviour::default()).await;
+    let mut client = connect(&relay, partners(&[])).await;
+    assert!(client.logged_in);
+    relay
+        .wait_for(|received| matches!(received.message, MessageType::CONNECT(..)))
+        .await;
+
+    relay.kick();
+
+    wait_for_event(&mut client.events, |event| {
+        matches!(
+            event,
+            ConnectionEvent::StatusChanged(ServerConnectionStatus::Reconnecting(info))
+                if info.attempt() == 1 && info.next_retry().is_some()
+        )
+    })
+    .await;
+    let received = relay
+        .wait_for(|received| matches!(received.message, MessageType::CONNECT(..)))
+        .await;
+    assert_eq!(received.connection, 2);
+    wait_for_event(&mut client.events, |event| {
+        is_status(event, ServerConnectionStatus::Connected)
+    })
+    .await;
+
+    // the sink survives the reconnect
+    let sink = client
+        .conn_handle
+        .lock()
+        .await
+        .as_ref()
+        .unwrap()
+        .sink
+        .clone();
+    sink.send(MessageType::BOOP(
+        String::from("alice"),
+        None,
+        BoopContent::default()
+    ))
+    .unwrap();
+    let received = relay
+        .wait_for(|received| matches!(received.message, MessageType::BOOP(..)))
+        .await;
+    assert_eq!(received, Received {
+        connection: 2,
+        message:    MessageType::BOOP(String::from("alice"), None, BoopContent::default())
+    });
+}
+
+#[tokio::test]
+async fn test_reconnect_gives_up_after_rejected_login() {
+    let mut relay = MockRelay::start(RelayBehaviour::default()).await;
+    let mut client = connect(&relay, partners(&[])).await;
+    assert!(client.logged_in);
+    relay.next_message().await;
+
+    relay.set_behaviour(|behaviour| behaviour.reject_login = true);
+    relay.kick();
+
+    wait_for_event(&mut client.events, |event| {
+        matches!(
+            event,
+            ConnectionEvent::StatusChanged(ServerConnectionStatus::Reconnecting(info))
+                if info.give_up_reason().is_some() && info.next_retry().is_none()
+        )
+    })
+    .await;
+    wait_for_event(&mut client.events, |event| {
+        is_status(event, ServerConnectionStatus::Disconnected)
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_queued_boops_fail_when_reconnect_gives_up() {
+    let mut relay = MockRelay::start(RelayBehaviour::default()).await;
+    let mut client = connect(&relay, partners(&["alice"])).await;
+    relay.next_message().await;
+
+    relay.set_behaviour(|behaviour| behaviour.reject_login = true);
+    relay.kick();
+    wait_for_event(&mut client.events, |event| {
+        matches!(
+            event,
+            ConnectionEvent::StatusChanged(ServerConnectionStatus::Reconnecting(_))
+        )
+    })
+    .await;
+
+    // nothing reads the sink until the connection is back, which it never is
+    client
+        .conn_handle
+        .lock()
+        .await
+        .as_ref()
+        .unwrap()
+        .sink
+        .send(MessageType::BOOP(
+            String::from("alice"),
+            Some(String::from("b1")),
+            BoopContent::default()
+        ))
+        .unwrap();
+    wait_for_event(&mut client.events, |event| {
+        *event == ConnectionEvent::BoopDelivery(String::from("b1"), BoopDeliveryStatus::Failed)
+    })
+    .await;
+    wait_for_event(&mut client.events, |event| {
+        is_status(event, ServerConnectionStatus::Disconnected)
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_disconnect_cancels_reconnect() {
+    let mut relay = MockRelay::start(RelayBehaviour::default()).await;
+    let mut client = connect(&relay, partners(&[])).await;
+    relay.next_message().await;
+
+    relay.kick();
+    wait_for_event(&mut client.events, |event| {
+        matches!(
+            event,
+            ConnectionEvent::StatusChanged(ServerConnectionStatus::Reconnecting(_))
+        )
+    })
+    .await;
+
+    disconnect_from_server(&client.conn_handle).await;
+    wait_for_event(&mut client.events, |event| {
+        is_status(event, ServerConnectionStatus::Disconnected)
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_ping_timeout() {
+    let mut relay = MockRelay::start(RelayBehaviour {
+        drop_pongs: true,
+        ..Default::default()
+    })
+    .await;
+    let mut client = connect(&relay, partners(&[])).await;
+    relay.next_message().await;
+
+    // the client gives up on the connection after too many missed pongs and tries
+    // to get a new one
+    wait_for_event_within(&mut client.events, Duration::from_secs(60), |event| {
+        matches!(
+            event,
+            ConnectionEvent::StatusChanged(ServerConnectionStatus::Reconnecting(_))
+        )
+    })
+    .await;
+
+    // it kept pinging until then
+    let pings = std::iter::from_fn(|| relay.try_next_message())
+        .filter(|received| received.message == MessageType::PING)
+        .count();
+    assert!(pings > 3);
+}
+
+#[tokio::test]
+async fn test_quality_report() {
+    let relay = MockRelay::start(RelayBehaviour::default()).await;
+    let mut client = connect(&relay, partners(&[])).await;
+
+    // the first ping was answered by the time the next one is sent
+    let event = wait_for_event_within(&mut client.events, Duration::from_secs(15), |event| {
+        matches!(event, ConnectionEvent::QualityReport(_))
+    })
+    .await;
+    let stats = match event {
+        ConnectionEvent::QualityReport(stats) => stats,
+        _ => unreachable!()
+    };
+    assert_eq!(stats.samples(), 1);
+    assert_eq!(stats.missed(), 0);
+    assert_eq!(stats.min_rtt(), stats.max_rtt());
+    assert!(stats.avg_rtt().is_some());
+    assert_eq!(stats.jitter(), None);
+}
+
+#[tokio::test]
+async fn test_custom_ping_interval() {
+    let relay = MockRelay::start(RelayBehaviour {
+        drop_pongs: true,
+        ..Default::default()
+    })
+    .await;
+    let config = relay.config_with(json!({
+        "network": { "pingInterval": 1, "allowedPingMissed": 1 }
+    }));
+    let (res, mut events) =
+        try_connect_with(&Arc::new(Mutex::new(config)), relay.trust_anchors()).await;
+    res.unwrap();
+
+    // two unanswered pings a second apart are enough to give up
+    wait_for_event_within(&mut events, Duration::from_secs(4), |event| {
+        matches!(
+            event,
+            ConnectionEvent::StatusChanged(ServerConnectionStatus::Reconnecting(_))
+        )
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_handshake_timeout() {
+    // accepts the connection, but never answers the TLS handshake
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (_stream, _) = listener.accept().await.unwrap();
+        tokio::time::sleep(Duration::from_secs(30)).await;
+    });
+
+    let config: BoopConfig = serde_json::from_value(json!({
+        "serverAddress": format!("127.0.0.1:{}", port),
+        "user": "foo",
+        "password": "bar",
+        "network": { "handshakeTimeout": 1 }
+    }))
+    .unwrap();
+    let err = timeout(
+        Duration::from_secs(5),
+        try_connect(config, RootCertStore::empty())
+    )
+    .await
+    .expect("the handshake didn't time out")
+    .unwrap_err();
+    assert!(matches!(
+        err,
+        BoopError::Tls(TlsErrorCategory::Handshake, _)
+    ));
+}
+
+#[tokio::test]
+async fn test_disconnect_cancels_connect() {
+    // accepts the connection, but never answers the TLS handshake
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (_stream, _) = listener.accept().await.unwrap();
+        tokio::time::sleep(Duration::from_secs(30)).await;
+    });
+
+    let config: BoopConfig = serde_json::from_value(json!({
+        "serverAddress": format!("127.0.0.1:{}", port),
+        "user": "foo",
+        "password": "bar"
+    }))
+    .unwrap();
+    let conn_handle = Arc::new(Mutex::new(None));
+    let (event_tx, _events) = unbounded_channel();
+    let attempt = tokio::spawn({
+        let conn_handle = Arc::clone(&conn_handle);
+        async move {
+            connect_to_server(
+                &conn_handle,
+                Arc::new(Mutex::new(config)),
+                plain_keys(),
+                partners(&[]),
+                RootCertStore::empty(),
+                event_tx
+            )
+            .await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // the slot isn't locked during the handshake, the disconnect cancels it
+    timeout(Duration::from_secs(1), disconnect_from_server(&conn_handle))
+        .await
+        .expect("disconnect waited for the connection attempt");
+    let res = timeout(Duration::from_secs(1), attempt)
+        .await
+        .expect("the connection attempt wasn't cancelled")
+        .unwrap();
+    assert_eq!(res, Err(BoopError::NotConnected));
+    assert!(conn_handle.lock().await.is_none());
+}
+
+#[tokio::test]
+async fn test_partner_availability() {
+    let mut relay = MockRelay::start(RelayBehaviour {
+        online_partners: ["alice"].iter().map(|key| String::from(*key)).collect(),
+        ..Default::default()
+    })
+    .await;
+    let partners_handle = partners(&["alice", "bob"]);
+    let mut client = connect(&relay, Arc::clone(&partners_handle)).await;
+
+    // every partner is asked for
+    for _ in 0..2 {
+        relay
+            .wait_for(|received| matches!(received.message, MessageType::AYT(_)))
+            .await;
+    }
+
+    // partners are asked for in no particular order
+    let mut changes = Vec::new();
+    while changes.len() < 2 {
+        let event = wait_for_event(&mut client.events, |event| {
+            matches!(event, ConnectionEvent::PartnerStatusChanged(..))
+        })
+        .await;
+        changes.push(event);
+    }
+    assert!(changes.contains(&ConnectionEvent::PartnerStatusChanged(
+        String::from("alice"),
+        PartnerOnlineStatus::Online
+    )));
+    assert!(changes.contains(&ConnectionEvent::PartnerStatusChanged(
+        String::from("bob"),
+        PartnerOnlineStatus::Afk
+    )));
+
+    let partners = partners_handle.lock().await;
+    assert_eq!(partners["alice"].1, PartnerOnlineStatus::Online);
+    assert_eq!(partners["bob"].1, PartnerOnlineStatus::Afk);
+}
+
+#[tokio::test]
+async fn test_batched_presence_query() {
+    let mut relay = MockRelay::start(RelayBehaviour {
+        hello: HelloReply::Answer(vec![String::from("presence-batch")]),
+        online_partners: ["alice"].iter().map(|key| String::from(*key)).collect(),
+        ..Default::default()
+    })
+    .await;
+    let keys: Vec<String> = (0..120).map(|i| format!("partner{:03}", i)).collect();
+    let key_refs: Vec<&str> = std::iter::once("alice")
+        .chain(keys.iter().map(String::as_str))
+        .collect();
+    let partners_handle = partners(&key_refs);
+    let mut client = connect(&relay, Arc::clone(&partners_handle)).await;
+
+    // all partners are asked for in a few lines instead of one line each
+    let mut asked = Vec::new();
+    while asked.len() < key_refs.len() {
+        match relay
+            .wait_for(|received| {
+                matches!(
+                    received.message,
+                    MessageType::STATUS(_) | MessageType::AYT(_)
+                )
+            })
+            .await
+            .message
+        {
+            MessageType::STATUS(batch) => asked.extend(batch),
+            message => panic!("expected a STATUS query, got {:?}", message)
+        }
+    }
+    assert_eq!(asked.len(), key_refs.len());
+
+    wait_for_event(&mut client.events, |event| {
+        *event
+            == ConnectionEvent::PartnerStatusChanged(
+                String::from("alice"),
+                PartnerOnlineStatus::Online
+            )
+    })
+    .await;
+    wait_for_event(&mut client.events, |event| {
+        *event
+            == ConnectionEvent::PartnerStatusChanged(
+                String::from("partner119"),
+                PartnerOnlineStatus::Afk
+            )
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_presence_push() {
+    let mut relay = MockRelay::start(RelayBehaviour {
+        hello: HelloReply::Answer(vec![String::from("presence-push")]),
+        online_partners: ["alice"].iter().map(|key| String::from(*key)).collect(),
+        ..Default::default()
+    })
+    .await;
+    let mut client = connect(&relay, partners(&["bob", "alice"])).await;
+
+    // one subscription for all partners instead of polling
+    relay
+        .wait_for(|received| {
+            received.message
+                == MessageType::SUBSCRIBE(vec![String::from("alice"), String::from("bob")])
+        })
+        .await;
+    wait_for_event(&mut client.events, |event| {
+        *event
+            == ConnectionEvent::PartnerStatusChanged(
+                String::from("alice"),
+                PartnerOnlineStatus::Online
+            )
+    })
+    .await;
+
+    // changes are pushed by the relay
+    relay.send_line("AFK alice");
+    wait_for_event(&mut client.events, |event| {
+        *event
+            == ConnectionEvent::PartnerStatusChanged(
+                String::from("alice"),
+                PartnerOnlineStatus::Afk
+            )
+    })
+    .await;
+
+    let polled = std::iter::from_fn(|| relay.try_next_message())
+        .any(|received| matches!(received.message, MessageType::AYT(_)));
+    assert!(!polled);
+}
+
+#[tokio::test]
+async fn test_subscriptions_follow_partner_changes() {
+    let mut presence_relay = MockRelay::start(RelayBehaviour {
+        hello: HelloReply::Answer(vec![String::from("presence-push")]),
+        ..Default::default()
+    })
+    .await;
+    let mut legacy_relay = MockRelay::start(RelayBehaviour::default()).await;
+    let connections = ConnectionMap::default();
+    let (event_tx, _events) = unbounded_channel();
+
+    for (server, relay) in [("presence", &presence_relay), ("legacy", &legacy_relay)] {
+        connect_to_server(
+            &*connections.handle(server).await,
+            Arc::new(Mutex::new(relay.config("foo", "bar"))),
+            plain_keys(),
+            partners(&[]),
+            relay.trust_anchors(),
+            events_of_server(server, event_tx.clone())
+        )
+        .await
+        .unwrap();
+
+        connections
+            .update_subscriptions(server, vec![String::from("carol")], vec![String::from(
+                "alice"
+            )])
+            .await
+            .unwrap();
+    }
+
+    presence_relay
+        .wait_for(|received| {
+            received.message == MessageType::SUBSCRIBE(vec![String::from("carol")])
+        })
+        .await;
+    presence_relay
+        .wait_for(|received| {
+            received.message == MessageType::UNSUBSCRIBE(vec![String::from("alice")])
+        })
+        .await;
+
+    // the legacy relay keeps being polled instead
+    connections.disconnect_all().await;
+    let received = legacy_relay
+        .wait_for(|received| {
+            matches!(
+                received.message,
+                MessageType::SUBSCRIBE(_) | MessageType::UNSUBSCRIBE(_) | MessageType::DISCONNECT
+            )
+        })
+        .await;
+    assert_eq!(received.message, MessageType::DISCONNECT);
+}
+
+#[tokio::test]
+async fn test_incoming_boop() {
+    let relay = MockRelay::start(RelayBehaviour::default()).await;
+    let mut client = connect(&relay, partners(&["alice"])).await;
+    wait_for_event(&mut client.events, |event| {
+        is_status(event, ServerConnectionStatus::Connected)
+    })
+    .await;
+
+    relay.send_line("BOOP alice");
+
+    wait_for_event(&mut client.events, |event| {
+        *event == ConnectionEvent::Booped(String::from("alice"), BoopContent::default())
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_boop_payload() {
+    let mut relay = MockRelay::start(RelayBehaviour {
+        hello: HelloReply::Answer(vec![String::from("boop-payload")]),
+        ..Default::default()
+    })
+    .await;
+    let mut client = connect(&relay, partners(&["alice"])).await;
+    wait_for_event(&mut client.events, |event| {
+        is_status(event, ServerConnectionStatus::Connected)
+    })
+    .await;
+
+    relay.send_line("BOOP alice emoji 🐶 note \"come over\" kind come-here");
+    let content = BoopContent::new(
+        Some(String::from("🐶")),
+        Some(String::from("come over")),
+        BoopKind::ComeHere
+    );
+    wait_for_event(&mut client.events, |event| {
+        *event == ConnectionEvent::Booped(String::from("alice"), content.clone())
+    })
+    .await;
+
+    let sink = client
+        .conn_handle
+        .lock()
+        .await
+        .as_ref()
+        .unwrap()
+        .sink
+        .clone();
+    sink.send(MessageType::BOOP(
+        String::from("alice"),
+        None,
+        content.clone()
+    ))
+    .unwrap();
+    relay
+        .wait_for(|received| {
+            received.message == MessageType::BOOP(String::from("alice"), None, content.clone())
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn test_boop_payload_on_legacy_relay() {
+    let mut relay = MockRelay::start(RelayBehaviour::default()).await;
+    let client = connect(&relay, partners(&["alice"])).await;
+
+    let sink = client
+        .conn_handle
+        .lock()
+        .await
+        .as_ref()
+        .unwrap()
+        .sink
+        .clone();
+    sink.send(MessageType::BOOP(
+        String::from("alice"),
+        Some(String::from("b1")),
+        BoopContent::new(None, Some(String::from("hi")), BoopKind::Urgent)
+    ))
+    .unwrap();
+
+    // old relays only get the partner key
+    let received = relay
+        .wait_for(|received| matches!(received.message, MessageType::BOOP(..)))
+        .await;
+    assert_eq!(
+        received.message,
+        MessageType::BOOP(String::from("alice"), None, BoopContent::default())
+    );
+}
+
+#[tokio::test]
+async fn test_boop_delivery() {
+    let mut relay = MockRelay::start(RelayBehaviour {
+        hello: HelloReply::Answer(vec![String::from("ack")]),
+        online_partners: ["alice"].iter().map(|key| String::from(*key)).collect(),
+        ..Default::default()
+    })
+    .await;
+    let mut client = connect(&relay, partners(&["alice", "bob"])).await;
+
+    let sink = client
+        .conn_handle
+        .lock()
+        .await
+        .as_ref()
+        .unwrap()
+        .sink
+        .clone();
+    sink.send(MessageType::BOOP(
+        String::from("alice"),
+        Some(String::from("b1")),
+        BoopContent::default()
+    ))
+    .unwrap();
+    relay
+        .wait_for(|received| {
+            received.message
+                == MessageType::BOOP(
+                    String::from("alice"),
+                    Some(String::from("b1")),
+                    BoopContent::default()
+                )
+        })
+        .await;
+    wait_for_event(&mut client.events, |event| {
+        *event == ConnectionEvent::BoopDelivery(String::from("b1"), BoopDeliveryStatus::Sent)
+    })
+    .await;
+    wait_for_event(&mut client.events, |event| {
+        *event == ConnectionEvent::BoopDelivery(String::from("b1"), BoopDeliveryStatus::Delivered)
+    })
+    .await;
+
+    // bob isn't online
+    sink.send(MessageType::BOOP(
+        String::from("bob"),
+        Some(String::from("b2")),
+        BoopContent::default()
+    ))
+    .unwrap();
+    wait_for_event(&mut client.events, |event| {
+        *event
+            == ConnectionEvent::BoopDelivery(String::from("b2"), BoopDeliveryStatus::NotAvailable)
+    })
+    .await;
+
+    relay.send_line("FAILED b3 MALFORMED_ARGUMENTS");
+    wait_for_event(&mut client.events, |event| {
+        *event == ConnectionEvent::BoopDelivery(String::from("b3"), BoopDeliveryStatus::Failed)
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_boop_delivery_on_legacy_relay() {
+    let mut relay = MockRelay::start(RelayBehaviour::default()).await;
+    let mut client = connect(&relay, partners(&["alice"])).await;
+
+    let sink = client
+        .conn_handle
+        .lock()
+        .await
+        .as_ref()
+        .unwrap()
+        .sink
+        .clone();
+    sink.send(MessageType::BOOP(
+        String::from("alice"),
+        Some(String::from("b1")),
+        BoopContent::default()
+    ))
+    .unwrap();
+
+    // the relay doesn't get the id, but the boop is still reported as sent
+    let received = relay
+        .wait_for(|received| matches!(received.message, MessageType::BOOP(..)))
+        .await;
+    assert_eq!(
+        received.message,
+        MessageType::BOOP(String::from("alice"), None, BoopContent::default())
+    );
+    wait_for_event(&mut client.events, |event| {
+        *event == ConnectionEvent::BoopDelivery(String::from("b1"), BoopDeliveryStatus::Sent)
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_boop_by_non_partner_is_a_request() {
+    let relay = MockRelay::start(RelayBehaviour::default()).await;
+    let mut client = connect(&relay, partners(&["alice"])).await;
+    wait_for_event(&mut client.events, |event| {
+        is_status(event, ServerConnectionStatus::Connected)
+    })
+    .await;
+
+    relay.send_line("BOOP mallory");
+    relay.send_line("BOOP alice");
+
+    let event = wait_for_event(&mut client.events, |event| {
+        matches!(
+            event,
+            ConnectionEvent::Booped(..) | ConnectionEvent::BoopRequested(_)
+        )
+    })
+    .await;
+    assert_eq!(
+        event,
+        ConnectionEvent::BoopRequested(String::from("mallory"))
+    );
+    wait_for_event(&mut client.events, |event| {
+        *event == ConnectionEvent::Booped(String::from("alice"), BoopContent::default())
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_malformed_line() {
+    let mut relay = MockRelay::start(RelayBehaviour::default()).await;
+    let mut client = connect(&relay, partners(&[])).await;
+    wait_for_event(&mut client.events, |event| {
+        is_status(event, ServerConnectionStatus::Connected)
+    })
+    .await;
+
+    relay.send_line("GARBAGE foo");
+
+    relay
+        .wait_for(|received| {
+            received.message == MessageType::ERROR(MessageErrorKind::MalformedCommand)
+        })
+        .await;
+    // the client drops the broken connection and opens a new one
+    let received = relay
+        .wait_for(|received| matches!(received.message, MessageType::CONNECT(..)))
+        .await;
+    assert_eq!(received.connection, 2);
+}
+
+#[tokio::test]
+async fn test_protocol_mismatch() {
+    let mut relay = MockRelay::start(RelayBehaviour::default()).await;
+    let mut client = connect(&relay, partners(&[])).await;
+    wait_for_event(&mut client.events, |event| {
+        is_status(event, ServerConnectionStatus::Connected)
+    })
+    .await;
+
+    // HEY is only valid as a login answer
+    relay.send_line("HEY");
+
+    relay
+        .wait_for(|received| {
+            received.message == MessageType::ERROR(MessageErrorKind::ProtocolMismatch)
+        })
+        .await;
+    // the session ends there, the client logs in again
+    let received = relay
+        .wait_for(|received| matches!(received.message, MessageType::CONNECT(..)))
+        .await;
+    assert_eq!(received.connection, 2);
+}
+
+#[tokio::test]
+async fn test_bye_ends_session() {
+    let mut relay = MockRelay::start(RelayBehaviour::default()).await;
+    let mut client = connect(&relay, partners(&["alice"])).await;
+    wait_for_event(&mut client.events, |event| {
+        is_status(event, ServerConnectionStatus::Connected)
+    })
+    .await;
+
+    relay.send_line("BYE");
+    relay.send_line("BOOP alice");
+
+    // nothing is read after BYE, the client logs in again
+    wait_for_event(&mut client.events, |event| {
+        matches!(
+            event,
+            ConnectionEvent::StatusChanged(ServerConnectionStatus::Reconnecting(_))
+        )
+    })
+    .await;
+    relay
+        .wait_for(|received| {
+            received.connection == 2 && matches!(received.message, MessageType::CONNECT(..))
+        })
+        .await;
+    wait_for_event(&mut client.events, |event| {
+        is_status(event, ServerConnectionStatus::Connected)
+    })
+    .await;
+    while let Ok(event) = client.events.try_recv() {
+        assert!(!matches!(event, ConnectionEvent::Booped(..)));
+    }
+}
+
+/// Connects to the relay through a connection map, so boops take the same
+/// path as the ones of the desktop app
+async fn connect_map(relay: &MockRelay, config: BoopConfig) -> (ConnectionMap, ServerEventRx) {
+    let connections = ConnectionMap::default();
+    let (event_tx, events) = unbounded_channel();
+    connect_to_server(
+        &*connections.handle("relay").await,
+        Arc::new(Mutex::new(config)),
+        plain_keys(),
+        partners(&[]),
+        relay.trust_anchors(),
+        events_of_server("relay", event_tx)
+    )
+    .await
+    .unwrap();
+    (connections, events)
+}
+
+/// A boop that takes up a lot of the socket buffers
+fn bulky_boop() -> MessageType {
+    bulky_boop_with_id(None)
+}
+
+fn bulky_boop_with_id(id: Option<String>) -> MessageType {
+    MessageType::BOOP("k".repeat(4096), id, BoopContent::default())
+}
+
+#[tokio::test]
+async fn test_stalled_write_reconnects() {
+    let relay = MockRelay::start(RelayBehaviour::default()).await;
+    let config = relay.config_with(json!({
+        "network": { "pingInterval": 300, "writeTimeout": 1 }
+    }));
+    let (connections, mut events) = connect_map(&relay, config).await;
+    relay.set_behaviour(|behaviour| behaviour.stall_reads = true);
+
+    // keep the queue topped up until the socket buffers are full and a write
+    // can't finish within the timeout
+    let mut failed = None;
+    timeout(Duration::from_secs(60), async {
+        for boop in 0.. {
+            let id = format!("b{}", boop);
+            match connections
+                .send("relay", bulky_boop_with_id(Some(id)))
+                .await
+            {
+                Ok(_) => {}
+                Err(BoopError::SinkFull) => tokio::task::yield_now().await,
+                Err(err) => panic!("boop failed: {}", err)
+            }
+            while let Ok((_, event)) = events.try_recv() {
+                match event {
+                    ConnectionEvent::BoopDelivery(id, BoopDeliveryStatus::Failed) => {
+                        failed = Some(id)
+                    }
+                    ConnectionEvent::StatusChanged(ServerConnectionStatus::Reconnecting(_)) => {
+                        return
+                    }
+                    _ => {}
+                }
+            }
+        }
+    })
+    .await
+    .expect("the stalled write didn't time out");
+    // the boop that was being written is reported as failed
+    assert!(failed.is_some());
+
+    relay.set_behaviour(|behaviour| behaviour.stall_reads = false);
+    wait_for_server_event(&mut events, "relay", |event| {
+        is_status(event, ServerConnectionStatus::Connected)
+    })
+    .await;
+    assert!(connections.is_connected("relay").await);
+}
+
+#[tokio::test]
+async fn test_full_sink_rejects_boops() {
+    let relay = MockRelay::start(RelayBehaviour::default()).await;
+    let config = relay.config_with(json!({
+        "network": { "pingInterval": 300, "writeTimeout": 60 }
+    }));
+    let (connections, _events) = connect_map(&relay, config).await;
+    relay.set_behaviour(|behaviour| behaviour.stall_reads = true);
+
+    // the writer is stuck on the stalled socket long before this many boops
+    for _ in 0..100_000 {
+        match connections.send("relay", bulky_boop()).await {
+            Ok(_) => tokio::task::yield_now().await,
+            Err(err) => {
+                assert_eq!(err, BoopError::SinkFull);
+                return;
+            }
+        }
+    }
+    panic!("the sink never filled up");
+}
+
+#[tokio::test]
+async fn test_simultaneous_relays() {
+    let work = MockRelay::start(RelayBehaviour::default()).await;
+    let mut home = MockRelay::start(RelayBehaviour::default()).await;
+    let connections = ConnectionMap::default();
+    let (event_tx, mut events) = unbounded_channel();
+
+    for (server, relay) in [("work", &work), ("home", &home)] {
+        connect_to_server(
+            &*connections.handle(server).await,
+            Arc::new(Mutex::new(relay.config("foo", "bar"))),
+            plain_keys(),
+            partners(&["alice"]),
+            relay.trust_anchors(),
+            events_of_server(server, event_tx.clone())
+        )
+        .await
+        .unwrap();
+    }
+    assert!(connections.is_connected("work").await);
+    assert!(connections.is_connected("home").await);
+    assert!(!connections.is_connected("club").await);
+
+    // the work relay goes down for good
+    work.set_behaviour(|behaviour| behaviour.reject_login = true);
+    work.kick();
+    wait_for_server_event(&mut events, "work", |event| {
+        is_status(event, ServerConnectionStatus::Disconnected)
+    })
+    .await;
+
+    // the home connection doesn't notice
+    connections
+        .send(
+            "home",
+            MessageType::BOOP(String::from("alice"), None, BoopContent::default())
+        )
+        .await
+        .unwrap();
+    home.wait_for(|received| {
+        received.message == MessageType::BOOP(String::from("alice"), None, BoopContent::default())
+    })
+    .await;
+    home.send_line("BOOP alice");
+    wait_for_server_event(&mut events, "home", |event| {
+        *event == ConnectionEvent::Booped(String::from("alice"), BoopContent::default())
+    })
+    .await;
+
+    assert!(matches!(
+        connections
+            .send(
+                "club",
+                MessageType::BOOP(String::from("alice"), None, BoopContent::default())
+            )
+            .await,
+        Err(BoopError::NotConnected)
+    ));
+
+    connections.disconnect_all().await;
+    wait_for_server_event(&mut events, "home", |event| {
+        is_status(event, ServerConnectionStatus::Disconnected)
+    })
+    .await;
+}
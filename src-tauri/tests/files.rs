@@ -0,0 +1,141 @@
+use std::{
+    env,
+    fs,
+    path::{
+        Path,
+        PathBuf
+    }
+};
+
+use {
+    boop_snoot::{
+        config::BoopConfig,
+        files::{
+            get_config_file_path,
+            get_object_or_backup,
+            save_file,
+            FileRecovery
+        },
+        partners::BoopPartner,
+        schema::Versioned,
+        secrets::{
+            KeySource,
+            KEY_FILENAME
+        }
+    },
+    serde_json::Value
+};
+
+/// Copies a fixture into a fresh temporary directory
+fn install_fixture(fixture: &str, name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("boop-fixtures-{}-{}", fixture, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let file = dir.join(name);
+    fs::copy(fixture_path(fixture), &file).unwrap();
+    file
+}
+
+fn fixture_path(fixture: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(fixture)
+}
+
+fn saved_version(file: &Path) -> Value {
+    serde_json::from_str::<Value>(&fs::read_to_string(file).unwrap()).unwrap()["version"].clone()
+}
+
+#[tokio::test]
+async fn test_unversioned_config_is_upgraded() {
+    let file = install_fixture("config.unversioned.json", "boop.config.json");
+
+    let (config, recovery): (BoopConfig, _) = get_object_or_backup(&file);
+    assert_eq!(recovery, None);
+    assert_eq!(config.server_address(), "boop.example.com:9000");
+    assert_eq!(config.user_name(), "foo");
+    assert_plaintext_password_migrated(&file, &config);
+    assert!(config.ca_files().is_empty());
+    assert_eq!(config.pinned_fingerprint(), None);
+
+    // saved with the current version from now on
+    save_file(&file, &config).await.unwrap();
+    assert_eq!(saved_version(&file), BoopConfig::VERSION);
+    let (reread, recovery): (BoopConfig, _) = get_object_or_backup(&file);
+    assert_eq!(recovery, None);
+    assert_eq!(reread.user_name(), "foo");
+
+    let _ = fs::remove_dir_all(file.parent().unwrap());
+}
+
+#[test]
+fn test_v1_config_is_migrated() {
+    let file = install_fixture("config.v1.json", "boop.config.json");
+
+    let (config, recovery): (BoopConfig, _) = get_object_or_backup(&file);
+    assert_eq!(recovery, None);
+    assert_eq!(config.user_name(), "foo");
+    assert_plaintext_password_migrated(&file, &config);
+
+    // reading the upgraded file again doesn't change it
+    let upgraded = fs::read_to_string(&file).unwrap();
+    let (reread, recovery): (BoopConfig, _) = get_object_or_backup(&file);
+    assert_eq!(recovery, None);
+    assert_eq!(reread.user_name(), "foo");
+    assert_eq!(fs::read_to_string(&file).unwrap(), upgraded);
+
+    let _ = fs::remove_dir_all(file.parent().unwrap());
+}
+
+/// The plain text password is encrypted with the key file, and the file is
+/// written back without it
+fn assert_plaintext_password_migrated(file: &Path, config: &BoopConfig) {
+    let keys = KeySource::new(get_config_file_path(KEY_FILENAME));
+    assert!(!config.has_plaintext_password());
+    assert_eq!(config.password(&keys).unwrap(), "hunter2");
+
+    assert_eq!(saved_version(file), BoopConfig::VERSION);
+    assert!(!fs::read_to_string(file).unwrap().contains("hunter2"));
+}
+
+#[tokio::test]
+async fn test_unversioned_partners_are_upgraded() {
+    let file = install_fixture("partners.unversioned.json", "boop.partners.json");
+    let expected = vec![
+        BoopPartner::new(String::from("Bar"), String::from("bar")),
+        BoopPartner::new(String::from("Baz"), String::from("baz")),
+    ];
+
+    let (partners, recovery): (Vec<BoopPartner>, _) = get_object_or_backup(&file);
+    assert_eq!(recovery, None);
+    assert_eq!(partners, expected);
+
+    save_file(&file, &partners).await.unwrap();
+    assert_eq!(saved_version(&file), Vec::<BoopPartner>::VERSION);
+    let (reread, _): (Vec<BoopPartner>, _) = get_object_or_backup(&file);
+    assert_eq!(reread, expected);
+
+    let _ = fs::remove_dir_all(file.parent().unwrap());
+}
+
+#[test]
+fn test_future_config_is_preserved() {
+    let file = install_fixture("config.future.json", "boop.config.json");
+
+    let (config, recovery): (BoopConfig, _) = get_object_or_backup(&file);
+    let copy = file.with_file_name("boop.config.json.v999");
+    assert_eq!(
+        recovery,
+        Some(FileRecovery::NewerVersion(file.clone(), 999, copy.clone()))
+    );
+    assert_eq!(config.server_address(), "");
+
+    // neither the file nor its copy are changed by reading it
+    let original = fs::read_to_string(fixture_path("config.future.json")).unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), original);
+    assert_eq!(fs::read_to_string(&copy).unwrap(), original);
+
+    let _ = fs::remove_dir_all(file.parent().unwrap());
+}
@@ -0,0 +1,523 @@
+//! In-process mock of the BOOP relay for end-to-end tests of the connection
+//! loop. The relay listens on loopback with a freshly generated self-signed
+//! certificate and speaks just enough of the protocol to be scripted by the
+//! tests.
+
+#![allow(dead_code)]
+
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        Mutex
+    },
+    time::Duration
+};
+
+use {
+    boop_snoot::{
+        auth::{
+            auth_message,
+            client_proof,
+            to_hex,
+            MIN_ITERATIONS
+        },
+        config::BoopConfig,
+        message::{
+            create_message_text,
+            parse_message,
+            MessageErrorKind,
+            MessageType
+        },
+        network::{
+            ConnectionEvent,
+            EventRx
+        },
+        partners::{
+            build_partners_map,
+            BoopPartner,
+            PartnersHandle
+        },
+        secrets::{
+            KeyHandle,
+            KeySource
+        }
+    },
+    serde_json::json,
+    tokio::{
+        io::{
+            split,
+            AsyncBufReadExt,
+            AsyncWriteExt,
+            BufReader
+        },
+        net::{
+            TcpListener,
+            TcpStream
+        },
+        sync::{
+            broadcast,
+            mpsc
+        },
+        time::timeout
+    },
+    tokio_rustls::{
+        rustls::{
+            server::AllowAnyAuthenticatedClient,
+            Certificate,
+            PrivateKey,
+            RootCertStore,
+            ServerConfig
+        },
+        TlsAcceptor
+    }
+};
+
+/// Default time the helpers wait for something to happen
+pub const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Salt (hex) of every challenge the relay sends
+pub const CHALLENGE_SALT: &str = "5a17";
+
+/// How the relay reacts to the client's HELLO
+#[derive(Debug, Clone)]
+pub enum HelloReply {
+    /// answer with protocol version 2 and the given capabilities
+    Answer(Vec<String>),
+    /// behave like an old relay that answers unknown commands with an error
+    Error,
+    /// behave like an old relay that hangs up on unknown commands
+    Close,
+    /// break the protocol by answering with HEY
+    Unexpected
+}
+
+impl Default for HelloReply {
+    fn default() -> Self {
+        HelloReply::Answer(Vec::new())
+    }
+}
+
+/// Scriptable behaviour of the relay, can be changed while the relay runs
+#[derive(Debug, Clone, Default)]
+pub struct RelayBehaviour {
+    pub hello:           HelloReply,
+    /// answer every login with NO
+    pub reject_login:    bool,
+    /// password challenge logins are checked against
+    pub password:        String,
+    /// iteration count asked for in challenges instead of `MIN_ITERATIONS`,
+    /// the proofs of those logins are never answered
+    pub iterations:      Option<u32>,
+    /// never answer a PING
+    pub drop_pongs:      bool,
+    /// stop reading from the connections after their next message, so the
+    /// client's writes pile up
+    pub stall_reads:     bool,
+    /// partners reported as ONLINE when asked with AYT or STATUS or
+    /// subscribed to, everyone else is AFK. Boops with an id are acked as
+    /// delivered to them and as not available to everyone else.
+    pub online_partners: HashSet<String>
+}
+
+/// Things the test can make the relay do on the current connections
+#[derive(Debug, Clone)]
+enum RelayCommand {
+    SendLine(String),
+    Kick
+}
+
+/// A message received by the relay, together with the number of the
+/// connection it arrived on (counting from 1)
+#[derive(Debug, PartialEq)]
+pub struct Received {
+    pub connection: usize,
+    pub message:    MessageType
+}
+
+pub struct MockRelay {
+    address:         SocketAddr,
+    certificate:     Certificate,
+    certificate_pem: String,
+    client_ca:       Option<rcgen::Certificate>,
+    behaviour:       Arc<Mutex<RelayBehaviour>>,
+    commands:        broadcast::Sender<RelayCommand>,
+    received:        mpsc::UnboundedReceiver<Received>
+}
+
+impl MockRelay {
+    pub async fn start(behaviour: RelayBehaviour) -> MockRelay {
+        MockRelay::start_with_client_ca(behaviour, None).await
+    }
+
+    /// Starts a relay that only accepts clients with a certificate issued by
+    /// its own client CA (see `issue_client_certificate`)
+    pub async fn start_requiring_client_certificates(behaviour: RelayBehaviour) -> MockRelay {
+        let mut params = rcgen::CertificateParams::new(Vec::new());
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "mock relay client CA");
+        let client_ca =
+            rcgen::Certificate::from_params(params).expect("failed to generate client CA");
+
+        MockRelay::start_with_client_ca(behaviour, Some(client_ca)).await
+    }
+
+    async fn start_with_client_ca(
+        behaviour: RelayBehaviour,
+        client_ca: Option<rcgen::Certificate>
+    ) -> MockRelay {
+        let generated = rcgen::generate_simple_self_signed(vec![String::from("localhost")])
+            .expect("failed to generate relay certificate");
+        let certificate = Certificate(generated.serialize_der().unwrap());
+        let certificate_pem = generated.serialize_pem().unwrap();
+        let key = PrivateKey(generated.serialize_private_key_der());
+
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &client_ca {
+            Some(client_ca) => {
+                let mut client_roots = RootCertStore::empty();
+                client_roots
+                    .add(&Certificate(client_ca.serialize_der().unwrap()))
+                    .unwrap();
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(client_roots))
+            }
+            None => builder.with_no_client_auth()
+        };
+        let tls_config = builder
+            .with_single_cert(vec![certificate.clone()], key)
+            .expect("invalid relay certificate");
+        let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let behaviour = Arc::new(Mutex::new(behaviour));
+        let (commands, _) = broadcast::channel(16);
+        let (received_tx, received) = mpsc::unbounded_channel();
+
+        let accept_behaviour = Arc::clone(&behaviour);
+        let accept_commands = commands.clone();
+        tokio::spawn(async move {
+            let mut connection = 0;
+            while let Ok((stream, _)) = listener.accept().await {
+                connection += 1;
+                tokio::spawn(serve_connection(
+                    connection,
+                    stream,
+                    acceptor.clone(),
+                    Arc::clone(&accept_behaviour),
+                    accept_commands.subscribe(),
+                    received_tx.clone()
+                ));
+            }
+        });
+
+        MockRelay {
+            address,
+            certificate,
+            certificate_pem,
+            client_ca,
+            behaviour,
+            commands,
+            received
+        }
+    }
+
+    /// Issues a client certificate for `user` with the relay's client CA.
+    /// Returns the PEM encoded certificate and private key.
+    pub fn issue_client_certificate(&self, user: &str) -> (String, String) {
+        let client_ca = self
+            .client_ca
+            .as_ref()
+            .expect("relay doesn't require client certificates");
+
+        // certificates without a subject alternative name aren't accepted
+        let mut params = rcgen::CertificateParams::new(vec![String::from(user)]);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, user);
+        let client = rcgen::Certificate::from_params(params).unwrap();
+
+        (
+            client.serialize_pem_with_signer(client_ca).unwrap(),
+            client.serialize_private_key_pem()
+        )
+    }
+
+    /// Client config pointing at this relay
+    pub fn config(&self, user: &str, password: &str) -> BoopConfig {
+        serde_json::from_value(json!({
+            "serverAddress": format!("localhost:{}", self.address.port()),
+            "user": user,
+            "password": password
+        }))
+        .unwrap()
+    }
+
+    /// Client config pointing at this relay, with additional settings
+    pub fn config_with(&self, settings: serde_json::Value) -> BoopConfig {
+        let mut config = serde_json::to_value(self.config("foo", "bar")).unwrap();
+        for (key, value) in settings.as_object().unwrap() {
+            config[key] = value.clone();
+        }
+        serde_json::from_value(config).unwrap()
+    }
+
+    /// The relay's self-signed certificate, PEM encoded
+    pub fn certificate_pem(&self) -> String {
+        self.certificate_pem.clone()
+    }
+
+    /// Trust anchors that only contain the relay's self-signed certificate
+    pub fn trust_anchors(&self) -> RootCertStore {
+        let mut roots = RootCertStore::empty();
+        roots.add(&self.certificate).unwrap();
+        roots
+    }
+
+    pub fn set_behaviour(&self, change: impl FnOnce(&mut RelayBehaviour)) {
+        change(&mut self.behaviour.lock().unwrap());
+    }
+
+    /// Sends a raw line (without newline) on every open connection
+    pub fn send_line(&self, line: &str) {
+        let _ = self
+            .commands
+            .send(RelayCommand::SendLine(String::from(line)));
+    }
+
+    /// Drops every open connection without saying goodbye
+    pub fn kick(&self) {
+        let _ = self.commands.send(RelayCommand::Kick);
+    }
+
+    /// Waits for the next message the relay received
+    pub async fn next_message(&mut self) -> Received {
+        timeout(WAIT_TIMEOUT, self.received.recv())
+            .await
+            .expect("relay didn't receive a message in time")
+            .expect("relay stopped")
+    }
+
+    /// Returns the next received message if there already is one
+    pub fn try_next_message(&mut self) -> Option<Received> {
+        self.received.try_recv().ok()
+    }
+
+    /// Waits for a received message matching the predicate, skipping all others
+    pub async fn wait_for(&mut self, predicate: impl Fn(&Received) -> bool) -> Received {
+        loop {
+            let received = self.next_message().await;
+            if predicate(&received) {
+                return received;
+            }
+        }
+    }
+}
+
+async fn serve_connection(
+    connection: usize,
+    stream: TcpStream,
+    acceptor: TlsAcceptor,
+    behaviour: Arc<Mutex<RelayBehaviour>>,
+    mut commands: broadcast::Receiver<RelayCommand>,
+    received: mpsc::UnboundedSender<Received>
+) {
+    // a failed TLS handshake shows up as an error on the client side
+    let stream = match acceptor.accept(stream).await {
+        Ok(stream) => stream,
+        Err(_) => return
+    };
+    let (readhalf, mut writehalf) = split(stream);
+    let mut reader = BufReader::new(readhalf);
+    // auth message of the challenge login in progress
+    let mut challenge = None;
+
+    loop {
+        if behaviour.lock().unwrap().stall_reads {
+            // only a kick ends a stalled connection
+            match commands.recv().await {
+                Ok(RelayCommand::SendLine(_)) => continue,
+                Ok(RelayCommand::Kick) | Err(_) => return
+            }
+        }
+
+        let mut buf = String::new();
+        tokio::select! {
+            command = commands.recv() => {
+                match command {
+                    Ok(RelayCommand::SendLine(line)) => {
+                        if writehalf.write_all(format!("{}\n", line).as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(RelayCommand::Kick) | Err(_) => return
+                }
+            },
+            read = reader.read_line(&mut buf) => {
+                match read {
+                    Ok(0) | Err(_) => return,
+                    Ok(_) => {}
+                }
+
+                let message = match parse_message(&buf) {
+                    Ok(message) => message,
+                    Err(_) => continue
+                };
+                let hang_up = matches!(message, MessageType::HELLO(..))
+                    && matches!(behaviour.lock().unwrap().hello, HelloReply::Close);
+                let answers = {
+                    let behaviour = behaviour.lock().unwrap();
+                    let mut answers: Vec<MessageType> =
+                        answer_for(&message, &behaviour, &mut challenge).into_iter().collect();
+                    answers.extend(subscribed_statuses(&message, &behaviour));
+                    answers
+                };
+                let _ = received.send(Received { connection, message });
+                if hang_up {
+                    return;
+                }
+
+                for answer in answers {
+                    let closing = answer == MessageType::BYE;
+                    if writehalf.write_all(create_message_text(answer).as_bytes()).await.is_err() || closing {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn answer_for(
+    message: &MessageType,
+    behaviour: &RelayBehaviour,
+    challenge: &mut Option<String>
+) -> Option<MessageType> {
+    match message {
+        MessageType::HELLO(..) => match &behaviour.hello {
+            HelloReply::Answer(capabilities) => Some(MessageType::HELLO(2, capabilities.clone())),
+            HelloReply::Error => Some(MessageType::ERROR(MessageErrorKind::MalformedCommand)),
+            HelloReply::Close => None,
+            HelloReply::Unexpected => Some(MessageType::HEY)
+        },
+        MessageType::CONNECT(..) if behaviour.reject_login => Some(MessageType::NO),
+        MessageType::CONNECT(..) => Some(MessageType::HEY),
+        MessageType::AUTH(user, client_nonce) => {
+            let nonce = format!("{}5e7e", client_nonce);
+            let iterations = behaviour.iterations.unwrap_or(MIN_ITERATIONS);
+            *challenge = Some(auth_message(
+                user,
+                client_nonce,
+                &nonce,
+                CHALLENGE_SALT,
+                iterations
+            ));
+            Some(MessageType::CHALLENGE(
+                nonce,
+                String::from(CHALLENGE_SALT),
+                iterations
+            ))
+        }
+        MessageType::PROOF(_) if behaviour.iterations.is_some() => None,
+        MessageType::PROOF(proof) => {
+            let expected = challenge.take().map(|message| {
+                to_hex(&client_proof(
+                    &behaviour.password,
+                    &[0x5a, 0x17],
+                    MIN_ITERATIONS,
+                    &message
+                ))
+            });
+            if !behaviour.reject_login && expected.as_ref() == Some(proof) {
+                Some(MessageType::HEY)
+            } else {
+                Some(MessageType::NO)
+            }
+        }
+        MessageType::PING if behaviour.drop_pongs => None,
+        MessageType::PING => Some(MessageType::PONG),
+        MessageType::AYT(key) if behaviour.online_partners.contains(key) => {
+            Some(MessageType::ONLINE(key.clone()))
+        }
+        MessageType::AYT(key) => Some(MessageType::AFK(key.clone())),
+        MessageType::STATUS(keys) => Some(MessageType::PRESENCE(
+            keys.iter()
+                .map(|key| (key.clone(), behaviour.online_partners.contains(key)))
+                .collect()
+        )),
+        MessageType::BOOP(key, Some(id), _) if behaviour.online_partners.contains(key) => {
+            Some(MessageType::DELIVERED(id.clone()))
+        }
+        MessageType::BOOP(_, Some(id), _) => Some(MessageType::FAILED(
+            id.clone(),
+            MessageErrorKind::NotAvailable
+        )),
+        MessageType::DISCONNECT => Some(MessageType::BYE),
+        _ => None
+    }
+}
+
+/// Current status of every partner in a SUBSCRIBE, like a relay with
+/// presence push reports it
+fn subscribed_statuses(message: &MessageType, behaviour: &RelayBehaviour) -> Vec<MessageType> {
+    match message {
+        MessageType::SUBSCRIBE(keys) => keys
+            .iter()
+            .map(|key| {
+                if behaviour.online_partners.contains(key) {
+                    MessageType::ONLINE(key.clone())
+                } else {
+                    MessageType::AFK(key.clone())
+                }
+            })
+            .collect(),
+        _ => Vec::new()
+    }
+}
+
+/// Partners handle with the given user keys (nickname = user key)
+pub fn partners(keys: &[&str]) -> PartnersHandle {
+    let partners: Vec<BoopPartner> = keys
+        .iter()
+        .map(|key| serde_json::from_value(json!({ "nickname": key, "userKey": key })).unwrap())
+        .collect();
+
+    Arc::new(tokio::sync::Mutex::new(build_partners_map(partners)))
+}
+
+/// Keys for configs with a plain text password, nothing is ever encrypted with
+/// them
+pub fn plain_keys() -> KeyHandle {
+    let key_file = std::env::temp_dir().join("boop-unused-key");
+    Arc::new(tokio::sync::Mutex::new(KeySource::new(key_file)))
+}
+
+/// Waits for a connection event matching the predicate, skipping all others
+pub async fn wait_for_event(
+    events: &mut EventRx,
+    predicate: impl Fn(&ConnectionEvent) -> bool
+) -> ConnectionEvent {
+    wait_for_event_within(events, WAIT_TIMEOUT, predicate).await
+}
+
+/// Like `wait_for_event`, for things that take longer than `WAIT_TIMEOUT`
+pub async fn wait_for_event_within(
+    events: &mut EventRx,
+    limit: Duration,
+    predicate: impl Fn(&ConnectionEvent) -> bool
+) -> ConnectionEvent {
+    timeout(limit, async {
+        loop {
+            let event = events.recv().await.expect("event channel closed");
+            if predicate(&event) {
+                return event;
+            }
+        }
+    })
+    .await
+    .expect("expected connection event didn't arrive in time")
+}
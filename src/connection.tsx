@@ -9,15 +9,24 @@ interface ErrorEventPayload {
   message: string
 }
 
-export async function initConnection() {
-  if (alreadyAttemptingConnection)
+// errors returned by the backend commands
+export interface BackendError {
+  kind: string,
+  message: string,
+  fingerprint?: string // new server key, only for "certificateChanged"
+}
+
+// server: id of the profile to connect, the active one if it's left out
+export async function initConnection(server?: string) {
+  const attempt = server ?? "";
+  if (attemptingConnection.has(attempt))
     return;
 
   let connected = false;
   let shouldRetry = true;
-  alreadyAttemptingConnection = true;
+  attemptingConnection.add(attempt);
   tryer({
-    action: async () => { [connected, shouldRetry] = await tryConnection(); },
+    action: async () => { [connected, shouldRetry] = await tryConnection(server); },
     when: () => !connected && shouldRetry,
     until: () => connected || !shouldRetry,
     interval: 1000,
@@ -29,19 +38,23 @@ export async function initConnection() {
       })
     },
     pass: async () => {
-      await initNotifications();
-      alreadyAttemptingConnection = false;
+      if (!notifUnlisten)
+        await initNotifications();
+      attemptingConnection.delete(attempt);
     }
   })
 }
 
 // first: was connection attempt successful?
 // second: should it be retried?
-async function tryConnection(): Promise<[boolean, boolean]> {
+async function tryConnection(server?: string): Promise<[boolean, boolean]> {
     try {
-      const success: boolean = await invoke("connect");
-
-      if (!success) {
+      await invoke("connect", { server: server });
+      return [true, false];
+    }
+    catch (err) {
+      const error = err as BackendError;
+      if (error.kind == "loginRejected") {
         console.log("login was refused by server");
 
         await Swal.fire({
@@ -55,36 +68,84 @@ async function tryConnection(): Promise<[boolean, boolean]> {
         return [false, false];
       }
 
-      return [true, false];
-    }
-    catch (err) {
-      console.log("connection attempt failed");
+      if (error.kind == "certificateChanged") {
+        console.log("server certificate changed");
+
+        const result = await Swal.fire({
+          title: "Server certificate changed (o_O)",
+          text: error.message,
+          icon: "warning",
+          showCancelButton: true,
+          showConfirmButton: true,
+          confirmButtonText: "Trust the new key",
+          cancelButtonText: "Stay disconnected"
+        });
+
+        if (!result.isConfirmed)
+          return [false, false];
+
+        await invoke("accept_server_certificate", { fingerprint: error.fingerprint, server: server });
+        return [false, true];
+      }
+
+      if (error.kind == "credentialsLocked") {
+        const result = await Swal.fire({
+          title: "Enter your master passphrase",
+          text: "Your password is protected by a master passphrase.",
+          input: "password",
+          showCancelButton: true,
+          showConfirmButton: true,
+          confirmButtonText: "Unlock",
+          cancelButtonText: "Stay disconnected"
+        });
+
+        if (!result.isConfirmed)
+          return [false, false];
+
+        try {
+          await invoke("unlock_credentials", { passphrase: result.value, server: server });
+        }
+        catch (unlockErr) {
+          await sendError((unlockErr as BackendError).message);
+        }
+        return [false, true];
+      }
+
+      console.log("connection attempt failed:", error.message);
       return [false, true];
     }
 }
 
 let notifUnlisten: UnlistenFn;
-let alreadyAttemptingConnection = false;
+const attemptingConnection = new Set<string>(); // servers that are being connected
 
 export async function initNotifications() {
   await listen("backend-error", async (event) => {
       await sendError((event.payload as ErrorEventPayload).message);
   });
+
+  // settings or partners that had to be restored while starting up
+  const recoveries: ErrorEventPayload[] = await invoke("take_file_recoveries");
+  for (const recovery of recoveries) {
+      await sendError(recovery.message);
+  }
   
+  // every server is reconnected on its own, the others stay as they are
   notifUnlisten = await listen("connection-state-changed", event => {
-      if ((event.payload as ConnectionStatusPayload).status == -1) {
-          if (alreadyAttemptingConnection)
+      const payload = event.payload as ConnectionStatusPayload;
+      if (payload.status == -1) {
+          if (attemptingConnection.has(payload.server))
             return;
             
-          alreadyAttemptingConnection = true;
+          attemptingConnection.add(payload.server);
 
           let connected = false;
           tryer({
-            action: async () => { connected = await tryConnection(); },
+            action: async () => { [connected] = await tryConnection(payload.server); },
             until: () => connected,
             interval: 2000,
             pass: async () => {
-              alreadyAttemptingConnection = false;
+              attemptingConnection.delete(payload.server);
             }
           })
       }
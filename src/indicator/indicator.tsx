@@ -4,26 +4,85 @@ import { listen, UnlistenFn } from "@tauri-apps/api/event";
 import './indicator.css';
 
 export interface ConnectionStatusPayload {
+    server: string, // id of the profile whose connection changed
     // -1 -> dis- / not connected
     // 0 -> attempting connection
     // 1 -> connected
-    status: number
+    // 2 -> reconnecting after the connection was lost
+    status: number,
+    reconnect?: ReconnectInfo
 }
 
+export interface ReconnectInfo {
+    attempt: number,
+    nextRetry?: number,
+    giveUpReason?: string
+}
+
+// round trip times in ms, null until the first pong
+export interface ConnectionStats {
+    samples: number,
+    minRtt: number | null,
+    avgRtt: number | null,
+    maxRtt: number | null,
+    jitter: number | null,
+    missed: number, // pings still waiting for their pong
+    lost: number // pings of this connection that never got one
+}
+
+interface ConnectionQualityPayload {
+    server: string,
+    stats: ConnectionStats
+}
+
+// worst status first
+const STATUS_ORDER = [-1, 2, 0, 1];
+
 export default function ConnectionIndicator(props) {
-    const [connectionState, setConnectionState] = createSignal(-1);
+    const [serverStates, setServerStates] = createSignal({} as Record<string, number>);
+    const [serverStats, setServerStats] = createSignal({} as Record<string, ConnectionStats>);
+
+    // the indicator shows the worst status of all server connections
+    const connectionState = () => {
+        const states = Object.values(serverStates());
+        if (states.length == 0)
+            return -1;
+
+        return STATUS_ORDER.find(status => states.includes(status)) ?? -1;
+    }
 
     let unlisten: UnlistenFn;
+    let qualityUnlisten: UnlistenFn;
     onMount(async () => {
         unlisten = await listen("connection-state-changed", event => {
-            setConnectionState((event.payload as ConnectionStatusPayload).status);
+            const payload = event.payload as ConnectionStatusPayload;
+            setServerStates(states => ({ ...states, [payload.server]: payload.status }));
+            // stats of a lost connection don't tell anything anymore
+            if (payload.status != 1) {
+                setServerStats(stats => {
+                    const { [payload.server]: _, ...others } = stats;
+                    return others;
+                });
+            }
+        })
+        qualityUnlisten = await listen("connection-quality", event => {
+            const payload = event.payload as ConnectionQualityPayload;
+            setServerStats(stats => ({ ...stats, [payload.server]: payload.stats }));
         })
     })
 
     onCleanup(() => {
         unlisten();
+        qualityUnlisten();
     })
 
+    // shown when hovering the indicator, one line per server
+    const qualityText = () => Object.entries(serverStats())
+        .map(([server, stats]) => stats.avgRtt == null
+            ? `${server}: no pong yet, ${stats.missed} missed`
+            : `${server}: ${stats.avgRtt} ms (${stats.minRtt}-${stats.maxRtt} ms, jitter ${stats.jitter ?? 0} ms), ${stats.missed} missed, ${stats.lost} lost`)
+        .join("\n");
+
     const indicatorText = () => {
         switch (connectionState()) {
             case 1:
@@ -32,15 +91,17 @@ export default function ConnectionIndicator(props) {
                 return "connecting...";
             case -1: 
                 return "disconnected";
+            case 2:
+                return "reconnecting...";
         }
     }
 
     return (
         <>
-            <span id="indicator" style={{ cursor: "default" }} classList={{
+            <span id="indicator" style={{ cursor: "default" }} title={qualityText()} classList={{
                 connected: connectionState() == 1,
                 disconnected: connectionState() == -1,
-                connecting: connectionState() == 0,
+                connecting: connectionState() == 0 || connectionState() == 2,
             }}>{indicatorText()}</span>
         </>
     )
@@ -3,7 +3,8 @@ import { listen, UnlistenFn } from "@tauri-apps/api/event";
 import { sendNotification } from "@tauri-apps/api/notification";
 import { createSignal, onCleanup, onMount, Show, useContext } from "solid-js";
 import { unwrap } from "solid-js/store";
-import { sendError } from "../connection";
+import Swal from "sweetalert2";
+import { BackendError, sendError } from "../connection";
 import { BoopTimerContext } from "./booptimers";
 
 import './partner_row.css';
@@ -11,32 +12,94 @@ import editIcon from '../../icons/edit.svg';
 import saveIcon from '../../icons/save.svg';
 import deleteIcon from '../../icons/delete.svg';
 
+// optional extras of a boop, relays without boop payloads drop them
+interface BoopContent {
+    emoji?: string,
+    note?: string,
+    kind: "plain" | "gentle" | "urgent" | "come-here"
+}
+
 interface BoopPayload {
+    server: string,
     partner_key: string,
+    content: BoopContent,
+    count: number // boops of a flood shown as one
+}
+
+type DeliveryStatus = "queued" | "expired" | "sent" | "delivered" | "notAvailable" | "failed" | "rateLimited" | "busy";
+
+interface BoopReceipt {
+    id: string,
+    status: DeliveryStatus // always queued at first
+}
+
+// boop waiting in the outbox for the connection
+interface QueuedBoop {
+    id: string,
+    partnerKey: string,
+    content: BoopContent,
+    queuedAt: number
+}
+
+interface BoopDeliveryPayload {
+    server: string,
+    id: string,
+    status: DeliveryStatus
 }
 
+// relays without acks never get past "sent"
+const DELIVERY_TEXTS = {
+    "queued": "waiting for the connection",
+    "expired": "expired before the connection came back",
+    "sent": "sent",
+    "delivered": "delivered",
+    "notAvailable": "partner not available",
+    "failed": "failed",
+    "rateLimited": "not sent, slow down a bit",
+    "busy": "not sent, the connection is busy"
+};
+
+const BOOP_TITLES = {
+    "plain": "BOOP!",
+    "gentle": "boop",
+    "urgent": "URGENT BOOP!",
+    "come-here": "COME HERE!"
+};
+
 export default function PartnerRow(props) {
     const [boops, { updateBoops }] = useContext(BoopTimerContext);
     const [lastBoopTimeText, setBoopTimeText] = createSignal("none yet (╥﹏╥)");
     const [editing, setEditing] = createSignal(false);
     const [hovering, setHovering] = createSignal(false);
     const [clicking, setClicking] = createSignal(false);
+    const [delivery, setDelivery] = createSignal<DeliveryStatus | null>(null);
 
     let mainDiv: HTMLDivElement;
     let editButton: HTMLButtonElement;
+    let cancelButton: HTMLButtonElement;
     let fieldNickname: HTMLInputElement;
     let fieldUserKey: HTMLInputElement;
 
     let boopUnlisten: UnlistenFn;
+    let deliveryUnlisten: UnlistenFn;
     let boopTimeInterval;    
+    let lastBoopId: string | null = null;
+    // reports that came in before the boop command returned the boop's id
+    let earlyDeliveries: Record<string, DeliveryStatus> = {};
 
     onMount(async () => {
         boopUnlisten = await listen("booped", async event => {
-            if ((event.payload as BoopPayload).partner_key == props.user_key) {
+            const payload = event.payload as BoopPayload;
+            if (payload.server == props.server && payload.partner_key == props.user_key) {
                 updateBoops(props.user_key);
+
+                const content = payload.content;
+                const emoji = content.emoji ? `${content.emoji} ` : "";
+                const note = content.note ? `\n"${content.note}"` : "";
+                const times = payload.count > 1 ? ` ${payload.count} times` : "";
                 sendNotification({
-                    title: "BOOP!",
-                    body: `You were booped by ${props.nickname}!`
+                    title: BOOP_TITLES[content.kind] ?? BOOP_TITLES["plain"],
+                    body: `${emoji}You were booped by ${props.nickname}${times}!${note}`
                 })
 
                 // play animation
@@ -54,6 +117,37 @@ export default function PartnerRow(props) {
             }            
         });
 
+        // boops that were queued before the app was closed
+        try {
+            const outbox: QueuedBoop[] = await invoke("get_outbox", { server: props.server });
+            const queued = outbox.filter(boop => boop.partnerKey == props.user_key).pop();
+            if (queued) {
+                lastBoopId = queued.id;
+                setDelivery("queued");
+            }
+        }
+        catch (err) {
+            console.error("failed to load the outbox", err);
+        }
+
+        deliveryUnlisten = await listen("boop-delivery", event => {
+            const payload = event.payload as BoopDeliveryPayload;
+            if (payload.server != props.server) {
+                return;
+            }
+            // "sent" can come after the ack of a quick relay
+            const outdated = (current: DeliveryStatus | null) =>
+                payload.status == "sent" && current != null && current != "queued";
+            if (payload.id == lastBoopId) {
+                if (!outdated(delivery())) {
+                    setDelivery(payload.status);
+                }
+            }
+            else if (!outdated(earlyDeliveries[payload.id] ?? null)) {
+                earlyDeliveries[payload.id] = payload.status;
+            }
+        });
+
         mainDiv.addEventListener("mouseover", () => setHovering(true));
         mainDiv.addEventListener("mouseleave", () => setHovering(false));
         mainDiv.addEventListener("click", handleClick);
@@ -61,6 +155,7 @@ export default function PartnerRow(props) {
 
     onCleanup(() => {
         boopUnlisten();
+        deliveryUnlisten();
         clearInterval(boopTimeInterval);
 
         mainDiv.removeEventListener("mouseover", () => setHovering(true));
@@ -69,9 +164,11 @@ export default function PartnerRow(props) {
     })
 
     const handleClick = async (event) => {        
-        if (!editButton.contains(event.target) && !editing() && !clicking()) {
+        const onButton = editButton.contains(event.target) || (cancelButton && cancelButton.contains(event.target));
+        if (!onButton && !editing() && !clicking()) {
             setClicking(true);
-            await boop();
+            // shift + click boops with an emoji, a note or a kind
+            await boop(event.shiftKey ? await askBoopContent() : null);
             setTimeout(() => {setClicking(false)}, 3000);
         }        
     }
@@ -92,21 +189,77 @@ export default function PartnerRow(props) {
         setEditing(false);
 
         //idx: number, previous_user_key: string, user_key: string, nickname: string | null
-        await props.saver(props.idx, props.user_key, new_user_key, nickname);
+        await props.saver(props.idx, props.user_key, new_user_key, nickname, props.server);
     }
 
     const deleteEntry = async () => {
-        await props.remover(props.idx, props.user_key.length > 0 ? props.user_key : null);
+        await props.remover(props.idx, props.user_key.length > 0 ? props.user_key : null, props.server);
     } 
 
-    const boop = async () => {
+    const askBoopContent = async (): Promise<BoopContent | null> => {
+        const result = await Swal.fire({
+            title: `Boop ${props.nickname}`,
+            html: `<input id="boop-emoji" class="swal2-input" placeholder="emoji" maxlength="16">
+                   <input id="boop-note" class="swal2-input" placeholder="note" maxlength="140">
+                   <select id="boop-kind" class="swal2-select">
+                       <option value="plain">plain</option>
+                       <option value="gentle">gentle</option>
+                       <option value="urgent">urgent</option>
+                       <option value="come-here">come here</option>
+                   </select>`,
+            showCancelButton: true,
+            showConfirmButton: true,
+            confirmButtonText: "Boop",
+            preConfirm: () => ({
+                emoji: (document.getElementById("boop-emoji") as HTMLInputElement).value || undefined,
+                note: (document.getElementById("boop-note") as HTMLInputElement).value || undefined,
+                kind: (document.getElementById("boop-kind") as HTMLSelectElement).value
+            })
+        });
+
+        return result.isConfirmed ? result.value : null;
+    }
+
+    const boop = async (content: BoopContent | null) => {
         try {
-            if (props.online >= 1) {
-                await invoke("boop", { partnerKey: props.user_key });
+            // boops to partners that aren't known to be away, they wait in the outbox
+            // while the server isn't connected
+            if (props.online != -1) {
+                setDelivery(null);
+                lastBoopId = null;
+                earlyDeliveries = {};
+                const receipt: BoopReceipt = await invoke("boop", { partnerKey: props.user_key, content: content, server: props.server });
+                lastBoopId = receipt.id;
+                setDelivery(earlyDeliveries[receipt.id] ?? receipt.status);
             }            
         }
         catch (err) {
-            await sendError(err);
+            lastBoopId = null;
+            // too many boops in a row or a busy connection are only shown in the row, no
+            // need for a dialog
+            if ((err as BackendError).kind == "rateLimited") {
+                setDelivery("rateLimited");
+                return;
+            }
+            if ((err as BackendError).kind == "sinkFull") {
+                setDelivery("busy");
+                return;
+            }
+
+            setDelivery("failed");
+            await sendError((err as BackendError).message);
+        }
+    }
+
+    const cancelQueuedBoop = async () => {
+        try {
+            if (await invoke("cancel_queued_boop", { id: lastBoopId, server: props.server })) {
+                lastBoopId = null;
+                setDelivery(null);
+            }
+        }
+        catch (err) {
+            await sendError((err as BackendError).message);
         }
     }
 
@@ -149,6 +302,16 @@ export default function PartnerRow(props) {
                         <button class="edit" onClick={() => setEditing(true)} ref={editButton}><img src={editIcon} alt="edit" /></button>
                     </div>
                     <p class="last-boop">last boop: {lastBoopTimeText()}</p>
+                    <Show when={delivery() != null}>
+                        <p classList={{
+                            delivery: true,
+                            undelivered: ["notAvailable", "failed", "expired", "rateLimited", "busy"].includes(delivery())
+                        }}>your boop: {DELIVERY_TEXTS[delivery()]}
+                            <Show when={delivery() == "queued"}>
+                                <button class="cancel-boop" onClick={async () => await cancelQueuedBoop()} ref={cancelButton}>cancel</button>
+                            </Show>
+                        </p>
+                    </Show>
                 </Show>
 
                 <Show when={editing()}>
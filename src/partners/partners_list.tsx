@@ -5,6 +5,7 @@ import { createSignal, onMount } from "solid-js";
 import { createStore, unwrap } from "solid-js/store";
 import { PartnerLockContext } from "./partners_update_lock";
 import PartnerRow from "./partner_row";
+import { BackendError } from "../connection";
 
 import './partners_list.css';
 import Swal from "sweetalert2";
@@ -12,10 +13,12 @@ import Swal from "sweetalert2";
 export interface Partner {
     nickname: string,
     user_key: string,
-    online: number
+    online: number,
+    server?: string // id of the profile the partner belongs to, new partners go to the active one
 }
 
 interface PartnerEventPayload {
+    server: string,
     user_key: string,
     online: number
 }
@@ -34,7 +37,7 @@ export default function PartnersList(props) {
 
         unlisten = await listen("partner-status-changed", event => {
             const casted = event.payload as PartnerEventPayload;
-            setPartners("partners", p => p.user_key === casted.user_key, "online", casted.online);
+            setPartners("partners", p => p.server === casted.server && p.user_key === casted.user_key, "online", casted.online);
         });
     })
 
@@ -54,17 +57,17 @@ export default function PartnersList(props) {
         }
     }
 
-    const removeItem = async (idx: number, user_key: string | null) => {
+    const removeItem = async (idx: number, user_key: string | null, server?: string) => {
         setPartners("partners", idx, entry => undefined);
 
         if (user_key != null && user_key.length > 0) {
             try {
-                await invoke("del_partner", { partnerKey: user_key });
+                await invoke("del_partner", { partnerKey: user_key, server: server });
             }
             catch (err) {
                 await Swal.fire({
                     title: "Deleting failed",
-                    text: "The partner entry couldn't be deleted.",
+                    text: `The partner entry couldn't be deleted: ${(err as BackendError).message}`,
                     icon: "error",
                     toast: true,
                     timer: 5000,
@@ -76,23 +79,24 @@ export default function PartnersList(props) {
         await fetchAll();
     }
 
-    const saveItem = async (idx: number, previous_user_key: string, user_key: string, nickname: string) => {
+    const saveItem = async (idx: number, previous_user_key: string, user_key: string, nickname: string, server?: string) => {
         try {
             if (user_key != previous_user_key) {
-                await invoke("del_partner", { partnerKey: previous_user_key });
+                await invoke("del_partner", { partnerKey: previous_user_key, server: server });
             }
 
             await invoke("add_or_update_partner", {
                 partner: {
                     nickname: nickname,
                     userKey: user_key
-                }
+                },
+                server: server
             });
         }
         catch (err) {
             await Swal.fire({
                 title: "Saving failed",
-                text: "The partner entry couldn't be saved.",
+                text: `The partner entry couldn't be saved: ${(err as BackendError).message}`,
                 icon: "error",
                 toast: true,
                 timer: 5000,
@@ -1,5 +1,7 @@
-import { createSignal, createResource, onMount, createEffect, Suspense, Accessor, Setter } from "solid-js";
+import { createSignal, createResource, onMount, createEffect, Suspense, Accessor, Setter, For } from "solid-js";
 import { invoke } from '@tauri-apps/api/tauri'
+import Swal from 'sweetalert2';
+import { BackendError, initConnection, sendError } from '../connection';
 
 import './settings.css';
 
@@ -7,32 +9,215 @@ interface SettingsPayload {
     serverAddress: string;
     user: string;
     password: string;
+    loginMode: "password" | "certificate";
+    caFiles: string[];
+    onlyCustomCas: boolean;
+    pinCertificate: boolean;
+    pinnedFingerprint?: string;
+    clientCertificate?: string;
+    clientKey?: string;
+    outboxExpiry: number; // minutes
+    boopBurst: number; // boops in a row per partner, 0 turns the limit off
+    boopRefill: number; // seconds until another boop is allowed
+    network: NetworkSettings;
+}
+
+// advanced, used from the next connection on. All times in seconds
+interface NetworkSettings {
+    pingInterval: number;
+    allowedPingMissed: number; // unanswered pings before the connection is dropped
+    partnerCheckInterval: number; // relays without presence push are asked this often
+    connectTimeout: number;
+    handshakeTimeout: number;
+    writeTimeout: number;
+}
+
+interface Profile {
+    id: string;
+    name: string;
+}
+
+interface ProfilesPayload {
+    active: string;
+    profiles: Profile[];
 }
 
 export default function Settings(props) {
+    const [profiles, setProfiles] = createSignal<ProfilesPayload>({ active: "", profiles: [] });
+
     let fieldServer: HTMLInputElement;
     let fieldUser: HTMLInputElement;
     let fieldPassword: HTMLInputElement;
+    let fieldCertificateLogin: HTMLInputElement;
+    let fieldClientCertificate: HTMLInputElement;
+    let fieldClientKey: HTMLInputElement;
+    let fieldCaFiles: HTMLTextAreaElement;
+    let fieldOnlyCustomCas: HTMLInputElement;
+    let fieldPinCertificate: HTMLInputElement;
+    let fieldOutboxExpiry: HTMLInputElement;
+    let fieldBoopBurst: HTMLInputElement;
+    let fieldBoopRefill: HTMLInputElement;
+    let fieldPingInterval: HTMLInputElement;
+    let fieldAllowedPingMissed: HTMLInputElement;
+    let fieldPartnerCheckInterval: HTMLInputElement;
+    let fieldConnectTimeout: HTMLInputElement;
+    let fieldHandshakeTimeout: HTMLInputElement;
+    let fieldWriteTimeout: HTMLInputElement;
+    let fieldPassphrase: HTMLInputElement;
+
+    // settings without a field here (like the pinned certificate) are saved unchanged
+    let fetchedSettings: SettingsPayload;
 
     const fetchAndAssignSettings = async () => {
         try {
-            const fetchedSettings: SettingsPayload = await invoke('get_settings');
+            fetchedSettings = await invoke('get_settings');
             fieldServer.value = fetchedSettings.serverAddress;
             fieldUser.value = fetchedSettings.user;
             fieldPassword.value = fetchedSettings.password;
+            fieldCertificateLogin.checked = fetchedSettings.loginMode == "certificate";
+            fieldClientCertificate.value = fetchedSettings.clientCertificate ?? "";
+            fieldClientKey.value = fetchedSettings.clientKey ?? "";
+            fieldCaFiles.value = fetchedSettings.caFiles.join("\n");
+            fieldOnlyCustomCas.checked = fetchedSettings.onlyCustomCas;
+            fieldPinCertificate.checked = fetchedSettings.pinCertificate;
+            fieldOutboxExpiry.value = fetchedSettings.outboxExpiry.toString();
+            fieldBoopBurst.value = fetchedSettings.boopBurst.toString();
+            fieldBoopRefill.value = fetchedSettings.boopRefill.toString();
+            fieldPingInterval.value = fetchedSettings.network.pingInterval.toString();
+            fieldAllowedPingMissed.value = fetchedSettings.network.allowedPingMissed.toString();
+            fieldPartnerCheckInterval.value = fetchedSettings.network.partnerCheckInterval.toString();
+            fieldConnectTimeout.value = fetchedSettings.network.connectTimeout.toString();
+            fieldHandshakeTimeout.value = fetchedSettings.network.handshakeTimeout.toString();
+            fieldWriteTimeout.value = fetchedSettings.network.writeTimeout.toString();
+        }
+        catch(err) {
+            console.error(err);
+        }
+    }
+
+    const fetchProfiles = async () => {
+        try {
+            setProfiles(await invoke('get_profiles'));
         }
         catch(err) {
             console.error(err);
         }
     }
 
-    onMount(async () => { await fetchAndAssignSettings(); });
+    onMount(async () => {
+        await fetchProfiles();
+        await fetchAndAssignSettings();
+    });
+
+    const askProfileName = async (title: string, name: string): Promise<string | null> => {
+        const result = await Swal.fire({
+            title: title,
+            input: "text",
+            inputValue: name,
+            showCancelButton: true,
+            showConfirmButton: true
+        });
+
+        return result.isConfirmed ? result.value : null;
+    };
+
+    // runs a profile command and shows what went wrong
+    const changeProfiles = async (command: string, args: object) => {
+        try {
+            await invoke(command, args);
+        }
+        catch(err) {
+            console.error(`${command} failed`, err);
+            await sendError((err as BackendError).message);
+        }
+
+        await fetchProfiles();
+    };
+
+    const switchProfile = async (id: string) => {
+        let connected = false;
+        try {
+            connected = await invoke("switch_profile", { id: id });
+        }
+        catch(err) {
+            console.error("switch_profile failed", err);
+            await sendError((err as BackendError).message);
+        }
+
+        await fetchProfiles();
+        await fetchAndAssignSettings();
+
+        // the other profiles stay connected, the new one connects unless it already is
+        if (!connected)
+            await initConnection();
+    };
+
+    const newProfileClick = async () => {
+        const name = await askProfileName("New profile", "");
+        if (name != null)
+            await changeProfiles("create_profile", { name: name });
+    };
+
+    const renameProfileClick = async () => {
+        const active = profiles().profiles.find(profile => profile.id == profiles().active);
+        const name = await askProfileName("Rename profile", active?.name ?? "");
+        if (name != null)
+            await changeProfiles("rename_profile", { id: profiles().active, name: name });
+    };
+
+    const deleteProfileClick = async () => {
+        const others = profiles().profiles.filter(profile => profile.id != profiles().active);
+        if (others.length == 0) {
+            await sendError("This is the only profile, create another one first.");
+            return;
+        }
+
+        const result = await Swal.fire({
+            title: "Delete profile",
+            text: "The profile is deleted with its settings, partners and boop requests. Which one?",
+            input: "select",
+            inputOptions: Object.fromEntries(others.map(profile => [profile.id, profile.name])),
+            icon: "warning",
+            showCancelButton: true,
+            showConfirmButton: true,
+            confirmButtonText: "Delete"
+        });
+
+        if (result.isConfirmed)
+            await changeProfiles("delete_profile", { id: result.value });
+    };
+
+    const numberOr = (field: HTMLInputElement, fallback: number) => {
+        const value = parseInt(field.value);
+        return value >= 0 ? value : fallback;
+    };
 
     const saveClick = async () => {
         const newSettings: SettingsPayload = {
+            ...fetchedSettings,
             serverAddress: fieldServer.value,
             user: fieldUser.value,
-            password: fieldPassword.value
+            password: fieldPassword.value,
+            loginMode: fieldCertificateLogin.checked ? "certificate" : "password",
+            // empty fields mean no client certificate
+            clientCertificate: fieldClientCertificate.value.trim() || null,
+            clientKey: fieldClientKey.value.trim() || null,
+            caFiles: fieldCaFiles.value.split("\n").map(file => file.trim()).filter(file => file.length > 0),
+            onlyCustomCas: fieldOnlyCustomCas.checked,
+            pinCertificate: fieldPinCertificate.checked,
+            // invalid numbers keep the saved values
+            outboxExpiry: numberOr(fieldOutboxExpiry, fetchedSettings.outboxExpiry),
+            boopBurst: numberOr(fieldBoopBurst, fetchedSettings.boopBurst),
+            boopRefill: numberOr(fieldBoopRefill, fetchedSettings.boopRefill),
+            // out of range values are refused by the backend
+            network: {
+                pingInterval: numberOr(fieldPingInterval, fetchedSettings.network.pingInterval),
+                allowedPingMissed: numberOr(fieldAllowedPingMissed, fetchedSettings.network.allowedPingMissed),
+                partnerCheckInterval: numberOr(fieldPartnerCheckInterval, fetchedSettings.network.partnerCheckInterval),
+                connectTimeout: numberOr(fieldConnectTimeout, fetchedSettings.network.connectTimeout),
+                handshakeTimeout: numberOr(fieldHandshakeTimeout, fetchedSettings.network.handshakeTimeout),
+                writeTimeout: numberOr(fieldWriteTimeout, fetchedSettings.network.writeTimeout)
+            }
         };
 
         try {
@@ -41,15 +226,44 @@ export default function Settings(props) {
         }
         catch(err) {
             console.error("failed to save settings", err);
+            await sendError((err as BackendError).message);
         }
 
         props.savedSettings();
     };
 
+    const passphraseClick = async () => {
+        // an empty passphrase goes back to the key file
+        const passphrase = fieldPassphrase.value || null;
+
+        try {
+            await invoke("set_master_passphrase", { passphrase: passphrase });
+            fieldPassphrase.value = "";
+            console.log("successfully changed master passphrase");
+        }
+        catch(err) {
+            console.error("failed to change master passphrase", err);
+            await sendError((err as BackendError).message);
+        }
+    };
+
     return (
         <>
             < div class="settings" >
                 <h2>Settings</h2>
+                <div class="profiles">
+                    <label for="settings-profile">Profile
+                        <select id="settings-profile" class="textbox" value={profiles().active}
+                            onChange={async (e) => await switchProfile(e.currentTarget.value)}>
+                            <For each={profiles().profiles}>
+                                {(profile) => <option value={profile.id}>{profile.name}</option>}
+                            </For>
+                        </select>
+                    </label>
+                    <button onClick={async () => await newProfileClick()} >New</button>
+                    <button onClick={async () => await renameProfileClick()} >Rename</button>
+                    <button onClick={async () => await deleteProfileClick()} >Delete</button>
+                </div>
                 <div class="settings-inputs">
                     <label for="settings-server-address" >Server
                         <input type="text" id="settings-server-address" class="textbox" ref={fieldServer} />
@@ -62,6 +276,71 @@ export default function Settings(props) {
                             <input type="password" id="settings-password" class="textbox" ref={fieldPassword} />
                         </label>
                     </div>
+                    <div class="login">
+                        <label for="settings-client-certificate">Client certificate file
+                            <input type="text" id="settings-client-certificate" class="textbox" ref={fieldClientCertificate} />
+                        </label>
+                        <label for="settings-client-key">Client key file
+                            <input type="text" id="settings-client-key" class="textbox" ref={fieldClientKey} />
+                        </label>
+                    </div>
+                    <label for="settings-certificate-login" class="checkbox">
+                        <input type="checkbox" id="settings-certificate-login" ref={fieldCertificateLogin} />
+                        Log in with the client certificate instead of the password
+                    </label>
+                    <div class="login">
+                        <label for="settings-passphrase">Master passphrase (leave empty to remove it)
+                            <input type="password" id="settings-passphrase" class="textbox" ref={fieldPassphrase} />
+                        </label>
+                        <button id="set-passphrase" onClick={async () => await passphraseClick()} >Set</button>
+                    </div>
+                    <label for="settings-ca-files">Extra CA files (one path per line)
+                        <textarea id="settings-ca-files" class="textbox" rows="2" ref={fieldCaFiles} />
+                    </label>
+                    <label for="settings-only-custom-cas" class="checkbox">
+                        <input type="checkbox" id="settings-only-custom-cas" ref={fieldOnlyCustomCas} />
+                        Only trust these CAs
+                    </label>
+                    <label for="settings-pin-certificate" class="checkbox">
+                        <input type="checkbox" id="settings-pin-certificate" ref={fieldPinCertificate} />
+                        Pin the server's certificate (for self-signed servers)
+                    </label>
+                    <label for="settings-outbox-expiry">Drop boops made while disconnected after (minutes)
+                        <input type="number" min="0" id="settings-outbox-expiry" class="textbox" ref={fieldOutboxExpiry} />
+                    </label>
+                    <div class="login">
+                        <label for="settings-boop-burst">Boops in a row per partner (0 for no limit)
+                            <input type="number" min="0" id="settings-boop-burst" class="textbox" ref={fieldBoopBurst} />
+                        </label>
+                        <label for="settings-boop-refill">Then one boop every (seconds)
+                            <input type="number" min="0" id="settings-boop-refill" class="textbox" ref={fieldBoopRefill} />
+                        </label>
+                    </div>
+                    <details class="advanced">
+                        <summary>Advanced network (used from the next connection on)</summary>
+                        <div class="login">
+                            <label for="settings-ping-interval">Ping every (seconds, 1-300)
+                                <input type="number" min="1" max="300" id="settings-ping-interval" class="textbox" ref={fieldPingInterval} />
+                            </label>
+                            <label for="settings-allowed-ping-missed">Reconnect after missed pings (1-20)
+                                <input type="number" min="1" max="20" id="settings-allowed-ping-missed" class="textbox" ref={fieldAllowedPingMissed} />
+                            </label>
+                        </div>
+                        <label for="settings-partner-check-interval">Ask for partners every (seconds, 5-3600)
+                            <input type="number" min="5" max="3600" id="settings-partner-check-interval" class="textbox" ref={fieldPartnerCheckInterval} />
+                        </label>
+                        <div class="login">
+                            <label for="settings-connect-timeout">Connect timeout (seconds, 1-120)
+                                <input type="number" min="1" max="120" id="settings-connect-timeout" class="textbox" ref={fieldConnectTimeout} />
+                            </label>
+                            <label for="settings-handshake-timeout">Handshake and login timeout (1-120)
+                                <input type="number" min="1" max="120" id="settings-handshake-timeout" class="textbox" ref={fieldHandshakeTimeout} />
+                            </label>
+                            <label for="settings-write-timeout">Write timeout (1-120)
+                                <input type="number" min="1" max="120" id="settings-write-timeout" class="textbox" ref={fieldWriteTimeout} />
+                            </label>
+                        </div>
+                    </details>
                 </div>
                 <div class="save-container">
                     <button id="save-settings" onClick={async () => await saveClick()} >Save Settings</button>